@@ -1,25 +1,56 @@
 use crate::ledger::*;
 use crate::ConstraintF;
+use ark_crypto_primitives::crh::constraints::CRHGadget;
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
-use ark_r1cs_std::bits::{uint8::UInt8, ToBytesGadget};
+use ark_r1cs_std::bits::{uint32::UInt32, uint64::UInt64, uint8::UInt8, ToBytesGadget};
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_simple_payments::account::*;
 use ark_simple_payments::signature::schnorr::constraints::*;
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKeyVar = PublicKeyVar<EdwardsProjective, EdwardsVar>;
 
-/// Account identifier. This prototype supports only 256 accounts at a time.
+/// Convert a public key to bytes, like `ark_simple_payments::account::public_key_to_bytes_le`.
+#[tracing::instrument(target = "r1cs", skip(public_key))]
+pub fn public_key_to_bytes_le(
+    public_key: &AccountPublicKeyVar,
+) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    // The bytes of a point start with its x-coordinate.
+    let mut x_bytes = public_key.to_bytes()?;
+    x_bytes.truncate(x_bytes.len() / 2);
+    Ok(x_bytes)
+}
+
+/// Account identifier.
 #[derive(Clone, Debug)]
-pub struct AccountIdVar(pub UInt8<ConstraintF>);
+pub struct AccountIdVar(pub UInt32<ConstraintF>);
 
 impl AccountIdVar {
     /// Convert the account identifier to bytes.
     #[tracing::instrument(target = "r1cs", skip(self))]
     pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
-        vec![self.0.clone()]
+        self.0.to_bytes().unwrap()
+    }
+
+    /// Whether `path` leads to the leaf of this account, i.e., whether the position of
+    /// its leaf equals this identifier.
+    #[tracing::instrument(target = "r1cs", skip(self, path))]
+    pub fn is_position_of<C: MerkleConfigGadget>(
+        &self,
+        path: &MerklePathVar<C>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // Identifiers and positions may have different numbers of bits, so the missing
+        // high bits of either one must be zero.
+        let mut id_bits = self.0.to_bits_le();
+        let mut position = path.position().to_vec();
+        let num_bits = id_bits.len().max(position.len());
+        id_bits.resize(num_bits, Boolean::FALSE);
+        position.resize(num_bits, Boolean::FALSE);
+        id_bits[..].is_eq(&position[..])
     }
 }
 
@@ -30,7 +61,210 @@ impl AllocVar<AccountId, ConstraintF> for AccountIdVar {
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
-        UInt8::new_variable(cs, || f().map(|u| u.borrow().0), mode).map(Self)
+        let cs = cs.into().cs();
+        let id = f().map(|id| id.borrow().0).ok();
+        if mode != AllocationMode::Input {
+            return UInt32::new_variable(cs, || id.ok_or(SynthesisError::AssignmentMissing), mode)
+                .map(Self);
+        }
+        // A public identifier takes a single public input, which is the identifier as a
+        // field element, rather than one per bit.
+        let bits = UInt32::new_witness(cs.clone(), || id.ok_or(SynthesisError::AssignmentMissing))?;
+        let input = FpVar::new_input(cs, || {
+            id.map(ConstraintF::from)
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        Boolean::le_bits_to_fp_var(&bits.to_bits_le())?.enforce_equal(&input)?;
+        Ok(Self(bits))
+    }
+}
+
+/// The number of transactions an account has sent so far.
+#[derive(Clone, Debug)]
+pub struct NonceVar(pub UInt64<ConstraintF>);
+
+impl NonceVar {
+    /// Convert the nonce to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        self.0.to_bytes().unwrap()
+    }
+
+    /// Compute the next nonce, enforcing that the increment does not overflow.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn checked_increment(&self) -> Result<Self, SynthesisError> {
+        // An increment overflows only if every bit of the nonce is set.
+        let is_max = self.0.is_eq(&UInt64::constant(u64::MAX))?;
+        is_max.enforce_equal(&Boolean::FALSE)?;
+        let result = UInt64::addmany(&[self.0.clone(), UInt64::constant(1)])?;
+        Ok(NonceVar(result))
+    }
+}
+
+impl AllocVar<Nonce, ConstraintF> for NonceVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Nonce>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        UInt64::new_variable(cs, || f().map(|n| n.borrow().0), mode).map(Self)
+    }
+}
+
+/// The position of a public key in the registry of public keys.
+/// See `ark_simple_payments::account::RegistryKey`.
+#[derive(Clone, Debug)]
+pub struct RegistryKeyVar(pub Vec<UInt8<ConstraintF>>);
+
+impl RegistryKeyVar {
+    /// The key of `public_key`, which consists of the low bytes of its x-coordinate.
+    #[tracing::instrument(target = "r1cs", skip(public_key))]
+    pub fn of(public_key: &AccountPublicKeyVar) -> Result<Self, SynthesisError> {
+        let mut bytes = public_key.to_bytes()?;
+        bytes.truncate(RegistryKey::NUM_BYTES);
+        Ok(Self(bytes))
+    }
+
+    /// Convert the key to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        let mut bytes = self.0.clone();
+        bytes.push(UInt8::constant(0));
+        bytes
+    }
+
+    /// Whether this key is smaller than `other`.
+    #[tracing::instrument(target = "r1cs", skip(self, other))]
+    pub fn is_smaller_than(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // Keys have fewer bits than half of the field, so they can be compared as field
+        // elements.
+        let this = Boolean::le_bits_to_fp_var(&self.0.to_bits_le()?)?;
+        let other = Boolean::le_bits_to_fp_var(&other.0.to_bits_le()?)?;
+        this.is_cmp_unchecked(&other, Ordering::Less, false)
+    }
+}
+
+impl AllocVar<RegistryKey, ConstraintF> for RegistryKeyVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<RegistryKey>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|key| {
+            let cs = cs.into();
+            let bytes = key.borrow().to_bytes_le();
+            bytes[..RegistryKey::NUM_BYTES]
+                .iter()
+                .map(|byte| UInt8::new_variable(cs.clone(), || Ok(byte), mode))
+                .collect::<Result<_, _>>()
+                .map(Self)
+        })
+    }
+}
+
+/// The digest of an account's multisig policy.
+/// See `ark_simple_payments::account::PolicyDigest`.
+#[derive(Clone, Debug)]
+pub struct PolicyDigestVar(pub Vec<UInt8<ConstraintF>>);
+
+impl PolicyDigestVar {
+    /// The digest of the accounts that have no multisig policy.
+    pub fn zero() -> Self {
+        Self(vec![
+            UInt8::constant(0);
+            PolicyDigest::default().to_bytes_le().len()
+        ])
+    }
+
+    /// Convert the digest to bytes.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Vec<UInt8<ConstraintF>> {
+        self.0.clone()
+    }
+
+    /// The digest as a field element. This is only meaningful for the bytes of a leaf
+    /// of the account Merkle tree, which always encode a field element canonically.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_field_element(&self) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        Boolean::le_bits_to_fp_var(&self.0.to_bits_le()?)
+    }
+}
+
+impl AllocVar<PolicyDigest, ConstraintF> for PolicyDigestVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<PolicyDigest>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|digest| {
+            let cs = cs.into();
+            digest
+                .borrow()
+                .to_bytes_le()
+                .iter()
+                .map(|byte| UInt8::new_variable(cs.clone(), || Ok(byte), mode))
+                .collect::<Result<_, _>>()
+                .map(Self)
+        })
+    }
+}
+
+/// The authorization policy of a multisig account, whose public keys are padded with
+/// the identity up to `MAX_SIGNERS` keys.
+#[derive(Clone)]
+pub struct MultisigPolicyVar {
+    /// The number of approvals that a transaction needs.
+    pub threshold: UInt8<ConstraintF>,
+    /// The public keys of the signers.
+    pub public_keys: Vec<AccountPublicKeyVar>,
+}
+
+impl MultisigPolicyVar {
+    /// Convert the policy to bytes, like `MultisigPolicy::to_bytes_le`.
+    #[tracing::instrument(target = "r1cs", skip(self))]
+    pub fn to_bytes_le(&self) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let mut bytes = vec![self.threshold.clone()];
+        for public_key in &self.public_keys {
+            bytes.extend(public_key_to_bytes_le(public_key)?);
+        }
+        Ok(bytes)
+    }
+
+    /// The digest of the policy, like `MultisigPolicy::digest`.
+    #[tracing::instrument(target = "r1cs", skip(self, leaf_crh_params))]
+    pub fn digest<C: MerkleConfigGadget>(
+        &self,
+        leaf_crh_params: &LeafHashParamsVar<C>,
+    ) -> Result<FpVar<ConstraintF>, SynthesisError> {
+        C::LeafHashGadget::evaluate(leaf_crh_params, &self.to_bytes_le()?)
+    }
+}
+
+impl AllocVar<MultisigPolicy, ConstraintF> for MultisigPolicyVar {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<MultisigPolicy>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|policy| {
+            let policy = policy.borrow();
+            let cs = cs.into();
+            let threshold = UInt8::new_variable(cs.clone(), || Ok(policy.threshold), mode)?;
+            let public_keys = (0..MAX_SIGNERS)
+                .map(|i| {
+                    let public_key = policy.public_keys.get(i).copied().unwrap_or_default();
+                    AccountPublicKeyVar::new_variable(cs.clone(), || Ok(public_key), mode)
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Self {
+                threshold,
+                public_keys,
+            })
+        })
     }
 }
 
@@ -41,6 +275,12 @@ pub struct AccountInformationVar {
     pub public_key: AccountPublicKeyVar,
     /// The balance associated with this this account.
     pub balance: AmountVar,
+    /// The nonce that the next transaction sent from this account must carry.
+    pub nonce: NonceVar,
+    /// The smallest registry key that is larger than the key of `public_key`.
+    pub next_key: RegistryKeyVar,
+    /// The digest of the account's multisig policy, or zero if it has none.
+    pub policy: PolicyDigestVar,
 }
 
 impl AccountInformationVar {
@@ -52,6 +292,9 @@ impl AccountInformationVar {
             .unwrap()
             .into_iter()
             .chain(self.balance.to_bytes_le())
+            .chain(self.nonce.to_bytes_le())
+            .chain(self.next_key.to_bytes_le())
+            .chain(self.policy.to_bytes_le())
             .collect()
     }
 }
@@ -68,10 +311,16 @@ impl AllocVar<AccountInformation, ConstraintF> for AccountInformationVar {
             let cs = cs.into();
             let public_key =
                 AccountPublicKeyVar::new_variable(cs.clone(), || Ok(&info.public_key), mode)?;
-            let balance = AmountVar::new_variable(cs, || Ok(&info.balance), mode)?;
+            let balance = AmountVar::new_variable(cs.clone(), || Ok(&info.balance), mode)?;
+            let nonce = NonceVar::new_variable(cs.clone(), || Ok(&info.nonce), mode)?;
+            let next_key = RegistryKeyVar::new_variable(cs.clone(), || Ok(&info.next_key), mode)?;
+            let policy = PolicyDigestVar::new_variable(cs, || Ok(&info.policy), mode)?;
             Ok(Self {
                 public_key,
                 balance,
+                nonce,
+                next_key,
+                policy,
             })
         })
     }
@@ -0,0 +1,165 @@
+//! A sequencer that rolls up the transactions of a ledger.
+//!
+//! ```text
+//! rollup-node <ledger-dir> <output-dir> --file <transactions>
+//! rollup-node <ledger-dir> <output-dir> --socket <path>
+//! ```
+//!
+//! The ledger is the `LedgerStore` in `<ledger-dir>`, which must have a fee collector
+//! (see `LedgerStore::set_fee_collector`). Transactions are read as a
+//! sequence of serialized `Transaction`s, either from a file, or from each connection
+//! to a Unix socket. Valid transactions are batched and proven, and the proven
+//! batches are appended to `<output-dir>/batches`, where a verifier can read them
+//! with `sequencer::read_batches`. Proven batches are also applied to the ledger.
+//!
+//! The keys are read from `<output-dir>/proving_key` and `<output-dir>/verifying_key`,
+//! and are generated if they do not exist yet.
+
+use ark_rollup::prover::{Groth16, RollupSNARK};
+use ark_rollup::sequencer::{BatchRecord, Sequencer};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, SerializationError};
+use ark_simple_payments::ledger::Parameters;
+use ark_simple_payments::store::LedgerStore;
+use ark_simple_payments::transaction::Transaction;
+use ark_snark::SNARK;
+use ark_std::rand::{rngs::StdRng, SeedableRng};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// The number of transactions per batch.
+const NUM_TX: usize = 4;
+
+type ProvingKey = <Groth16 as SNARK<ark_rollup::ConstraintF>>::ProvingKey;
+type VerifyingKey = <Groth16 as SNARK<ark_rollup::ConstraintF>>::VerifyingKey;
+type Proof = <Groth16 as SNARK<ark_rollup::ConstraintF>>::Proof;
+
+fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    if args.len() != 5 || (args[3] != "--file" && args[3] != "--socket") {
+        eprintln!(
+            "usage: {} <ledger-dir> <output-dir> (--file <transactions> | --socket <path>)",
+            args[0]
+        );
+        std::process::exit(2);
+    }
+    if let Err(e) = run(&args[1], &args[2], &args[3], &args[4]) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(ledger_dir: &str, output_dir: &str, mode: &str, input: &str) -> Result<(), Box<dyn Error>> {
+    let mut rng = os_rng()?;
+    let mut store = LedgerStore::open(ledger_dir)?;
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir)?;
+    let proving_key = load_or_generate_keys(output_dir, &store, &mut rng)?;
+    let mut sequencer = Sequencer::<Groth16, NUM_TX>::new(
+        store.parameters().clone(),
+        store.state().clone(),
+        proving_key,
+        output_dir.join("batches"),
+    )?;
+
+    if mode == "--file" {
+        submit_all(&mut sequencer, BufReader::new(File::open(input)?));
+        while let Some(record) = sequencer.cut_batch(&mut rng)? {
+            commit(&mut store, &record)?;
+        }
+        // There is no more input, so prove what is left.
+        for record in sequencer.flush(&mut rng)? {
+            commit(&mut store, &record)?;
+        }
+    } else {
+        let _ = fs::remove_file(input);
+        let listener = UnixListener::bind(input)?;
+        eprintln!("listening on {}", input);
+        for stream in listener.incoming() {
+            submit_all(&mut sequencer, BufReader::new(stream?));
+            while let Some(record) = sequencer.cut_batch(&mut rng)? {
+                commit(&mut store, &record)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Submit every transaction that can be read from `reader`, until it is exhausted.
+fn submit_all<R: Read>(sequencer: &mut Sequencer<Groth16, NUM_TX>, mut reader: R) {
+    loop {
+        match Transaction::deserialize(&mut reader) {
+            Ok(tx) => {
+                if let Err(e) = sequencer.submit(tx) {
+                    eprintln!("rejected transaction: {}", e);
+                }
+            }
+            Err(SerializationError::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                return;
+            }
+            Err(e) => {
+                eprintln!("malformed input: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Apply the transactions of a proven batch to the ledger store.
+fn commit(store: &mut LedgerStore, record: &BatchRecord<Proof>) -> Result<(), Box<dyn Error>> {
+    for tx in &record.transactions {
+        store.apply_transaction(tx)?;
+    }
+    assert_eq!(store.state().root(), record.final_root);
+    store.checkpoint()?;
+    eprintln!(
+        "proved a batch of {} transaction(s); {} -> {}",
+        record.transactions.len(),
+        root_hex(&record.initial_root),
+        root_hex(&record.final_root),
+    );
+    Ok(())
+}
+
+/// Read the keys from `output_dir`, or generate them (and write them there) if they
+/// do not exist yet.
+fn load_or_generate_keys(
+    output_dir: &Path,
+    store: &LedgerStore,
+    rng: &mut StdRng,
+) -> Result<ProvingKey, Box<dyn Error>> {
+    let pk_path = output_dir.join("proving_key");
+    let vk_path = output_dir.join("verifying_key");
+    if pk_path.exists() && vk_path.exists() {
+        return Ok(ProvingKey::deserialize(&fs::read(pk_path)?[..])?);
+    }
+    eprintln!("generating keys for batches of {} transactions", NUM_TX);
+    let parameters: &Parameters = store.parameters();
+    let tree_height = store.state().account_merkle_tree.height();
+    let (pk, vk): (ProvingKey, VerifyingKey) =
+        Groth16::setup_rollup::<_, _, _, NUM_TX>(parameters, tree_height, rng)?;
+    let mut bytes = Vec::new();
+    vk.serialize(&mut bytes)?;
+    fs::write(vk_path, bytes)?;
+    let mut bytes = Vec::new();
+    pk.serialize(&mut bytes)?;
+    fs::write(pk_path, bytes)?;
+    Ok(pk)
+}
+
+/// A cryptographically secure RNG, seeded by the operating system.
+fn os_rng() -> Result<StdRng, Box<dyn Error>> {
+    let mut seed = [0u8; 32];
+    File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    Ok(StdRng::from_seed(seed))
+}
+
+fn root_hex(root: &impl CanonicalSerialize) -> String {
+    let mut bytes = Vec::new();
+    root.serialize(&mut bytes).unwrap();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
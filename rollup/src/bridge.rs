@@ -0,0 +1,69 @@
+use crate::account::AccountIdVar;
+use crate::ledger::{AccRootVar, AmountVar, MerkleConfigGadget, ParametersVar, SigSchemeGadget};
+use crate::transaction::TransactionVar;
+use crate::ConstraintF;
+use ark_crypto_primitives::crh::{CRHGadget, TwoToOneCRHGadget};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+
+/// A commitment to a sequence of deposits or exits.
+/// See `ark_simple_payments::bridge::BridgeDigest`.
+pub type BridgeDigestVar = AccRootVar;
+
+/// Extend `digest` with an operation that moves `amount` into or out of `account`.
+/// See `ark_simple_payments::bridge::extend_digest`.
+#[tracing::instrument(target = "r1cs", skip(parameters, digest, account, amount))]
+pub fn extend_digest<C: MerkleConfigGadget>(
+    parameters: &ParametersVar<C>,
+    digest: &BridgeDigestVar,
+    account: &AccountIdVar,
+    amount: &AmountVar,
+) -> Result<BridgeDigestVar, SynthesisError> {
+    let mut entry = account.to_bytes_le();
+    entry.extend(amount.to_bytes_le());
+    let entry_hash = C::LeafHashGadget::evaluate(&parameters.leaf_crh_params, &entry)?;
+    C::TwoToOneHashGadget::evaluate(
+        &parameters.two_to_one_crh_params,
+        &digest.to_bytes()?,
+        &entry_hash.to_bytes()?,
+    )
+}
+
+/// Extend `deposits_digest` if `tx` is a deposit, and `exits_digest` if `tx` is a
+/// withdrawal, unless `enabled` is false. Returns the new digests.
+///
+/// The digests commit to `tx.recipient` for deposits and to `tx.sender` for withdrawals,
+/// which `TransactionVar::validate` checks to be the accounts whose leaves are updated.
+#[tracing::instrument(
+    target = "r1cs",
+    skip(parameters, tx, enabled, deposits_digest, exits_digest)
+)]
+pub fn absorb_transaction<C: MerkleConfigGadget, S: SigSchemeGadget>(
+    parameters: &ParametersVar<C>,
+    tx: &TransactionVar<S>,
+    enabled: &Boolean<ConstraintF>,
+    deposits_digest: &BridgeDigestVar,
+    exits_digest: &BridgeDigestVar,
+) -> Result<(BridgeDigestVar, BridgeDigestVar), SynthesisError> {
+    // Deposits move money into the recipient's account, and withdrawals move money
+    // out of the sender's account, so both can share one hash evaluation.
+    let account = AccountIdVar(UInt32::conditionally_select(
+        &tx.is_deposit,
+        &tx.recipient.0,
+        &tx.sender.0,
+    )?);
+    let digest =
+        BridgeDigestVar::conditionally_select(&tx.is_deposit, deposits_digest, exits_digest)?;
+    let extended = extend_digest(parameters, &digest, &account, &tx.amount)?;
+    let deposits_digest = BridgeDigestVar::conditionally_select(
+        &tx.is_deposit.and(enabled)?,
+        &extended,
+        deposits_digest,
+    )?;
+    let exits_digest = BridgeDigestVar::conditionally_select(
+        &tx.is_withdrawal.and(enabled)?,
+        &extended,
+        exits_digest,
+    )?;
+    Ok((deposits_digest, exits_digest))
+}
@@ -1,3 +1,4 @@
+use crate::account::AccountPublicKeyVar;
 use crate::ConstraintF;
 use ark_crypto_primitives::crh::injective_map::constraints::{
     PedersenCRHCompressorGadget, TECompressorGadget,
@@ -6,13 +7,20 @@ use ark_crypto_primitives::crh::{
     constraints::{CRHGadget, TwoToOneCRHGadget},
     injective_map::TECompressor,
 };
-use ark_crypto_primitives::merkle_tree::constraints::PathVar;
+use ark_crypto_primitives::merkle_tree::{self, Path};
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
 use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_simple_payments::ledger::*;
-use ark_simple_payments::signature::schnorr::constraints::ParametersVar as SchnorrParamsVar;
+use ark_simple_payments::poseidon;
+use ark_simple_payments::random_oracle::blake2s;
+use ark_simple_payments::signature::eddsa::constraints::EdDSASignatureVerifyGadget;
+use ark_simple_payments::signature::schnorr::constraints::{
+    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget,
+};
+use ark_simple_payments::signature::SigVerifyGadget;
 use std::borrow::Borrow;
 
 /// Represents transaction amounts and account balances.
@@ -79,51 +87,235 @@ impl AllocVar<Amount, ConstraintF> for AmountVar {
     }
 }
 
-pub type TwoToOneHashGadget = PedersenCRHCompressorGadget<
-    EdwardsProjective,
-    TECompressor,
-    TwoToOneWindow,
-    EdwardsVar,
-    TECompressorGadget,
->;
-
-pub type LeafHashGadget = PedersenCRHCompressorGadget<
-    EdwardsProjective,
-    TECompressor,
-    LeafWindow,
-    EdwardsVar,
-    TECompressorGadget,
->;
-
-pub type AccRootVar =
-    <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::OutputVar;
-pub type AccPathVar = PathVar<MerkleConfig, LeafHashGadget, TwoToOneHashGadget, ConstraintF>;
-pub type LeafHashParamsVar = <LeafHashGadget as CRHGadget<LeafHash, ConstraintF>>::ParametersVar;
-pub type TwoToOneHashParamsVar =
-    <TwoToOneHashGadget as TwoToOneCRHGadget<TwoToOneHash, ConstraintF>>::ParametersVar;
-
-/// The parameters that are used in transaction creation and validation.
-pub struct ParametersVar {
+/// The gadgets for the hash functions of an account Merkle tree configuration. Like the
+/// native hashes, they output field elements, so that roots are `AccRootVar`s whatever
+/// the configuration.
+pub trait MerkleConfigGadget: AccountMerkleConfig {
+    type LeafHashGadget: CRHGadget<Self::LeafHash, ConstraintF, OutputVar = FpVar<ConstraintF>>;
+    type TwoToOneHashGadget: TwoToOneCRHGadget<
+        Self::TwoToOneHash,
+        ConstraintF,
+        OutputVar = FpVar<ConstraintF>,
+    >;
+}
+
+impl MerkleConfigGadget for PedersenMerkleConfig {
+    type LeafHashGadget = PedersenCRHCompressorGadget<
+        EdwardsProjective,
+        TECompressor,
+        LeafWindow,
+        EdwardsVar,
+        TECompressorGadget,
+    >;
+    type TwoToOneHashGadget = PedersenCRHCompressorGadget<
+        EdwardsProjective,
+        TECompressor,
+        TwoToOneWindow,
+        EdwardsVar,
+        TECompressorGadget,
+    >;
+}
+
+impl MerkleConfigGadget for PoseidonMerkleConfig {
+    type LeafHashGadget = poseidon::constraints::CRHGadget<ConstraintF, LeafSize>;
+    type TwoToOneHashGadget = poseidon::constraints::TwoToOneCRHGadget<ConstraintF>;
+}
+
+pub type AccRootVar = FpVar<ConstraintF>;
+pub type AccPathVar<C = MerkleConfig> = MerklePathVar<C>;
+pub type LeafHashParamsVar<C = MerkleConfig> =
+    <<C as MerkleConfigGadget>::LeafHashGadget as CRHGadget<
+        <C as merkle_tree::Config>::LeafHash,
+        ConstraintF,
+    >>::ParametersVar;
+pub type TwoToOneHashParamsVar<C = MerkleConfig> =
+    <<C as MerkleConfigGadget>::TwoToOneHashGadget as TwoToOneCRHGadget<
+        <C as merkle_tree::Config>::TwoToOneHash,
+        ConstraintF,
+    >>::ParametersVar;
+
+/// The output of the leaf hash of `C` in a circuit.
+type LeafDigestVar<C> = <<C as MerkleConfigGadget>::LeafHashGadget as CRHGadget<
+    <C as merkle_tree::Config>::LeafHash,
+    ConstraintF,
+>>::OutputVar;
+/// The output of the two-to-one hash of `C` in a circuit.
+type TwoToOneDigestVar<C> = <<C as MerkleConfigGadget>::TwoToOneHashGadget as TwoToOneCRHGadget<
+    <C as merkle_tree::Config>::TwoToOneHash,
+    ConstraintF,
+>>::OutputVar;
+
+/// An authentication path in a Merkle tree hashed according to `C`. It is verified like
+/// `ark_crypto_primitives`'s `PathVar`, but it also exposes the position of its leaf, so
+/// that circuits can check which account a path leads to (see
+/// `AccountIdVar::is_position_of`).
+pub struct MerklePathVar<C: MerkleConfigGadget> {
+    /// The bits of the leaf's position, starting from the least significant one, which
+    /// is whether the leaf is a right child.
+    position: Vec<Boolean<ConstraintF>>,
+    /// The sibling of the leaf.
+    leaf_sibling: LeafDigestVar<C>,
+    /// The siblings of the non-leaf nodes on the path, from the bottom up.
+    auth_path: Vec<TwoToOneDigestVar<C>>,
+}
+
+impl<C: MerkleConfigGadget> MerklePathVar<C> {
+    /// The bits of the position of the path's leaf, starting from the least significant
+    /// one.
+    pub fn position(&self) -> &[Boolean<ConstraintF>] {
+        &self.position
+    }
+
+    /// Check that `other` leads to the same position with the same siblings as this
+    /// path. Then `other` is this path after its leaf was updated, and the rest of the
+    /// tree stayed the same.
+    pub fn has_same_siblings(&self, other: &Self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.position[..]
+            .is_eq(&other.position[..])?
+            .and(&self.leaf_sibling.is_eq(&other.leaf_sibling)?)?
+            .and(&self.auth_path[..].is_eq(&other.auth_path[..])?)
+    }
+
+    /// Compute the root of the tree, assuming that `leaf` is the leaf on this path.
+    pub fn calculate_root(
+        &self,
+        leaf_hash_params: &LeafHashParamsVar<C>,
+        two_to_one_hash_params: &TwoToOneHashParamsVar<C>,
+        leaf: &impl ToBytesGadget<ConstraintF>,
+    ) -> Result<TwoToOneDigestVar<C>, SynthesisError> {
+        let leaf_hash = C::LeafHashGadget::evaluate(leaf_hash_params, &leaf.to_bytes()?)?;
+        // A bit of the position is set iff the node on the path at that level is the
+        // right child of its parent.
+        let is_right_child = &self.position[0];
+        let left = is_right_child.select(&self.leaf_sibling, &leaf_hash)?;
+        let right = is_right_child.select(&leaf_hash, &self.leaf_sibling)?;
+        let mut node = C::TwoToOneHashGadget::evaluate(
+            two_to_one_hash_params,
+            &left.to_bytes()?,
+            &right.to_bytes()?,
+        )?;
+        for (is_right_child, sibling) in self.position[1..].iter().zip(&self.auth_path) {
+            let left = is_right_child.select(sibling, &node)?;
+            let right = is_right_child.select(&node, sibling)?;
+            node = C::TwoToOneHashGadget::evaluate(
+                two_to_one_hash_params,
+                &left.to_bytes()?,
+                &right.to_bytes()?,
+            )?;
+        }
+        Ok(node)
+    }
+
+    /// Check that hashing `leaf` along this path leads to `root`.
+    #[tracing::instrument(
+        target = "r1cs",
+        skip(self, leaf_hash_params, two_to_one_hash_params, root, leaf)
+    )]
+    pub fn verify_membership(
+        &self,
+        leaf_hash_params: &LeafHashParamsVar<C>,
+        two_to_one_hash_params: &TwoToOneHashParamsVar<C>,
+        root: &TwoToOneDigestVar<C>,
+        leaf: &impl ToBytesGadget<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        self.calculate_root(leaf_hash_params, two_to_one_hash_params, leaf)?
+            .is_eq(root)
+    }
+}
+
+impl<C: MerkleConfigGadget> AllocVar<Path<C>, ConstraintF> for MerklePathVar<C> {
+    #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
+    fn new_variable<T: Borrow<Path<C>>>(
+        cs: impl Into<Namespace<ConstraintF>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let cs = cs.into().cs();
+        f().and_then(|path| {
+            let path = path.borrow();
+            // The native path lists the siblings from the top down.
+            let position = (0..=path.auth_path.len())
+                .map(|i| (path.leaf_index >> i) & 1 == 1)
+                .collect::<Vec<_>>();
+            let auth_path = path.auth_path.iter().rev().cloned().collect::<Vec<_>>();
+            Ok(Self {
+                position: Vec::new_variable(
+                    ark_relations::ns!(cs, "position"),
+                    || Ok(position),
+                    mode,
+                )?,
+                leaf_sibling: LeafDigestVar::<C>::new_variable(
+                    ark_relations::ns!(cs, "leaf_sibling"),
+                    || Ok(path.leaf_sibling_hash),
+                    mode,
+                )?,
+                auth_path: Vec::new_variable(
+                    ark_relations::ns!(cs, "auth_path"),
+                    || Ok(auth_path),
+                    mode,
+                )?,
+            })
+        })
+    }
+}
+
+/// The gadget for `ChallengeRO`.
+pub type ChallengeROGadget = blake2s::constraints::ROGadget;
+
+/// The gadget that verifies the signatures of a transaction signature scheme. Like the
+/// schemes themselves, such gadgets share the parameters and keys of accounts.
+pub trait SigSchemeGadget: TransactionSigScheme + Sized {
+    type VerifyGadget: SigVerifyGadget<
+        Self,
+        ConstraintF,
+        ParametersVar = SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
+        PublicKeyVar = AccountPublicKeyVar,
+    >;
+}
+
+impl SigSchemeGadget for SchnorrSigScheme {
+    type VerifyGadget =
+        SchnorrSignatureVerifyGadget<EdwardsProjective, EdwardsVar, ChallengeROGadget>;
+}
+
+impl SigSchemeGadget for EdDSASigScheme {
+    type VerifyGadget =
+        EdDSASignatureVerifyGadget<EdwardsProjective, EdwardsVar, ChallengeROGadget>;
+}
+
+/// The gadget that verifies signatures of `S`.
+pub type SigGadget<S = SigScheme> = <S as SigSchemeGadget>::VerifyGadget;
+/// A signature of `S`.
+pub type SignatureVar<S = SigScheme> =
+    <SigGadget<S> as SigVerifyGadget<S, ConstraintF>>::SignatureVar;
+
+/// The parameters that are used in transaction creation and validation, on a ledger
+/// whose account Merkle tree is hashed according to `C`.
+pub struct ParametersVar<C: MerkleConfigGadget = MerkleConfig> {
     pub sig_params: SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
-    pub leaf_crh_params: LeafHashParamsVar,
-    pub two_to_one_crh_params: TwoToOneHashParamsVar,
+    pub leaf_crh_params: LeafHashParamsVar<C>,
+    pub two_to_one_crh_params: TwoToOneHashParamsVar<C>,
 }
 
-impl AllocVar<Parameters, ConstraintF> for ParametersVar {
+impl<C: MerkleConfigGadget, S: TransactionSigScheme> AllocVar<Parameters<C, S>, ConstraintF>
+    for ParametersVar<C>
+{
     #[tracing::instrument(target = "r1cs", skip(cs, f, _mode))]
-    fn new_variable<T: Borrow<Parameters>>(
+    fn new_variable<T: Borrow<Parameters<C, S>>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         _mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
         let cs = cs.into();
         f().and_then(|params| {
-            let params: &Parameters = params.borrow();
+            let params: &Parameters<C, S> = params.borrow();
             let sig_params = SchnorrParamsVar::new_constant(cs.clone(), &params.sig_params)?;
             let leaf_crh_params =
-                LeafHashParamsVar::new_constant(cs.clone(), &params.leaf_crh_params)?;
-            let two_to_one_crh_params =
-                TwoToOneHashParamsVar::new_constant(cs.clone(), &params.two_to_one_crh_params)?;
+                LeafHashParamsVar::<C>::new_constant(cs.clone(), &params.leaf_crh_params)?;
+            let two_to_one_crh_params = TwoToOneHashParamsVar::<C>::new_constant(
+                cs.clone(),
+                &params.two_to_one_crh_params,
+            )?;
             Ok(Self {
                 sig_params,
                 leaf_crh_params,
@@ -132,3 +324,187 @@ impl AllocVar<Parameters, ConstraintF> for ParametersVar {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::AccountIdVar;
+    use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_simple_payments::account::AccountId;
+    use ark_simple_payments::sparse_merkle_tree::SparseMerkleTree;
+
+    #[test]
+    fn sparse_path_verifies_in_circuit() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut tree = AccMerkleTree::<MerkleConfig>::blank(
+            &pp.leaf_crh_params,
+            &pp.two_to_one_crh_params,
+            21,
+        )
+        .unwrap();
+        let index = (1 << 20) - 3;
+        let leaf = [7u8; 80];
+        tree.update(index, &leaf).unwrap();
+        let path = tree.generate_proof(index).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        let pp_var = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        let root_var = AccRootVar::new_input(cs.clone(), || Ok(tree.root())).unwrap();
+        let path_var = AccPathVar::new_witness(cs.clone(), || Ok(path)).unwrap();
+        let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaf).unwrap();
+        let verifies = |leaf_var: &[UInt8<ConstraintF>]| {
+            path_var
+                .verify_membership(
+                    &pp_var.leaf_crh_params,
+                    &pp_var.two_to_one_crh_params,
+                    &root_var,
+                    &leaf_var,
+                )
+                .unwrap()
+                .value()
+                .unwrap()
+        };
+        assert!(verifies(&leaf_var));
+        // A different leaf is not a member.
+        let other_leaf_var = UInt8::new_witness_vec(cs.clone(), &[8u8; 80]).unwrap();
+        assert!(!verifies(&other_leaf_var));
+
+        // The path only leads to the account at its position, and not to accounts that
+        // share some of its bits, or that lie beyond the last leaf.
+        let leads_to = |id: u32| {
+            AccountIdVar::new_witness(cs.clone(), || Ok(AccountId(id)))
+                .unwrap()
+                .is_position_of(&path_var)
+                .unwrap()
+                .value()
+                .unwrap()
+        };
+        assert!(leads_to(index as u32));
+        for id in [
+            index as u32 ^ 1,
+            index as u32 & 0xff,
+            index as u32 | 1 << 20,
+        ] {
+            assert!(!leads_to(id));
+        }
+    }
+
+    /// The number of constraints that a transaction spends on the four account paths that
+    /// it checks, when the tree (which holds every `AccountId`) is hashed according to `C`.
+    fn path_constraints_per_transaction<C: MerkleConfigGadget>() -> usize {
+        let mut rng = ark_std::test_rng();
+        let leaf_params = <C::LeafHash as CRH>::setup(&mut rng).unwrap();
+        let two_to_one_params = <C::TwoToOneHash as TwoToOneCRH>::setup(&mut rng).unwrap();
+        let mut tree =
+            SparseMerkleTree::<C>::blank(&leaf_params, &two_to_one_params, MAX_TREE_HEIGHT + 1)
+                .unwrap();
+        let index = 12345;
+        let leaf = vec![7u8; <C::LeafHash as CRH>::INPUT_SIZE_BITS / 8];
+        tree.update(index, &leaf).unwrap();
+        let path = tree.generate_proof(index).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        let leaf_params_var = <C::LeafHashGadget as CRHGadget<_, _>>::ParametersVar::new_constant(
+            cs.clone(),
+            &leaf_params,
+        )
+        .unwrap();
+        let two_to_one_params_var =
+            <C::TwoToOneHashGadget as TwoToOneCRHGadget<_, _>>::ParametersVar::new_constant(
+                cs.clone(),
+                &two_to_one_params,
+            )
+            .unwrap();
+        let root_var = <C::TwoToOneHashGadget as TwoToOneCRHGadget<_, _>>::OutputVar::new_input(
+            cs.clone(),
+            || Ok(tree.root()),
+        )
+        .unwrap();
+        let leaf_var = UInt8::new_witness_vec(cs.clone(), &leaf).unwrap();
+        for _ in 0..4 {
+            let path_var = MerklePathVar::<C>::new_witness(cs.clone(), || Ok(&path)).unwrap();
+            path_var
+                .verify_membership(
+                    &leaf_params_var,
+                    &two_to_one_params_var,
+                    &root_var,
+                    &leaf_var.as_slice(),
+                )
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+        }
+        assert!(cs.is_satisfied().unwrap());
+        cs.num_constraints()
+    }
+
+    #[test]
+    fn poseidon_paths_take_fewer_constraints() {
+        let pedersen = path_constraints_per_transaction::<PedersenMerkleConfig>();
+        let poseidon = path_constraints_per_transaction::<PoseidonMerkleConfig>();
+        println!(
+            "Constraints per transaction for account paths: {} with Pedersen, {} with Poseidon",
+            pedersen, poseidon
+        );
+        assert!(poseidon < pedersen);
+    }
+
+    /// Checks that a signature of `S` on a message verifies in a circuit with the gadget of
+    /// `S`, and that it does not on another message, and returns the number of constraints
+    /// that verifying it takes.
+    fn signature_verifies_in_circuit<S: SigSchemeGadget>() -> usize {
+        let mut rng = ark_std::test_rng();
+        let parameters = S::setup(&mut rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, &mut rng).unwrap();
+        let message = b"Alice pays Bob";
+        let signature = S::sign(&parameters, &sk, message, &mut rng).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        let parameters_var =
+            <S::VerifyGadget as SigVerifyGadget<S, _>>::ParametersVar::new_constant(
+                cs.clone(),
+                &parameters,
+            )
+            .unwrap();
+        let pk_var = <S::VerifyGadget as SigVerifyGadget<S, _>>::PublicKeyVar::new_witness(
+            cs.clone(),
+            || Ok(&pk),
+        )
+        .unwrap();
+        let signature_var = <S::VerifyGadget as SigVerifyGadget<S, _>>::SignatureVar::new_witness(
+            cs.clone(),
+            || Ok(&signature),
+        )
+        .unwrap();
+        let verifies = |message: &[u8]| {
+            let message_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+            <S::VerifyGadget as SigVerifyGadget<S, _>>::verify(
+                &parameters_var,
+                &pk_var,
+                &message_var,
+                &signature_var,
+            )
+            .unwrap()
+            .value()
+            .unwrap()
+        };
+        let num_constraints = cs.num_constraints();
+        assert!(verifies(message));
+        let num_constraints = cs.num_constraints() - num_constraints;
+        assert!(!verifies(b"Alice pays Eve"));
+        assert!(cs.is_satisfied().unwrap());
+        num_constraints
+    }
+
+    #[test]
+    fn either_signature_scheme_verifies_in_circuit() {
+        let schnorr = signature_verifies_in_circuit::<SchnorrSigScheme>();
+        let eddsa = signature_verifies_in_circuit::<EdDSASigScheme>();
+        println!(
+            "Constraints per signature verification: {} with Schnorr, {} with EdDSA",
+            schnorr, eddsa
+        );
+    }
+}
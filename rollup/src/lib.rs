@@ -1,7 +1,11 @@
 pub type ConstraintF = ark_bls12_381::Fr;
 
 pub mod account;
+pub mod bridge;
 pub mod ledger;
 pub mod transaction;
 
+pub mod prover;
 pub mod rollup;
+pub mod sequencer;
+pub mod verifier;
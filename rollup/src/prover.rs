@@ -0,0 +1,279 @@
+use crate::ledger::{MerkleConfigGadget, SigSchemeGadget};
+use crate::rollup::Rollup;
+use crate::transaction::UnaryRollup;
+use crate::ConstraintF;
+use ark_bls12_381::Bls12_381;
+use ark_ff::ToConstraintField;
+use ark_simple_payments::account::AccountId;
+use ark_simple_payments::bridge::BridgeDigest;
+use ark_simple_payments::ledger::{AccRoot, Parameters};
+use ark_snark::SNARK;
+use ark_std::rand::{CryptoRng, RngCore};
+
+/// Groth16 over BLS12-381, whose scalar field is `ConstraintF`.
+pub type Groth16 = ark_groth16::Groth16<Bls12_381>;
+/// GM17 over BLS12-381, whose scalar field is `ConstraintF`.
+pub type GM17 = ark_gm17::GM17<Bls12_381>;
+
+/// A SNARK that proves that a batch of transactions takes the ledger from one root to
+/// another, via either `Rollup<NUM_TX>` or `UnaryRollup`. Proofs are generated with
+/// `SNARK::prove`, from circuits created via `Rollup::with_state_and_transactions` or
+/// `UnaryRollup::with_state_and_transaction` with transaction validation enabled.
+///
+/// This is implemented for every SNARK over `ConstraintF`, such as `Groth16` and `GM17`,
+/// so that code which is generic over `RollupSNARK` can switch between them.
+pub trait RollupSNARK: SNARK<ConstraintF> {
+    /// Generate keys for proving and verifying batches of `NUM_TX` transactions on a
+    /// ledger whose account Merkle tree has height `tree_height`.
+    /// The keys are specific to `ledger_params`, `NUM_TX`, and `tree_height`.
+    fn setup_rollup<
+        R: RngCore + CryptoRng,
+        C: MerkleConfigGadget,
+        S: SigSchemeGadget,
+        const NUM_TX: usize,
+    >(
+        ledger_params: &Parameters<C, S>,
+        tree_height: usize,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), Self::Error> {
+        let circuit = Rollup::<NUM_TX, C, S>::new_empty(ledger_params.clone(), tree_height);
+        Self::circuit_specific_setup(circuit, rng)
+    }
+
+    /// Generate keys for proving and verifying single transactions via `UnaryRollup`
+    /// on a ledger whose account Merkle tree has height `tree_height`.
+    /// The keys are specific to `ledger_params` and `tree_height`.
+    fn setup_unary_rollup<R: RngCore + CryptoRng, C: MerkleConfigGadget, S: SigSchemeGadget>(
+        ledger_params: &Parameters<C, S>,
+        tree_height: usize,
+        rng: &mut R,
+    ) -> Result<(Self::ProvingKey, Self::VerifyingKey), Self::Error> {
+        let circuit = UnaryRollup::new_empty(ledger_params.clone(), tree_height);
+        Self::circuit_specific_setup(circuit, rng)
+    }
+
+    /// Verify that `proof` shows that a valid batch of transactions takes the ledger
+    /// from `initial_root` to `final_root`, while crediting the deposits committed to
+    /// by `deposits_digest`, emitting the exits committed to by `exits_digest`, and
+    /// crediting the fees to `fee_collector`.
+    fn verify_rollup(
+        vk: &Self::VerifyingKey,
+        initial_root: &AccRoot,
+        final_root: &AccRoot,
+        deposits_digest: &BridgeDigest,
+        exits_digest: &BridgeDigest,
+        fee_collector: AccountId,
+        proof: &Self::Proof,
+    ) -> Result<bool, Self::Error> {
+        let inputs = public_inputs(
+            initial_root,
+            final_root,
+            deposits_digest,
+            exits_digest,
+            fee_collector,
+        );
+        Self::verify(vk, &inputs, proof)
+    }
+}
+
+impl<S: SNARK<ConstraintF>> RollupSNARK for S {}
+
+/// The public inputs of the rollup circuits, in the order in which they declare them.
+pub fn public_inputs(
+    initial_root: &AccRoot,
+    final_root: &AccRoot,
+    deposits_digest: &BridgeDigest,
+    exits_digest: &BridgeDigest,
+    fee_collector: AccountId,
+) -> Vec<ConstraintF> {
+    let mut inputs = initial_root.to_field_elements().unwrap();
+    inputs.extend(final_root.to_field_elements().unwrap());
+    inputs.extend(deposits_digest.to_field_elements().unwrap());
+    inputs.extend(exits_digest.to_field_elements().unwrap());
+    inputs.push(ConstraintF::from(fee_collector.0));
+    inputs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_simple_payments::account::Nonce;
+    use ark_simple_payments::bridge::{empty_digest, extend_digest};
+    use ark_simple_payments::ledger::{Amount, State};
+    use ark_simple_payments::transaction::Transaction;
+    use std::fmt::Debug;
+
+    fn prove_and_verify_batches<S: RollupSNARK>()
+    where
+        S::Error: Debug,
+    {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        let empty = empty_digest();
+        // Verify a proof for a batch that credits its fees to the operator.
+        let verify = |vk: &S::VerifyingKey,
+                      initial_root: &AccRoot,
+                      final_root: &AccRoot,
+                      deposits_digest: &BridgeDigest,
+                      exits_digest: &BridgeDigest,
+                      proof: &S::Proof| {
+            S::verify_rollup(
+                vk,
+                initial_root,
+                final_root,
+                deposits_digest,
+                exits_digest,
+                operator_id,
+                proof,
+            )
+            .unwrap()
+        };
+
+        // The keys only depend on the shape of the ledger, not on its contents.
+        let tree_height = state.account_merkle_tree.height();
+        let (pk, vk) = S::setup_rollup::<_, _, _, 1>(&pp, tree_height, &mut rng).unwrap();
+        let (unary_pk, unary_vk) = S::setup_unary_rollup(&pp, tree_height, &mut rng).unwrap();
+
+        // Prove the first transaction via `Rollup`...
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut state,
+            true,
+        )
+        .unwrap();
+        let (initial_root, final_root) = (rollup.initial_root.unwrap(), rollup.final_root.unwrap());
+        assert_eq!(final_root, state.root());
+        let proof = S::prove(&pk, rollup, &mut rng).unwrap();
+        assert!(verify(
+            &vk,
+            &initial_root,
+            &final_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+        // The proof does not verify against any other pair of roots, or against
+        // deposits and exits that the batch does not contain...
+        assert!(!verify(
+            &vk,
+            &final_root,
+            &initial_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+        assert!(!verify(
+            &vk,
+            &initial_root,
+            &initial_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+        let deposit = extend_digest(&pp, &empty, bob_id, Amount(5));
+        assert!(!verify(
+            &vk,
+            &initial_root,
+            &final_root,
+            &deposit,
+            &empty,
+            &proof
+        ));
+        assert!(!verify(
+            &vk,
+            &initial_root,
+            &final_root,
+            &empty,
+            &deposit,
+            &proof
+        ));
+        // ... or for another fee collector.
+        assert!(!S::verify_rollup(
+            &vk,
+            &initial_root,
+            &final_root,
+            &empty,
+            &empty,
+            bob_id,
+            &proof
+        )
+        .unwrap());
+
+        // ... and the second one via `UnaryRollup`.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(1),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state, true).unwrap();
+        assert_eq!(rollup.initial_root, final_root);
+        let (next_root, final_root) = (rollup.initial_root, rollup.final_root);
+        let proof = S::prove(&unary_pk, rollup, &mut rng).unwrap();
+        assert!(verify(
+            &unary_vk,
+            &next_root,
+            &final_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+        assert!(!verify(
+            &unary_vk,
+            &initial_root,
+            &final_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+        // Keys for one circuit do not work for the other.
+        assert!(!verify(
+            &vk,
+            &next_root,
+            &final_root,
+            &empty,
+            &empty,
+            &proof
+        ));
+
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(10));
+        assert_eq!(state.id_to_account_info[&operator_id].balance, Amount(2));
+    }
+
+    #[test]
+    fn groth16_proves_batches() {
+        prove_and_verify_batches::<Groth16>();
+    }
+
+    #[test]
+    fn gm17_proves_batches() {
+        prove_and_verify_batches::<GM17>();
+    }
+}
@@ -1,158 +1,494 @@
-use crate::account::AccountInformationVar;
+use crate::account::{AccountIdVar, AccountInformationVar};
+use crate::bridge::{absorb_transaction, BridgeDigestVar};
 use crate::ledger::*;
-use crate::transaction::TransactionVar;
+use crate::transaction::{verify_fee_credit, TransactionVar};
 use crate::ConstraintF;
+use ark_ed_on_bls12_381::EdwardsProjective;
 use ark_r1cs_std::prelude::*;
+use ark_r1cs_std::uint64::UInt64;
 use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
 use ark_simple_payments::{
-    account::AccountInformation,
-    ledger::{AccPath, AccRoot, Parameters, State},
-    transaction::Transaction,
+    account::{AccountId, AccountInformation, Nonce},
+    bridge::{self, empty_digest, BridgeDigest},
+    ledger::{
+        AccPath, AccRoot, AccountMerkleConfig, Amount, ChallengeRO, LedgerError, MerkleConfig,
+        Parameters, SchnorrSigScheme, SigScheme, State, TransactionSigScheme,
+    },
+    signature::aggregation::{self, constraints::AggregateSignatureVar, AggregateSignature},
+    transaction::{Transaction, TransactionKind},
 };
+use derivative::Derivative;
 
-pub struct Rollup<const NUM_TX: usize> {
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct Rollup<
+    const NUM_TX: usize,
+    C: MerkleConfigGadget = MerkleConfig,
+    S: SigSchemeGadget = SigScheme,
+> {
     /// The ledger parameters.
-    pub ledger_params: Parameters,
+    pub ledger_params: Parameters<C, S>,
     /// The Merkle tree root before applying this batch of transactions.
     pub initial_root: Option<AccRoot>,
     /// The Merkle tree root after applying this batch of transactions.
     pub final_root: Option<AccRoot>,
+    /// The digest of the deposits that this batch credits, in order.
+    pub deposits_digest: Option<BridgeDigest>,
+    /// The digest of the exits that the withdrawals in this batch emit, in order.
+    pub exits_digest: Option<BridgeDigest>,
     /// The current batch of transactions.
-    pub transactions: Option<Vec<Transaction>>,
+    pub transactions: Option<Vec<Transaction<S>>>,
+    /// Whether the i-th transaction is a no-op, which is only there to pad the batch
+    /// to `NUM_TX` transactions. No-ops leave the state unchanged.
+    pub is_noop: Option<Vec<bool>>,
     /// The sender's account information and corresponding authentication path,
     /// *before* applying the transactions.
-    pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
+    pub sender_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath<C>)>>,
     /// The authentication path corresponding to the sender's account information
-    /// *after* applying the transactions.
-    pub sender_post_paths: Option<Vec<AccPath>>,
-    /// The recipient's account information and corresponding authentication path,
-    /// *before* applying the transactions.
-    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath)>>,
+    /// *after* updating the sender, but *before* updating the recipient.
+    pub sender_post_paths: Option<Vec<AccPath<C>>>,
+    /// The recipient's account information *before* applying the transactions, and
+    /// the corresponding authentication path *after* updating the sender.
+    pub recv_pre_tx_info_and_paths: Option<Vec<(AccountInformation, AccPath<C>)>>,
     /// The authentication path corresponding to the recipient's account information
     /// *after* applying the transactions.
-    pub recv_post_paths: Option<Vec<AccPath>>,
+    pub recv_post_paths: Option<Vec<AccPath<C>>>,
     /// List of state roots, so that the i-th root is the state roots before applying
     /// the i-th transaction. This means that `pre_tx_roots[0] == initial_root`.
     pub pre_tx_roots: Option<Vec<AccRoot>>,
     /// List of state roots, so that the i-th root is the state root after applying
-    /// the i-th transaction. This means that `post_tx_roots[NUM_TX - 1] == final_root`.
+    /// the i-th transaction. The fees of the batch are credited to the fee collector
+    /// only afterwards, which turns `post_tx_roots[NUM_TX - 1]` into `final_root`.
     pub post_tx_roots: Option<Vec<AccRoot>>,
+    /// The account that the fees of the batch are credited to.
+    pub fee_collector: Option<AccountId>,
+    /// The fee collector's account information and corresponding authentication path,
+    /// *after* applying the transactions but *before* crediting the fees.
+    pub fee_collector_pre_info_and_path: Option<(AccountInformation, AccPath<C>)>,
+    /// The authentication path corresponding to the fee collector's account information
+    /// *after* crediting the fees.
+    pub fee_collector_post_path: Option<AccPath<C>>,
 }
 
-impl<const NUM_TX: usize> Rollup<NUM_TX> {
-    pub fn new_empty(ledger_params: Parameters) -> Self {
+impl<const NUM_TX: usize, C: MerkleConfigGadget, S: SigSchemeGadget> Rollup<NUM_TX, C, S> {
+    /// Create a rollup with placeholder values for all inputs and witnesses, for a
+    /// ledger whose account Merkle tree has height `tree_height` (as returned by
+    /// `AccMerkleTree::height`). Since the values do not form a valid batch, this is
+    /// only useful to determine the shape of the circuit, e.g. during SNARK setup.
+    pub fn new_empty(ledger_params: Parameters<C, S>, tree_height: usize) -> Self {
+        let acc_info = placeholder_account_info();
+        let path = placeholder_path(tree_height);
         Self {
             ledger_params,
-            initial_root: None,
-            final_root: None,
-            transactions: None,
-            sender_pre_tx_info_and_paths: None,
-            sender_post_paths: None,
-            recv_pre_tx_info_and_paths: None,
-            recv_post_paths: None,
-            pre_tx_roots: None,
-            post_tx_roots: None,
+            initial_root: Some(AccRoot::default()),
+            final_root: Some(AccRoot::default()),
+            deposits_digest: Some(empty_digest()),
+            exits_digest: Some(empty_digest()),
+            transactions: Some(vec![placeholder_transaction(); NUM_TX]),
+            is_noop: Some(vec![false; NUM_TX]),
+            sender_pre_tx_info_and_paths: Some(vec![(acc_info, path.clone()); NUM_TX]),
+            sender_post_paths: Some(vec![path.clone(); NUM_TX]),
+            recv_pre_tx_info_and_paths: Some(vec![(acc_info, path.clone()); NUM_TX]),
+            recv_post_paths: Some(vec![path.clone(); NUM_TX]),
+            pre_tx_roots: Some(vec![AccRoot::default(); NUM_TX]),
+            post_tx_roots: Some(vec![AccRoot::default(); NUM_TX]),
+            fee_collector: Some(AccountId(0)),
+            fee_collector_pre_info_and_path: Some((acc_info, path.clone())),
+            fee_collector_post_path: Some(path),
         }
     }
 
     pub fn only_initial_and_final_roots(
-        ledger_params: Parameters,
+        ledger_params: Parameters<C, S>,
         initial_root: AccRoot,
         final_root: AccRoot,
+        deposits_digest: BridgeDigest,
+        exits_digest: BridgeDigest,
+        fee_collector: AccountId,
     ) -> Self {
         Self {
             ledger_params,
             initial_root: Some(initial_root),
             final_root: Some(final_root),
+            deposits_digest: Some(deposits_digest),
+            exits_digest: Some(exits_digest),
             transactions: None,
+            is_noop: None,
             sender_pre_tx_info_and_paths: None,
             sender_post_paths: None,
             recv_pre_tx_info_and_paths: None,
             recv_post_paths: None,
             pre_tx_roots: None,
             post_tx_roots: None,
+            fee_collector: Some(fee_collector),
+            fee_collector_pre_info_and_path: None,
+            fee_collector_post_path: None,
         }
     }
 
+    /// Create a rollup that applies `transactions` to `state`, and then credits their
+    /// fees to the fee collector of `state`. Returns `None`, and leaves `state`
+    /// unchanged, if the ledger has no fee collector, or if `validate_transactions` is
+    /// true and a transaction is invalid.
     pub fn with_state_and_transactions(
-        ledger_params: Parameters,
-        transactions: &[Transaction],
-        state: &mut State,
+        ledger_params: Parameters<C, S>,
+        transactions: &[Transaction<S>],
+        state: &mut State<C>,
         validate_transactions: bool,
     ) -> Option<Self> {
         assert_eq!(transactions.len(), NUM_TX);
-        let initial_root = Some(state.root());
+        Self::with_state_and_padded_transactions(
+            ledger_params,
+            transactions,
+            state,
+            validate_transactions,
+        )
+    }
+
+    /// Like `with_state_and_transactions`, but accepts up to `NUM_TX` transactions, and
+    /// fills the remaining slots of the batch with no-ops. This way, the keys for
+    /// `Rollup<NUM_TX>` can be used to prove smaller batches, and even empty ones.
+    pub fn with_state_and_padded_transactions(
+        ledger_params: Parameters<C, S>,
+        transactions: &[Transaction<S>],
+        state: &mut State<C>,
+        validate_transactions: bool,
+    ) -> Option<Self> {
+        if transactions.len() > NUM_TX {
+            return None;
+        }
+        // The batch is applied to a copy of `state`, which only replaces `state` once the
+        // whole batch has been applied, so that `state` is left untouched on failure.
+        let mut new_state = state.clone();
+        let fee_collector = new_state.fee_collector?;
+        let initial_root = Some(new_state.root());
+        let (deposits_digest, exits_digest) = bridge::digests(&ledger_params, transactions);
         let mut sender_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
         let mut recipient_pre_tx_info_and_paths = Vec::with_capacity(NUM_TX);
         let mut sender_post_paths = Vec::with_capacity(NUM_TX);
         let mut recipient_post_paths = Vec::with_capacity(NUM_TX);
         let mut pre_tx_roots = Vec::with_capacity(NUM_TX);
         let mut post_tx_roots = Vec::with_capacity(NUM_TX);
-        for tx in transactions {
-            if !tx.validate(&ledger_params, &*state) && validate_transactions {
-                return None;
-            }
-        }
+        let mut total_fees = Amount(0);
         for tx in transactions {
             let sender_id = tx.sender;
             let recipient_id = tx.recipient;
-            let pre_tx_root = state.root();
-            let sender_pre_acc_info = *state.id_to_account_info.get(&sender_id)?;
-            let sender_pre_path = state
-                .account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-            let recipient_pre_acc_info = *state.id_to_account_info.get(&recipient_id)?;
-            let recipient_pre_path = state
-                .account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
+            let (sender_involved, recipient_involved) = involved_accounts(tx);
+            let pre_tx_root = new_state.root();
+            let (sender_acc_info, sender_pre_path) =
+                info_and_path(&new_state, sender_id, sender_involved)?;
+            let (recipient_acc_info, _) =
+                info_and_path(&new_state, recipient_id, recipient_involved)?;
 
-            if validate_transactions {
-                state.apply_transaction(&ledger_params, tx)?;
-            } else {
-                let _ = state.apply_transaction(&ledger_params, tx);
+            // Each transaction is validated against the state left behind by the
+            // previous ones, since it must carry the sender's nonce at that point. Fees
+            // are credited all at once, after applying the whole batch.
+            if apply_deferring_fee(&ledger_params, &mut new_state, tx).is_ok() {
+                total_fees = total_fees.checked_add(tx.fee)?;
+            } else if validate_transactions {
+                return None;
             }
-            let post_tx_root = state.root();
-            let sender_post_path = state
-                .account_merkle_tree
-                .generate_proof(sender_id.0 as usize)
-                .unwrap();
-            let recipient_post_path = state
-                .account_merkle_tree
-                .generate_proof(recipient_id.0 as usize)
-                .unwrap();
-            sender_pre_tx_info_and_paths.push((sender_pre_acc_info, sender_pre_path));
-            recipient_pre_tx_info_and_paths.push((recipient_pre_acc_info, recipient_pre_path));
+            let post_tx_root = new_state.root();
+            // The transaction only changes the leaves of the sender and of the recipient,
+            // which the circuit updates in this order. So updating the sender keeps its
+            // siblings, and the recipient has the same siblings before and after its
+            // update as in the final state.
+            let sender_post_path = sender_pre_path.clone();
+            let (_, recipient_post_path) =
+                info_and_path(&new_state, recipient_id, recipient_involved)?;
+            let recipient_pre_path = recipient_post_path.clone();
+            sender_pre_tx_info_and_paths.push((sender_acc_info, sender_pre_path));
+            recipient_pre_tx_info_and_paths.push((recipient_acc_info, recipient_pre_path));
             sender_post_paths.push(sender_post_path);
             recipient_post_paths.push(recipient_post_path);
             pre_tx_roots.push(pre_tx_root);
             post_tx_roots.push(post_tx_root);
         }
 
-        Some(Self {
+        // No-ops do not touch any accounts, so their account information and paths are
+        // just placeholders.
+        let num_noops = NUM_TX - transactions.len();
+        let noop_root = new_state.root();
+        let placeholder_path = placeholder_path(new_state.account_merkle_tree.height());
+        let placeholder_info_and_path = (placeholder_account_info(), placeholder_path.clone());
+        let mut padded_transactions = transactions.to_vec();
+        padded_transactions.resize(NUM_TX, placeholder_transaction());
+        let mut is_noop = vec![false; transactions.len()];
+        is_noop.resize(NUM_TX, true);
+        sender_pre_tx_info_and_paths.extend(vec![placeholder_info_and_path.clone(); num_noops]);
+        recipient_pre_tx_info_and_paths.extend(vec![placeholder_info_and_path; num_noops]);
+        sender_post_paths.extend(vec![placeholder_path.clone(); num_noops]);
+        recipient_post_paths.extend(vec![placeholder_path; num_noops]);
+        pre_tx_roots.extend(vec![noop_root; num_noops]);
+        post_tx_roots.extend(vec![noop_root; num_noops]);
+
+        let fee_collector_pre_acc_info = *new_state.id_to_account_info.get(&fee_collector)?;
+        let fee_collector_pre_path = new_state
+            .account_merkle_tree
+            .generate_proof(fee_collector.leaf_index())
+            .unwrap();
+        new_state.credit_fees(total_fees).ok()?;
+        let fee_collector_post_path = new_state
+            .account_merkle_tree
+            .generate_proof(fee_collector.leaf_index())
+            .unwrap();
+
+        let rollup = Self {
             ledger_params,
             initial_root,
-            final_root: Some(state.root()),
-            transactions: Some(transactions.to_vec()),
+            final_root: Some(new_state.root()),
+            deposits_digest: Some(deposits_digest),
+            exits_digest: Some(exits_digest),
+            transactions: Some(padded_transactions),
+            is_noop: Some(is_noop),
             sender_pre_tx_info_and_paths: Some(sender_pre_tx_info_and_paths),
             recv_pre_tx_info_and_paths: Some(recipient_pre_tx_info_and_paths),
             sender_post_paths: Some(sender_post_paths),
             recv_post_paths: Some(recipient_post_paths),
             pre_tx_roots: Some(pre_tx_roots),
             post_tx_roots: Some(post_tx_roots),
+            fee_collector: Some(fee_collector),
+            fee_collector_pre_info_and_path: Some((
+                fee_collector_pre_acc_info,
+                fee_collector_pre_path,
+            )),
+            fee_collector_post_path: Some(fee_collector_post_path),
+        };
+        *state = new_state;
+        Some(rollup)
+    }
+}
+
+/// Like `Rollup`, but verifies a single half-aggregate of the signatures of all
+/// transactions, instead of the signature of each transaction (see
+/// `signature::aggregation`). The individual signatures in `rollup.transactions` are
+/// ignored. Of the approvals of a multisig transaction, only the first one is
+/// aggregated, and the others are verified one by one.
+///
+/// The aggregate consists of Schnorr signatures, so the transactions are signed with
+/// `SchnorrSigScheme`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""))]
+pub struct AggregateRollup<const NUM_TX: usize, C: MerkleConfigGadget = MerkleConfig> {
+    /// The batch of transactions, along with the roots and paths that it touches.
+    pub rollup: Rollup<NUM_TX, C, SchnorrSigScheme>,
+    /// The aggregate of the signatures of the transactions that are signed (see
+    /// `TransactionVar::signer_key`), where the no-ops, the unsigned transactions and the
+    /// multisig transactions without a first approval leave their slots empty.
+    pub aggregate_signature: Option<AggregateSignature<EdwardsProjective>>,
+}
+
+impl<const NUM_TX: usize, C: MerkleConfigGadget> AggregateRollup<NUM_TX, C> {
+    /// Create a rollup with placeholder values for all inputs and witnesses, like
+    /// `Rollup::new_empty`.
+    pub fn new_empty(ledger_params: Parameters<C, SchnorrSigScheme>, tree_height: usize) -> Self {
+        Self {
+            rollup: Rollup::new_empty(ledger_params, tree_height),
+            aggregate_signature: Some(AggregateSignature {
+                prover_commitments: vec![Default::default(); NUM_TX],
+                prover_response: Default::default(),
+            }),
+        }
+    }
+
+    pub fn only_initial_and_final_roots(
+        ledger_params: Parameters<C, SchnorrSigScheme>,
+        initial_root: AccRoot,
+        final_root: AccRoot,
+        deposits_digest: BridgeDigest,
+        exits_digest: BridgeDigest,
+        fee_collector: AccountId,
+    ) -> Self {
+        Self {
+            rollup: Rollup::only_initial_and_final_roots(
+                ledger_params,
+                initial_root,
+                final_root,
+                deposits_digest,
+                exits_digest,
+                fee_collector,
+            ),
+            aggregate_signature: None,
+        }
+    }
+
+    /// Like `Rollup::with_state_and_padded_transactions`, but also aggregates the
+    /// signatures of the transactions.
+    pub fn with_state_and_padded_transactions(
+        ledger_params: Parameters<C, SchnorrSigScheme>,
+        transactions: &[Transaction<SchnorrSigScheme>],
+        state: &mut State<C>,
+        validate_transactions: bool,
+    ) -> Option<Self> {
+        let mut new_state = state.clone();
+        let rollup = Rollup::with_state_and_padded_transactions(
+            ledger_params,
+            transactions,
+            &mut new_state,
+            validate_transactions,
+        )?;
+        let transactions = rollup.transactions.as_ref()?;
+        let is_noop = rollup.is_noop.as_ref()?;
+        let senders = rollup.sender_pre_tx_info_and_paths.as_ref()?;
+        let messages = transactions
+            .iter()
+            .zip(is_noop)
+            .map(|(tx, is_noop)| tx.signed_message(&new_state).filter(|_| !is_noop))
+            .collect::<Vec<_>>();
+        let batch = messages
+            .iter()
+            .zip(transactions)
+            .zip(senders)
+            .map(|((message, tx), (sender_acc_info, _))| {
+                // Multisig transactions contribute the approval of their first signer.
+                let (public_key, signature) = match &tx.multisig {
+                    Some(approval) => (
+                        *approval.policy.public_keys.first()?,
+                        approval.signatures.first()?.as_ref()?,
+                    ),
+                    None => (sender_acc_info.public_key, &tx.signature),
+                };
+                Some((public_key, &message.as_ref()?[..], signature))
+            })
+            .collect::<Vec<_>>();
+        let aggregate_signature = aggregation::aggregate::<EdwardsProjective, ChallengeRO>(
+            &rollup.ledger_params.sig_params,
+            &batch,
+        )
+        .ok()?;
+        *state = new_state;
+        Some(Self {
+            rollup,
+            aggregate_signature: Some(aggregate_signature),
         })
     }
 }
 
-impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX> {
+/// A transaction that only serves as a placeholder.
+pub(crate) fn placeholder_transaction<S: TransactionSigScheme>() -> Transaction<S> {
+    Transaction {
+        kind: TransactionKind::Transfer,
+        sender: AccountId(0),
+        recipient: AccountId(0),
+        amount: Amount(0),
+        fee: Amount(0),
+        nonce: Nonce(0),
+        signature: Default::default(),
+        multisig: None,
+    }
+}
+
+/// Account information that only serves as a placeholder.
+pub(crate) fn placeholder_account_info() -> AccountInformation {
+    AccountInformation {
+        public_key: Default::default(),
+        balance: Amount(0),
+        nonce: Nonce(0),
+        next_key: Default::default(),
+        policy: Default::default(),
+    }
+}
+
+/// An authentication path of the right length for a tree of height `tree_height`,
+/// which only serves as a placeholder.
+pub(crate) fn placeholder_path<C: AccountMerkleConfig>(tree_height: usize) -> AccPath<C> {
+    AccPath {
+        leaf_sibling_hash: Default::default(),
+        auth_path: vec![Default::default(); tree_height - 2],
+        leaf_index: 0,
+    }
+}
+
+/// Whether `tx` changes the account of its sender and of its recipient, respectively.
+/// Deposits do not involve the sender, and withdrawals do not involve the recipient.
+pub(crate) fn involved_accounts<S: TransactionSigScheme>(tx: &Transaction<S>) -> (bool, bool) {
+    (
+        tx.kind != TransactionKind::Deposit,
+        tx.kind != TransactionKind::Withdrawal,
+    )
+}
+
+/// Apply `tx` to `state` like a batch does, without crediting its fee. Deposits are
+/// credited via `State::apply_deposit`, since the L1 only accepts the batch if they are
+/// next in its deposit queue.
+pub(crate) fn apply_deferring_fee<C: AccountMerkleConfig, S: TransactionSigScheme>(
+    ledger_params: &Parameters<C, S>,
+    state: &mut State<C>,
+    tx: &Transaction<S>,
+) -> Result<(), LedgerError> {
+    if tx.kind != TransactionKind::Deposit {
+        return state.apply_transaction_deferring_fee(ledger_params, tx);
+    }
+    let deposit = bridge::Deposit::from_transaction(tx).ok_or(LedgerError::InvalidDeposit)?;
+    state.apply_deposit(&deposit)
+}
+
+/// The account information of `id` and its authentication path in `state`, or
+/// placeholders if the account is not `involved` in the transaction at hand.
+/// The information of the next free leaf, which a registration fills, is a placeholder
+/// too. Returns `None` if an involved account does not exist.
+pub(crate) fn info_and_path<C: AccountMerkleConfig>(
+    state: &State<C>,
+    id: AccountId,
+    involved: bool,
+) -> Option<(AccountInformation, AccPath<C>)> {
+    if !involved {
+        let tree_height = state.account_merkle_tree.height();
+        return Some((placeholder_account_info(), placeholder_path(tree_height)));
+    }
+    let info = match state.leaf_info(id) {
+        Some(info) => info,
+        None if Some(id) == state.next_available_account => placeholder_account_info(),
+        None => return None,
+    };
+    let path = state
+        .account_merkle_tree
+        .generate_proof(id.leaf_index())
+        .ok()?;
+    Some((info, path))
+}
+
+/// How a rollup circuit verifies the signatures of its transactions.
+enum SignatureCheck {
+    /// Verify the signature of each transaction.
+    Individual,
+    /// Verify a single aggregate of all signatures, as in `AggregateRollup`.
+    Aggregate(Option<AggregateSignature<EdwardsProjective>>),
+}
+
+impl<const NUM_TX: usize, C: MerkleConfigGadget, S: SigSchemeGadget>
+    ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX, C, S>
+{
     #[tracing::instrument(target = "r1cs", skip(self, cs))]
     fn generate_constraints(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.generate_constraints_with(cs, SignatureCheck::Individual)
+    }
+}
+
+impl<const NUM_TX: usize, C: MerkleConfigGadget> ConstraintSynthesizer<ConstraintF>
+    for AggregateRollup<NUM_TX, C>
+{
+    #[tracing::instrument(target = "r1cs", skip(self, cs))]
+    fn generate_constraints(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+    ) -> Result<(), SynthesisError> {
+        self.rollup
+            .generate_constraints_with(cs, SignatureCheck::Aggregate(self.aggregate_signature))
+    }
+}
+
+impl<const NUM_TX: usize, C: MerkleConfigGadget, S: SigSchemeGadget> Rollup<NUM_TX, C, S> {
+    fn generate_constraints_with(
+        self,
+        cs: ConstraintSystemRef<ConstraintF>,
+        signature_check: SignatureCheck,
     ) -> Result<(), SynthesisError> {
         // Declare the parameters as constants.
-        let ledger_params = ParametersVar::new_constant(
+        let ledger_params = ParametersVar::<C>::new_constant(
             ark_relations::ns!(cs, "Ledger parameters"),
             &self.ledger_params,
         )?;
@@ -165,10 +501,31 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
         let final_root = AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || {
             self.final_root.ok_or(SynthesisError::AssignmentMissing)
         })?;
+        // Declare the digests of the deposits and of the exits as public inputs.
+        let deposits_digest =
+            BridgeDigestVar::new_input(ark_relations::ns!(cs, "Deposits digest"), || {
+                self.deposits_digest
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+        let exits_digest =
+            BridgeDigestVar::new_input(ark_relations::ns!(cs, "Exits digest"), || {
+                self.exits_digest.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+        // Declare the fee collector as a public input.
+        let fee_collector =
+            AccountIdVar::new_input(ark_relations::ns!(cs, "Fee collector"), || {
+                self.fee_collector.ok_or(SynthesisError::AssignmentMissing)
+            })?;
         let mut prev_root = initial_root;
+        let mut total_fees = AmountVar::new_constant(cs.clone(), Amount(0))?;
+        let mut computed_deposits_digest = BridgeDigestVar::constant(empty_digest());
+        let mut computed_exits_digest = BridgeDigestVar::constant(empty_digest());
+        // The slots of the aggregate signature, if any.
+        let mut signature_slots = Vec::with_capacity(NUM_TX);
 
         for i in 0..NUM_TX {
             let tx = self.transactions.as_ref().and_then(|t| t.get(i));
+            let is_noop = self.is_noop.as_ref().map(|t| t[i]);
 
             let sender_acc_info = self.sender_pre_tx_info_and_paths.as_ref().map(|t| t[i].0);
             let sender_pre_path = self.sender_pre_tx_info_and_paths.as_ref().map(|t| &t[i].1);
@@ -184,9 +541,10 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
 
             // Let's declare all these things!
 
-            let tx = TransactionVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
-                tx.ok_or(SynthesisError::AssignmentMissing)
-            })?;
+            let tx =
+                TransactionVar::<S>::new_witness(ark_relations::ns!(cs, "Transaction"), || {
+                    tx.ok_or(SynthesisError::AssignmentMissing)
+                })?;
             // Declare the sender's initial account balance...
             let sender_acc_info = AccountInformationVar::new_witness(
                 ark_relations::ns!(cs, "Sender Account Info"),
@@ -198,8 +556,10 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                     sender_pre_path.ok_or(SynthesisError::AssignmentMissing)
                 })?;
             // ... and authentication path after the update.
-            // TODO: Fill in the following
-            // let sender_post_path = ???
+            let sender_post_path =
+                AccPathVar::new_witness(ark_relations::ns!(cs, "Sender Post-Path"), || {
+                    sender_post_path.ok_or(SynthesisError::AssignmentMissing)
+                })?;
 
             // Declare the recipient's initial account balance...
             let recipient_acc_info = AccountInformationVar::new_witness(
@@ -213,8 +573,10 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                 })?;
 
             // ... and authentication path after the update.
-            // TODO: Fill in the following
-            // let recipient_post_path = ???
+            let recipient_post_path =
+                AccPathVar::new_witness(ark_relations::ns!(cs, "Recipient Post-Path"), || {
+                    recipient_post_path.ok_or(SynthesisError::AssignmentMissing)
+                })?;
 
             // Declare the state root before the transaction...
             let pre_tx_root =
@@ -227,31 +589,129 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
                     post_tx_root.ok_or(SynthesisError::AssignmentMissing)
                 })?;
 
+            // Declare whether this transaction is a no-op.
+            let is_noop = Boolean::new_witness(ark_relations::ns!(cs, "Is no-op"), || {
+                is_noop.ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
             // Enforce that the state root after the previous transaction equals
             // the starting state root for this transaction
-            // TODO: Write this
-
-            // Validate that the transaction signature and amount is correct.
-            // TODO: Uncomment this
-            // tx.validate(
-            //     &ledger_params,
-            //     &sender_acc_info,
-            //     &sender_pre_path,
-            //     &sender_post_path,
-            //     &recipient_acc_info,
-            //     &recipient_pre_path,
-            //     &recipient_post_path,
-            //     &pre_tx_root,
-            //     &post_tx_root,
-            // )?
-            // .enforce_equal(&Boolean::TRUE)?;
+            prev_root.enforce_equal(&pre_tx_root)?;
+
+            // A no-op leaves the state unchanged, and pays no fee.
+            post_tx_root.conditional_enforce_equal(&pre_tx_root, &is_noop)?;
+            tx.fee
+                .0
+                .conditional_enforce_equal(&UInt64::constant(0), &is_noop)?;
+
+            // Unless it is a no-op, validate that the transaction signature and amount
+            // is correct. An aggregate signature is verified after the whole batch.
+            let message =
+                tx.authorized_message(&sender_acc_info.public_key, &recipient_acc_info.public_key)?;
+            let sig_verifies = match signature_check {
+                SignatureCheck::Individual => {
+                    let signer_key = tx.signer_key(&sender_acc_info.public_key)?;
+                    tx.verify_signature(&ledger_params.sig_params, &signer_key, &message)?
+                }
+                SignatureCheck::Aggregate(_) => {
+                    signature_slots.push((
+                        tx.is_signed()?
+                            .and(&is_noop.not())?
+                            .and(&tx.is_multisig.not().or(&tx.approvals[0].0)?)?,
+                        tx.signer_key(&sender_acc_info.public_key)?,
+                        message,
+                    ));
+                    Boolean::TRUE
+                }
+            };
+            tx.validate_with_signature(
+                &sig_verifies,
+                &ledger_params,
+                &sender_acc_info,
+                &sender_pre_path,
+                &sender_post_path,
+                &recipient_acc_info,
+                &recipient_pre_path,
+                &recipient_post_path,
+                &pre_tx_root,
+                &post_tx_root,
+            )?
+            .or(&is_noop)?
+            .enforce_equal(&Boolean::TRUE)?;
+
+            // Accumulate the fee, to be credited after the whole batch.
+            total_fees = total_fees.checked_add(&tx.fee)?;
+
+            // Unless it is a no-op, add the transaction to the deposits or the exits.
+            let (deposits, exits) = absorb_transaction(
+                &ledger_params,
+                &tx,
+                &is_noop.not(),
+                &computed_deposits_digest,
+                &computed_exits_digest,
+            )?;
+            computed_deposits_digest = deposits;
+            computed_exits_digest = exits;
 
             // Set the root for the next transaction.
             prev_root = post_tx_root;
         }
+        // Declare the fee collector's account information after all transactions...
+        let fee_collector_acc_info = AccountInformationVar::new_witness(
+            ark_relations::ns!(cs, "Fee Collector Account Info"),
+            || {
+                self.fee_collector_pre_info_and_path
+                    .as_ref()
+                    .map(|t| t.0)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
+        // ..., corresponding authentication path, ...
+        let fee_collector_pre_path =
+            AccPathVar::new_witness(ark_relations::ns!(cs, "Fee Collector Pre-Path"), || {
+                self.fee_collector_pre_info_and_path
+                    .as_ref()
+                    .map(|t| &t.1)
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+        // ... and authentication path after crediting the fees.
+        let fee_collector_post_path =
+            AccPathVar::new_witness(ark_relations::ns!(cs, "Fee Collector Post-Path"), || {
+                self.fee_collector_post_path
+                    .as_ref()
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })?;
+
         // Check that the final root is consistent with the root computed after
-        // applying all state transitions
-        // TODO: implement this
+        // applying all state transitions and crediting the accumulated fees.
+        // Check that the digests commit to the deposits and exits of the batch.
+        computed_deposits_digest.enforce_equal(&deposits_digest)?;
+        computed_exits_digest.enforce_equal(&exits_digest)?;
+
+        if let SignatureCheck::Aggregate(aggregate_signature) = signature_check {
+            let aggregate_signature = AggregateSignatureVar::new_witness(
+                ark_relations::ns!(cs, "Aggregate signature"),
+                || aggregate_signature.ok_or(SynthesisError::AssignmentMissing),
+            )?;
+            aggregation::constraints::verify::<_, _, ChallengeRO, ChallengeROGadget>(
+                &ledger_params.sig_params,
+                &signature_slots,
+                &aggregate_signature,
+            )?
+            .enforce_equal(&Boolean::TRUE)?;
+        }
+
+        verify_fee_credit(
+            &ledger_params,
+            &fee_collector,
+            &fee_collector_acc_info,
+            &fee_collector_pre_path,
+            &fee_collector_post_path,
+            &prev_root,
+            &final_root,
+            &total_fees,
+        )?
+        .enforce_equal(&Boolean::TRUE)?;
         Ok(())
     }
 }
@@ -259,19 +719,17 @@ impl<const NUM_TX: usize> ConstraintSynthesizer<ConstraintF> for Rollup<NUM_TX>
 #[cfg(test)]
 mod test {
     use super::*;
-    use ark_relations::r1cs::{
-        ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
-    };
-    use ark_simple_payments::account::AccountId;
-    use ark_simple_payments::ledger::{Amount, Parameters, State};
-    use ark_simple_payments::transaction::Transaction;
-    use tracing_subscriber::layer::SubscriberExt;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_simple_payments::account::{AccountId, Nonce, RegistryKey};
+    use ark_simple_payments::bridge::{digests, empty_digest, Exit, SimulatedL1};
+    use ark_simple_payments::ledger::{Amount, Parameters, SigScheme, State};
+    use ark_simple_payments::signature::SignatureScheme;
+    use ark_simple_payments::transaction::{Transaction, TransactionKind};
 
     fn test_cs<const NUM_TX: usize>(rollup: Rollup<NUM_TX>) -> bool {
-        let mut layer = ConstraintLayer::default();
-        layer.mode = OnlyConstraints;
-        let subscriber = tracing_subscriber::Registry::default().with(layer);
-        let _guard = tracing::subscriber::set_default(subscriber);
+        // Tracing every constraint of a batch doubles the memory it takes, so this only
+        // reports the index of the unsatisfied constraint. Trace the constraints with a
+        // `ConstraintLayer` to find out where it comes from.
         let cs = ConstraintSystem::new_ref();
         rollup.generate_constraints(cs.clone()).unwrap();
         let result = cs.is_satisfied().unwrap();
@@ -293,16 +751,27 @@ mod test {
         state
             .update_balance(alice_id, Amount(20))
             .expect("Alice's account should exist");
+        state.set_fee_collector(alice_id).unwrap();
         // Let's make an account for Bob.
-        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
         let mut temp_state = state.clone();
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone()],
+            std::slice::from_ref(&tx1),
             &mut temp_state,
             true,
         )
@@ -310,12 +779,78 @@ mod test {
         assert!(test_cs(rollup));
 
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn large_tree_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new((1 << 20) - 1, &pp);
+        // Let's make accounts for Alice and Bob at the end of the tree...
+        state.next_available_account = Some(AccountId((1 << 20) - 2));
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // ...and one for Carol, whose identifier matches Bob's in the lowest byte.
+        state.next_available_account = Some(AccountId(bob_id.0 & 0xff));
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+
+        // Redirecting the payment to Carol invalidates the signature, since the
+        // whole identifier is signed, and the recipient's paths must lead to the leaf
+        // at that whole identifier.
+        let mut bad_tx = tx;
+        bad_tx.recipient = carol_id;
+        let mut temp_state = state.clone();
+        assert!(!bad_tx.validate(&pp, &temp_state));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&bad_tx),
             &mut temp_state,
             false,
         )
@@ -335,26 +870,49 @@ mod test {
         state
             .update_balance(alice_id, Amount(20))
             .expect("Alice's account should exist");
+        state.set_fee_collector(alice_id).unwrap();
         // Let's make an account for Bob.
-        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
         let mut temp_state = state.clone();
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(tx1.validate(&pp, &temp_state));
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone()],
+            std::slice::from_ref(&tx1),
             &mut temp_state,
             true,
         )
         .unwrap();
         assert!(test_cs(rollup));
 
+        // Alice sends Bob another 5 units in the same batch.
+        let tx2 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
         let mut temp_state = state.clone();
         let rollup = Rollup::<2>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone(), tx1.clone()],
+            &[tx1.clone(), tx2.clone()],
             &mut temp_state,
             true,
         )
@@ -376,12 +934,22 @@ mod test {
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(21), &alice_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(21),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
             &mut temp_state,
             false,
         )
@@ -390,12 +958,40 @@ mod test {
 
         // Next, let's try a transaction where the signature is incorrect:
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
         let rollup = Rollup::<1>::with_state_and_transactions(
             pp.clone(),
-            &[bad_tx.clone()],
+            std::slice::from_ref(&bad_tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // Next, let's try replaying a transaction within a batch:
+        let mut temp_state = state.clone();
+        assert!(Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx1.clone()],
+            &mut temp_state.clone(),
+            true,
+        )
+        .is_none());
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx1.clone()],
             &mut temp_state,
             false,
         )
@@ -403,10 +999,481 @@ mod test {
         assert!(!test_cs(rollup));
 
         // Finally, let's try a transaction to an non-existant account:
-        let bad_tx =
-            Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            AccountId(10),
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
+    }
+
+    #[test]
+    fn batch_fees_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Let's make an account for the operator, who collects the fees.
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        // Alice pays Bob 10 units with a fee of 1, and Bob pays 4 of them back with a fee of 2.
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(10),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let tx2 = Transaction::create(
+            &pp,
+            bob_id,
+            alice_id,
+            &alice_pk,
+            Amount(4),
+            Amount(2),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx2.clone()],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup));
+        let balance = |id| temp_state.id_to_account_info[&id].balance;
+        assert_eq!(balance(alice_id), Amount(13));
+        assert_eq!(balance(bob_id), Amount(4));
+        assert_eq!(balance(operator_id), Amount(3));
+
+        // The batch ends in the same state as applying the transactions one by one.
+        let mut sequential_state = state.clone();
+        sequential_state.apply_transaction(&pp, &tx1).unwrap();
+        sequential_state.apply_transaction(&pp, &tx2).unwrap();
+        assert_eq!(sequential_state.root(), temp_state.root());
+
+        // Claiming that the fees were never credited must not satisfy the circuit.
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx2.clone()],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.final_root = rollup.post_tx_roots.as_ref().map(|roots| roots[1]);
+        assert!(!test_cs(rollup));
+
+        // Nor must claiming that the fees were credited to somebody else's account.
+        let mut temp_state = state.clone();
+        let mut rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[tx1.clone(), tx2.clone()],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.fee_collector = Some(bob_id);
+        assert!(!test_cs(rollup));
+
+        // Alice cannot spend more than her balance once the fee is included.
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(20),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        assert!(!bad_tx.validate(&pp, &temp_state));
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            std::slice::from_ref(&bad_tx),
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn aggregate_rollup_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays Bob, someone deposits to Bob, and Bob pays Alice back, which leaves
+        // room for a no-op.
+        let transactions = [
+            Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(0),
+                Nonce(0),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create_deposit(bob_id, Amount(3)),
+            Transaction::create(
+                &pp,
+                bob_id,
+                alice_id,
+                &alice_pk,
+                Amount(7),
+                Amount(0),
+                Nonce(0),
+                &bob_sk,
+                &mut rng,
+            ),
+        ];
+        let rollup = AggregateRollup::<4>::with_state_and_padded_transactions(
+            pp.clone(),
+            &transactions,
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        // Each constraint system is dropped before the next one is built, since a batch of
+        // this size takes a lot of memory.
+        let is_satisfied_with = |rollup: &dyn Fn(ConstraintSystemRef<_>)| {
+            let cs = ConstraintSystem::new_ref();
+            rollup(cs.clone());
+            (cs.is_satisfied().unwrap(), cs.num_constraints())
+        };
+        let (is_satisfied, aggregate_constraints) =
+            is_satisfied_with(&|cs| rollup.clone().generate_constraints(cs).unwrap());
+        assert!(is_satisfied);
+        let (_, empty_constraints) = is_satisfied_with(&|cs| {
+            AggregateRollup::<4>::new_empty(pp.clone(), state.account_merkle_tree.height())
+                .generate_constraints(cs)
+                .unwrap()
+        });
+        assert_eq!(empty_constraints, aggregate_constraints);
+
+        // The aggregate must cover the signatures of this batch.
+        let mut bad_rollup = rollup.clone();
+        let mut bad_aggregate = rollup.aggregate_signature.clone().unwrap();
+        bad_aggregate.prover_commitments.swap(0, 2);
+        bad_rollup.aggregate_signature = Some(bad_aggregate);
+        let (is_satisfied, _) =
+            is_satisfied_with(&|cs| bad_rollup.clone().generate_constraints(cs).unwrap());
+        assert!(!is_satisfied);
+
+        // Compare against verifying each signature individually.
+        let (is_satisfied, individual_constraints) =
+            is_satisfied_with(&|cs| rollup.rollup.clone().generate_constraints(cs).unwrap());
+        assert!(is_satisfied);
+        println!(
+            "Rollup<4>: {} constraints with an aggregate signature, {} with individual signatures",
+            aggregate_constraints, individual_constraints
+        );
+    }
+
+    #[test]
+    fn padded_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        // Alice pays Bob 5 units with a fee of 1, in a batch with room for three transactions.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<3>::with_state_and_padded_transactions(
+            pp.clone(),
+            std::slice::from_ref(&tx),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(rollup.is_noop, Some(vec![false, true, true]));
+        let mut sequential_state = state.clone();
+        sequential_state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(rollup.final_root, Some(sequential_state.root()));
+
+        // The padded batch uses exactly the same constraints as the one used for setup,
+        // so the same keys work for it.
+        let num_constraints = |rollup: Rollup<3>| {
+            let cs = ConstraintSystem::new_ref();
+            rollup.generate_constraints(cs.clone()).unwrap();
+            cs.num_constraints()
+        };
+        let setup_constraints = num_constraints(Rollup::<3>::new_empty(
+            pp.clone(),
+            state.account_merkle_tree.height(),
+        ));
+        assert_eq!(num_constraints(rollup.clone()), setup_constraints);
+        assert!(test_cs(rollup.clone()));
+
+        // A batch without transactions consists of no-ops only.
+        let mut temp_state = state.clone();
+        let empty_rollup =
+            Rollup::<3>::with_state_and_padded_transactions(pp.clone(), &[], &mut temp_state, true)
+                .unwrap();
+        assert_eq!(empty_rollup.is_noop, Some(vec![true, true, true]));
+        assert_eq!(empty_rollup.final_root, Some(state.root()));
+        assert!(test_cs(empty_rollup));
+
+        // Batches cannot contain more than `NUM_TX` transactions.
+        assert!(Rollup::<1>::with_state_and_padded_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut state.clone(),
+            true,
+        )
+        .is_none());
+
+        // If a transaction is invalid, the transactions before it are not applied either.
+        let mut temp_state = state.clone();
+        assert!(Rollup::<3>::with_state_and_padded_transactions(
+            pp.clone(),
+            &[tx.clone(), tx.clone()],
+            &mut temp_state,
+            true,
+        )
+        .is_none());
+        assert_eq!(temp_state.root(), state.root());
+        assert_eq!(temp_state.id_to_account_info[&alice_id].balance, Amount(20));
+
+        // A real transaction cannot be passed off as a no-op...
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.is_noop = Some(vec![true, true, true]);
+        assert!(!test_cs(bad_rollup));
+
+        // ... and a no-op cannot change the state.
+        let mut bad_rollup = rollup;
+        if let Some(roots) = bad_rollup.post_tx_roots.as_mut() {
+            roots[1] = state.root();
+        }
+        if let Some(roots) = bad_rollup.pre_tx_roots.as_mut() {
+            roots[2] = state.root();
+        }
+        assert!(!test_cs(bad_rollup));
+    }
+
+    #[test]
+    fn bridge_batch_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(bob_id).unwrap();
+        let mut l1 = SimulatedL1::new(pp.clone());
+
+        // Alice deposits 10 units, pays 4 of them to Bob, and Bob withdraws 3.
+        l1.deposit(alice_id, Amount(10));
+        let deposit = l1.pending_deposits().next().unwrap().to_transaction();
+        let transfer = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(4),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            bob_id,
+            Amount(3),
+            Amount(0),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        );
+        let batch = [deposit, transfer, withdrawal];
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<4>::with_state_and_padded_transactions(
+            pp.clone(),
+            &batch,
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(temp_state.id_to_account_info[&alice_id].balance, Amount(6));
+        assert_eq!(temp_state.id_to_account_info[&bob_id].balance, Amount(1));
+        assert_eq!(
+            (
+                rollup.deposits_digest.unwrap(),
+                rollup.exits_digest.unwrap()
+            ),
+            digests(&pp, &batch)
+        );
+        assert!(test_cs(rollup.clone()));
+
+        // The batch must commit to its deposits and exits.
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.deposits_digest = Some(empty_digest());
+        assert!(!test_cs(bad_rollup));
+        let mut bad_rollup = rollup.clone();
+        bad_rollup.exits_digest = Some(empty_digest());
+        assert!(!test_cs(bad_rollup));
+
+        // The L1 credits the deposit, and pays out the exit.
+        l1.process_batch(
+            &rollup.deposits_digest.unwrap(),
+            &rollup.exits_digest.unwrap(),
+            &Exit::from_transactions(&batch),
+        )
+        .unwrap();
+        assert_eq!(l1.pending_deposits().count(), 0);
+        assert_eq!(
+            l1.paid_exits(),
+            &[Exit {
+                account: bob_id,
+                amount: Amount(3)
+            }]
+        );
+    }
+
+    #[test]
+    fn registration_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        // Rejected registrations create no account, so make sure that the fee collector exists.
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_pk, _bob_sk) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        // Bob registers, and Alice pays him in the same batch.
+        let registration = Transaction::create_registration(&state, bob_pk).unwrap();
+        let bob_id = registration.recipient;
+        let payment = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup = Rollup::<2>::with_state_and_transactions(
+            pp.clone(),
+            &[registration.clone(), payment],
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(temp_state.id_to_account_info[&bob_id].balance, Amount(5));
+        let mut sequential_state = state.clone();
+        sequential_state.register(bob_pk).unwrap();
+        assert_ne!(sequential_state.root(), state.root());
+        assert_eq!(
+            rollup.post_tx_roots.as_ref().unwrap()[0],
+            sequential_state.root()
+        );
+        assert!(test_cs(rollup));
+
+        // Registering a public key twice is rejected...
+        let duplicate = Transaction {
+            kind: TransactionKind::Registration(alice_pk),
+            sender: state.registry_predecessor(RegistryKey::of(&alice_pk)),
+            ..registration.clone()
+        };
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &[duplicate],
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // ... and so is overwriting an existing account, ...
+        let mut overwrite = registration.clone();
+        overwrite.recipient = alice_id;
+        let rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &[overwrite],
+            &mut state.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // ... or filling another empty leaf than the registered identifier's, ...
+        let mut later_state = state.clone();
+        later_state.next_available_account = Some(AccountId(bob_id.0 + 1));
+        let mut rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &[Transaction::create_registration(&later_state, bob_pk).unwrap()],
+            &mut later_state,
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup.clone()));
+        if let Some(transactions) = rollup.transactions.as_mut() {
+            transactions[0].recipient = bob_id;
+        }
+        assert!(!test_cs(rollup));
+
+        // ... or creating an account with a non-zero balance.
+        let mut rollup = Rollup::<1>::with_state_and_transactions(
+            pp.clone(),
+            &[registration],
+            &mut state.clone(),
+            true,
+        )
+        .unwrap();
+        assert!(test_cs(rollup.clone()));
+        if let Some(transactions) = rollup.transactions.as_mut() {
+            transactions[0].amount = Amount(5);
+        }
+        assert!(!test_cs(rollup));
     }
 
     // Builds a circuit with two txs, using different pubkeys & amounts every time.
@@ -423,8 +1490,9 @@ mod test {
         state
             .update_balance(alice_id, Amount(1000))
             .expect("Alice's account should exist");
+        state.set_fee_collector(alice_id).unwrap();
         // Let's make an account for Bob.
-        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         let amount_to_send = rng.gen_range(0..200);
 
@@ -434,18 +1502,31 @@ mod test {
             &pp,
             alice_id,
             bob_id,
+            &bob_pk,
             Amount(amount_to_send),
+            Amount(0),
+            Nonce(0),
             &alice_sk,
             &mut rng,
         );
-        let rollup = Rollup::<2>::with_state_and_transactions(
+        let tx2 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(amount_to_send),
+            Amount(0),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        Rollup::<2>::with_state_and_transactions(
             pp.clone(),
-            &[tx1.clone(), tx1.clone()],
+            &[tx1.clone(), tx2.clone()],
             &mut temp_state,
             true,
         )
-        .unwrap();
-        rollup
+        .unwrap()
     }
 
     #[test]
@@ -466,6 +1547,9 @@ mod test {
         let public_input = [
             circuit_to_verify_against.initial_root.unwrap(),
             circuit_to_verify_against.final_root.unwrap(),
+            circuit_to_verify_against.deposits_digest.unwrap(),
+            circuit_to_verify_against.exits_digest.unwrap(),
+            ConstraintF::from(circuit_to_verify_against.fee_collector.unwrap().0),
         ];
 
         let proof = Groth16::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
@@ -479,6 +1563,9 @@ mod test {
         let public_input = [
             circuit_to_verify_against.final_root.unwrap(),
             circuit_to_verify_against.final_root.unwrap(),
+            circuit_to_verify_against.deposits_digest.unwrap(),
+            circuit_to_verify_against.exits_digest.unwrap(),
+            ConstraintF::from(circuit_to_verify_against.fee_collector.unwrap().0),
         ];
 
         let proof = Groth16::prove(&pk, circuit_to_verify_against, &mut rng).unwrap();
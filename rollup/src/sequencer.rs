@@ -0,0 +1,447 @@
+use crate::ledger::{MerkleConfigGadget, SigSchemeGadget};
+use crate::prover::RollupSNARK;
+use crate::rollup::{apply_deferring_fee, Rollup};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_simple_payments::account::{AccountId, AccountPublicKey};
+use ark_simple_payments::bridge::{BridgeDigest, Deposit, Exit};
+use ark_simple_payments::ledger::{
+    AccRoot, LedgerError, MerkleConfig, Parameters, SigScheme, State, TransactionSigScheme,
+};
+use ark_simple_payments::transaction::Transaction;
+use ark_std::rand::{CryptoRng, RngCore};
+use blake2::Blake2s;
+use derivative::Derivative;
+use digest::Digest;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// The size of the header of a batch record: the payload length, followed by the
+/// Blake2s hash of the payload.
+const RECORD_HEADER_SIZE: usize = 4 + 32;
+
+/// The reasons for which a `Sequencer` operation can fail.
+#[derive(Debug)]
+pub enum SequencerError {
+    /// Reading or writing the batch file failed.
+    Io(io::Error),
+    /// A record could not be encoded or decoded.
+    Serialization(SerializationError),
+    /// The ledger rejected a transaction.
+    Ledger(LedgerError),
+    /// The batch could not be turned into a rollup circuit, e.g. because its fees
+    /// overflow the fee collector's balance.
+    InvalidBatch,
+    /// The SNARK failed to generate a proof.
+    Proving(Box<dyn std::error::Error>),
+}
+
+impl fmt::Display for SequencerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Serialization(e) => write!(f, "serialization error: {}", e),
+            Self::Ledger(e) => write!(f, "ledger error: {}", e),
+            Self::InvalidBatch => write!(f, "the batch cannot be rolled up"),
+            Self::Proving(e) => write!(f, "proving error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SequencerError {}
+
+impl From<io::Error> for SequencerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SerializationError> for SequencerError {
+    fn from(e: SerializationError) -> Self {
+        Self::Serialization(e)
+    }
+}
+
+impl From<LedgerError> for SequencerError {
+    fn from(e: LedgerError) -> Self {
+        Self::Ledger(e)
+    }
+}
+
+/// A proven batch of transactions signed with `S`, as written to disk by the `Sequencer`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = "P: Clone"), Debug(bound = "P: fmt::Debug"))]
+pub struct BatchRecord<P, S: TransactionSigScheme = SigScheme> {
+    /// The transactions of the batch, without the no-ops used for padding.
+    pub transactions: Vec<Transaction<S>>,
+    /// The ledger root before applying the batch.
+    pub initial_root: AccRoot,
+    /// The ledger root after applying the batch, and crediting its fees.
+    pub final_root: AccRoot,
+    /// The digest of the deposits that the batch credits.
+    pub deposits_digest: BridgeDigest,
+    /// The digest of the exits that the batch emits.
+    pub exits_digest: BridgeDigest,
+    /// The proof that the batch takes the ledger from `initial_root` to `final_root`.
+    pub proof: P,
+}
+
+impl<P, S: TransactionSigScheme> BatchRecord<P, S> {
+    /// The exits that the batch emits, to be paid out on the L1.
+    pub fn exits(&self) -> Vec<Exit> {
+        Exit::from_transactions(&self.transactions)
+    }
+}
+
+impl<P: CanonicalSerialize, S: TransactionSigScheme> CanonicalSerialize for BatchRecord<P, S> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.transactions.serialize(&mut writer)?;
+        self.initial_root.serialize(&mut writer)?;
+        self.final_root.serialize(&mut writer)?;
+        self.deposits_digest.serialize(&mut writer)?;
+        self.exits_digest.serialize(&mut writer)?;
+        self.proof.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.transactions.serialized_size()
+            + self.initial_root.serialized_size()
+            + self.final_root.serialized_size()
+            + self.deposits_digest.serialized_size()
+            + self.exits_digest.serialized_size()
+            + self.proof.serialized_size()
+    }
+}
+
+impl<P: CanonicalDeserialize, S: TransactionSigScheme> CanonicalDeserialize for BatchRecord<P, S> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Self {
+            transactions: Vec::deserialize(&mut reader)?,
+            initial_root: AccRoot::deserialize(&mut reader)?,
+            final_root: AccRoot::deserialize(&mut reader)?,
+            deposits_digest: BridgeDigest::deserialize(&mut reader)?,
+            exits_digest: BridgeDigest::deserialize(&mut reader)?,
+            proof: P::deserialize(&mut reader)?,
+        })
+    }
+}
+
+/// Collects transactions signed with `Sig` into batches of `NUM_TX`, proves each batch
+/// with `S`, and appends the proven batches to a file, from which `read_batches` can
+/// recover them.
+///
+/// Transactions are checked against the state left behind by the transactions that
+/// are already waiting in the mempool, so that every batch that is cut from the
+/// mempool is valid.
+pub struct Sequencer<
+    S: RollupSNARK,
+    const NUM_TX: usize,
+    C: MerkleConfigGadget = MerkleConfig,
+    Sig: SigSchemeGadget = SigScheme,
+> {
+    parameters: Parameters<C, Sig>,
+    /// The state after the last proven batch.
+    state: State<C>,
+    /// The state after also applying the transactions in the mempool, except for
+    /// their fees, which are only credited once a batch is cut.
+    pending_state: State<C>,
+    mempool: VecDeque<Transaction<Sig>>,
+    proving_key: S::ProvingKey,
+    batches: File,
+}
+
+impl<S: RollupSNARK, const NUM_TX: usize, C: MerkleConfigGadget, Sig: SigSchemeGadget>
+    Sequencer<S, NUM_TX, C, Sig>
+where
+    S::Proof: CanonicalSerialize,
+{
+    /// Create a sequencer whose ledger starts out as `state`, and which appends the
+    /// batches that it proves with `proving_key` to the file at `batches_path`.
+    /// `proving_key` must come from `RollupSNARK::setup_rollup::<_, C, Sig, NUM_TX>`.
+    /// Fails if the ledger has no fee collector, since batches credit their fees to it.
+    pub fn new(
+        parameters: Parameters<C, Sig>,
+        state: State<C>,
+        proving_key: S::ProvingKey,
+        batches_path: impl AsRef<Path>,
+    ) -> Result<Self, SequencerError> {
+        if state.fee_collector.is_none() {
+            return Err(LedgerError::MissingFeeCollector.into());
+        }
+        let batches = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(batches_path)?;
+        Ok(Self {
+            parameters,
+            pending_state: state.clone(),
+            state,
+            mempool: VecDeque::new(),
+            proving_key,
+            batches,
+        })
+    }
+
+    /// The state after the last proven batch.
+    pub fn state(&self) -> &State<C> {
+        &self.state
+    }
+
+    /// The transactions that have not been proven yet.
+    pub fn mempool(&self) -> impl Iterator<Item = &Transaction<Sig>> {
+        self.mempool.iter()
+    }
+
+    /// Add `tx` to the mempool, if it is valid after the transactions that are
+    /// already there. Deposits are rejected, since they must be submitted via
+    /// `submit_deposit`.
+    pub fn submit(&mut self, tx: Transaction<Sig>) -> Result<(), LedgerError> {
+        self.pending_state
+            .apply_transaction_deferring_fee(&self.parameters, &tx)?;
+        self.mempool.push_back(tx);
+        Ok(())
+    }
+
+    /// Add `deposit` to the mempool, so that a batch credits it. The L1 only accepts the
+    /// batch if its deposits are the next ones in the deposit queue, so deposits must be
+    /// submitted in the order of `SimulatedL1::pending_deposits`.
+    pub fn submit_deposit(&mut self, deposit: &Deposit) -> Result<(), LedgerError> {
+        self.pending_state.apply_deposit(deposit)?;
+        self.mempool.push_back(deposit.to_transaction());
+        Ok(())
+    }
+
+    /// Add the registration of `public_key` to the mempool, and return the identifier
+    /// of the new account. The registration is created against the state after the
+    /// transactions that are already in the mempool (see
+    /// `Transaction::create_registration`), so that it is proven as part of a batch.
+    pub fn register(&mut self, public_key: AccountPublicKey) -> Result<AccountId, LedgerError> {
+        let tx = Transaction::create_registration(&self.pending_state, public_key)?;
+        let id = tx.recipient;
+        self.submit(tx)?;
+        Ok(id)
+    }
+
+    /// If the mempool holds at least `NUM_TX` transactions, prove the oldest `NUM_TX`
+    /// of them as a batch, and write the batch to disk.
+    pub fn cut_batch<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Option<BatchRecord<S::Proof, Sig>>, SequencerError> {
+        if self.mempool.len() < NUM_TX {
+            return Ok(None);
+        }
+        self.prove_batch(NUM_TX, rng).map(Some)
+    }
+
+    /// Prove all transactions in the mempool, padding the last batch with no-ops if
+    /// needed, and write the batches to disk.
+    pub fn flush<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Vec<BatchRecord<S::Proof, Sig>>, SequencerError> {
+        let mut records = Vec::new();
+        while !self.mempool.is_empty() {
+            let batch_size = self.mempool.len().min(NUM_TX);
+            records.push(self.prove_batch(batch_size, rng)?);
+        }
+        Ok(records)
+    }
+
+    /// Prove the oldest `batch_size` transactions of the mempool.
+    fn prove_batch<R: RngCore + CryptoRng>(
+        &mut self,
+        batch_size: usize,
+        rng: &mut R,
+    ) -> Result<BatchRecord<S::Proof, Sig>, SequencerError> {
+        let transactions: Vec<_> = self.mempool.iter().take(batch_size).cloned().collect();
+        let mut new_state = self.state.clone();
+        let rollup = Rollup::<NUM_TX, C, Sig>::with_state_and_padded_transactions(
+            self.parameters.clone(),
+            &transactions,
+            &mut new_state,
+            true,
+        )
+        .ok_or(SequencerError::InvalidBatch)?;
+        let record = BatchRecord {
+            transactions,
+            initial_root: self.state.root(),
+            final_root: new_state.root(),
+            deposits_digest: rollup.deposits_digest.unwrap(),
+            exits_digest: rollup.exits_digest.unwrap(),
+            proof: S::prove(&self.proving_key, rollup, rng)
+                .map_err(|e| SequencerError::Proving(Box::new(e)))?,
+        };
+        self.append(&record)?;
+
+        // The fees of the batch are now credited, so recompute the state that the
+        // remaining transactions lead to. A transaction that is no longer valid (e.g.,
+        // because it relied on fees not yet being credited) is dropped.
+        self.state = new_state;
+        self.pending_state = self.state.clone();
+        let remaining = self.mempool.split_off(batch_size);
+        self.mempool.clear();
+        for tx in remaining {
+            if apply_deferring_fee(&self.parameters, &mut self.pending_state, &tx).is_ok() {
+                self.mempool.push_back(tx);
+            }
+        }
+        Ok(record)
+    }
+
+    /// Append `record` to the batch file, and flush it to disk.
+    fn append(&mut self, record: &BatchRecord<S::Proof, Sig>) -> Result<(), SequencerError> {
+        let mut payload = Vec::new();
+        record.serialize(&mut payload)?;
+        let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&Blake2s::digest(&payload));
+        bytes.extend_from_slice(&payload);
+        self.batches.write_all(&bytes)?;
+        self.batches.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Read the batches that a `Sequencer` wrote to the file at `path`, in order.
+/// A record that was only partially written when the sequencer crashed is ignored.
+pub fn read_batches<P: CanonicalDeserialize, S: TransactionSigScheme>(
+    path: impl AsRef<Path>,
+) -> Result<Vec<BatchRecord<P, S>>, SequencerError> {
+    let bytes = fs::read(path)?;
+    let mut records = Vec::new();
+    let mut rest = &bytes[..];
+    while let Some(header) = rest.get(..RECORD_HEADER_SIZE) {
+        let payload_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let payload = match rest.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload_len) {
+            Some(payload) if Blake2s::digest(payload)[..] == header[4..] => payload,
+            _ => break,
+        };
+        records.push(BatchRecord::deserialize(payload)?);
+        rest = &rest[RECORD_HEADER_SIZE + payload_len..];
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prover::Groth16;
+    use ark_simple_payments::account::Nonce;
+    use ark_simple_payments::ledger::{Amount, SigScheme};
+    use ark_simple_payments::signature::SignatureScheme;
+
+    #[test]
+    fn batches_are_proven_and_persisted() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        let tree_height = state.account_merkle_tree.height();
+        let (pk, vk) = Groth16::setup_rollup::<_, _, _, 2>(&pp, tree_height, &mut rng).unwrap();
+        let path = std::env::temp_dir().join("ark-rollup-sequencer-test");
+        let _ = fs::remove_file(&path);
+        let mut sequencer =
+            Sequencer::<Groth16, 2>::new(pp.clone(), state.clone(), pk, &path).unwrap();
+
+        let pay_bob = |nonce, rng: &mut _| {
+            Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(1),
+                Nonce(nonce),
+                &alice_sk,
+                rng,
+            )
+        };
+        sequencer.submit(pay_bob(0, &mut rng)).unwrap();
+        // Replays and transactions with a skipped nonce are rejected.
+        assert!(sequencer.submit(pay_bob(0, &mut rng)).is_err());
+        assert!(sequencer.submit(pay_bob(2, &mut rng)).is_err());
+        assert!(sequencer.cut_batch(&mut rng).unwrap().is_none());
+        // Carol registers, and her registration is proven like any other transaction.
+        let (carol_pk, _carol_sk) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let carol_id = sequencer.register(carol_pk).unwrap();
+        assert_eq!(carol_id, AccountId(4));
+        assert_eq!(
+            sequencer.register(carol_pk),
+            Err(LedgerError::DuplicatePublicKey)
+        );
+        sequencer.submit(pay_bob(1, &mut rng)).unwrap();
+        // Deposits only enter the mempool out of the L1 deposit queue.
+        let deposit = Deposit {
+            recipient: bob_id,
+            amount: Amount(3),
+        };
+        assert_eq!(
+            sequencer.submit(deposit.to_transaction()),
+            Err(LedgerError::SelfIssuedDeposit)
+        );
+        sequencer.submit_deposit(&deposit).unwrap();
+
+        // The first two transactions form a full batch, and the last two another one.
+        let first = sequencer.cut_batch(&mut rng).unwrap().unwrap();
+        assert_eq!(first.transactions.len(), 2);
+        assert_eq!(sequencer.mempool().count(), 2);
+        let rest = sequencer.flush(&mut rng).unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].transactions.len(), 2);
+        assert_eq!(sequencer.mempool().count(), 0);
+        assert_eq!(
+            sequencer.state().id_to_account_info[&bob_id].balance,
+            Amount(13)
+        );
+        assert_eq!(
+            sequencer.state().id_to_account_info[&operator_id].balance,
+            Amount(2)
+        );
+        assert_eq!(sequencer.state().pub_key_to_id[&carol_pk], carol_id);
+
+        // A separate process can read the batches back, and check them.
+        let records: Vec<BatchRecord<_>> = read_batches(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        for (record, expected) in records.iter().zip([first, rest[0].clone()]) {
+            assert_eq!(record.transactions.len(), expected.transactions.len());
+            assert_eq!(record.initial_root, expected.initial_root);
+            assert_eq!(record.final_root, expected.final_root);
+            assert_eq!(record.proof, expected.proof);
+        }
+        assert_eq!(records[0].initial_root, state.root());
+        assert_eq!(records[0].final_root, records[1].initial_root);
+        assert_eq!(records[1].final_root, sequencer.state().root());
+        for record in &records {
+            assert!(Groth16::verify_rollup(
+                &vk,
+                &record.initial_root,
+                &record.final_root,
+                &record.deposits_digest,
+                &record.exits_digest,
+                operator_id,
+                &record.proof
+            )
+            .unwrap());
+        }
+
+        // A torn record at the end of the file is ignored.
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, bytes).unwrap();
+        let records =
+            read_batches::<ark_groth16::Proof<ark_bls12_381::Bls12_381>, SigScheme>(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
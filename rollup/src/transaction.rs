@@ -1,59 +1,266 @@
-use crate::account::{AccountIdVar, AccountInformationVar, AccountPublicKeyVar};
-use crate::ledger::{self, AccPathVar, AccRootVar, AmountVar, ParametersVar};
+use crate::account::{
+    public_key_to_bytes_le, AccountIdVar, AccountInformationVar, AccountPublicKeyVar,
+    MultisigPolicyVar, NonceVar, PolicyDigestVar, RegistryKeyVar,
+};
+use crate::bridge::{absorb_transaction, BridgeDigestVar};
+use crate::ledger::{
+    self, AccPathVar, AccRootVar, AmountVar, MerkleConfigGadget, ParametersVar, SigSchemeGadget,
+    SignatureVar,
+};
+use crate::rollup::{
+    apply_deferring_fee, info_and_path, involved_accounts, placeholder_account_info,
+    placeholder_path, placeholder_transaction,
+};
 use crate::ConstraintF;
 use ark_ed_on_bls12_381::{constraints::EdwardsVar, EdwardsProjective};
+use ark_r1cs_std::bits::uint64::UInt64;
+use ark_r1cs_std::fields::fp::FpVar;
 use ark_r1cs_std::prelude::*;
 use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, Namespace, SynthesisError};
-use ark_simple_payments::account::AccountInformation;
-use ark_simple_payments::ledger::{AccPath, AccRoot, Parameters, State};
-use ark_simple_payments::signature::schnorr::constraints::{
-    ParametersVar as SchnorrParamsVar, SchnorrSignatureVerifyGadget, SignatureVar,
+use ark_simple_payments::account::{
+    AccountId, AccountInformation, AccountPublicKey, MultisigPolicy, MAX_SIGNERS,
 };
+use ark_simple_payments::bridge::{self, empty_digest, BridgeDigest};
+use ark_simple_payments::ledger::{AccPath, AccRoot, MerkleConfig, Parameters, SigScheme, State};
+use ark_simple_payments::signature::schnorr::constraints::ParametersVar as SchnorrParamsVar;
 use ark_simple_payments::signature::SigVerifyGadget;
-use ark_simple_payments::transaction::Transaction;
+use ark_simple_payments::transaction::{Transaction, TransactionKind};
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 
-/// Transaction transferring some amount from one account to another.
-pub struct TransactionVar {
+/// Transaction transferring some amount from one account to another, or between an
+/// account and the L1, or registering a new account, signed with `S`.
+pub struct TransactionVar<S: SigSchemeGadget = SigScheme> {
+    /// Whether the transaction is a deposit.
+    pub is_deposit: Boolean<ConstraintF>,
+    /// Whether the transaction is a withdrawal.
+    pub is_withdrawal: Boolean<ConstraintF>,
+    /// Whether the transaction is a registration.
+    pub is_registration: Boolean<ConstraintF>,
+    /// The public key that a registration registers, or the identity otherwise.
+    pub public_key: AccountPublicKeyVar,
     /// The account information of the sender.
     pub sender: AccountIdVar,
     /// The account information of the recipient.
     pub recipient: AccountIdVar,
     /// The amount being transferred from the sender to the receiver.
     pub amount: AmountVar,
+    /// The fee paid by the sender to the fee collector, on top of `amount`.
+    pub fee: AmountVar,
+    /// The sender's nonce at the time the transaction was created.
+    pub nonce: NonceVar,
     /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: SignatureVar<EdwardsProjective, EdwardsVar>,
+    /// the amount, the fee, the nonce, and the kind. For multisig transactions, this is
+    /// the approval of the first signer of `policy` (see `signer_key`).
+    pub signature: SignatureVar<S>,
+    /// Whether the transaction carries the approvals of a multisig policy.
+    pub is_multisig: Boolean<ConstraintF>,
+    /// The policy of the sender's multisig account, or an empty policy otherwise.
+    pub policy: MultisigPolicyVar,
+    /// For each public key of `policy`, whether its signer approved the transaction,
+    /// along with their signature. The first signature is `signature`.
+    pub approvals: Vec<(Boolean<ConstraintF>, SignatureVar<S>)>,
 }
 
-impl TransactionVar {
-    /// Verify just the signature in the transaction.
-    #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key))]
-    fn verify_signature(
+impl<S: SigSchemeGadget> TransactionVar<S> {
+    /// The message that authorizes the transaction, like `Transaction::signed_message`,
+    /// which consists of (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey
+    /// || Amount || Fee || Nonce || Kind). The public keys are `sender_public_key` and
+    /// `recipient_public_key`, except that withdrawals use `sender_public_key` twice.
+    pub fn authorized_message(
         &self,
-        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
-        pub_key: &AccountPublicKeyVar,
-    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
+        sender_public_key: &AccountPublicKeyVar,
+        recipient_public_key: &AccountPublicKeyVar,
+    ) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+        let recipient_public_key = AccountPublicKeyVar::conditionally_select(
+            &self.is_withdrawal,
+            sender_public_key,
+            recipient_public_key,
+        )?;
         let mut message = self.sender.to_bytes_le();
+        message.extend(public_key_to_bytes_le(sender_public_key)?);
         message.extend(self.recipient.to_bytes_le());
+        message.extend(public_key_to_bytes_le(&recipient_public_key)?);
         message.extend(self.amount.to_bytes_le());
-        SchnorrSignatureVerifyGadget::verify(pp, pub_key, &message, &self.signature)
+        message.extend(self.fee.to_bytes_le());
+        message.extend(self.nonce.to_bytes_le());
+        message.push(self.kind_to_byte()?);
+        Ok(message)
+    }
+
+    /// Whether the transaction needs a signature, i.e., whether it is a transfer or a
+    /// withdrawal.
+    pub fn is_signed(&self) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        Ok(self.is_deposit.or(&self.is_registration)?.not())
+    }
+
+    /// Verify just the signature in the transaction, over the signed message `message`
+    /// (see `authorized_message`). The salt of `pp` is the domain tag of the ledger's
+    /// chain (see `Parameters::domain_tag`), so signatures for other chains do not verify.
+    #[tracing::instrument(target = "r1cs", skip(self, pp, pub_key, message))]
+    pub(crate) fn verify_signature(
+        &self,
+        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
+        pub_key: &AccountPublicKeyVar,
+        message: &[UInt8<ConstraintF>],
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        <ledger::SigGadget<S> as SigVerifyGadget<S, _>>::verify(
+            pp,
+            pub_key,
+            message,
+            &self.signature,
+        )
+    }
+
+    /// The public key that `signature` must verify under: the sender's public key
+    /// `sender_public_key`, or the first public key of `policy` for multisig transactions.
+    /// Sharing `signature` between both cases saves a signature verification.
+    pub fn signer_key(
+        &self,
+        sender_public_key: &AccountPublicKeyVar,
+    ) -> Result<AccountPublicKeyVar, SynthesisError> {
+        AccountPublicKeyVar::conditionally_select(
+            &self.is_multisig,
+            &self.policy.public_keys[0],
+            sender_public_key,
+        )
     }
 
-    /// Check that the transaction is valid for the given ledger state. This checks
-    /// the following conditions:
+    /// Whether the approvals in the transaction satisfy `self.policy`: each approval
+    /// verifies under the public key of its signer, and there are at least as many
+    /// approvals as the policy's threshold. The first approval is `signature`, which is
+    /// taken to verify if `signature_verifies`. The approvals sign `message` (see
+    /// `authorized_message`). This does not check the policy itself.
+    #[tracing::instrument(target = "r1cs", skip(self, pp, message, signature_verifies))]
+    pub(crate) fn verify_approvals(
+        &self,
+        pp: &SchnorrParamsVar<EdwardsProjective, EdwardsVar>,
+        message: &[UInt8<ConstraintF>],
+        signature_verifies: &Boolean<ConstraintF>,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        let identity = AccountPublicKeyVar::new_constant(
+            ConstraintSystemRef::None,
+            AccountPublicKey::default(),
+        )?;
+        let mut approvals_verify = Boolean::TRUE;
+        let mut num_approvals = FpVar::zero();
+        for (i, ((is_approved, signature), public_key)) in self
+            .approvals
+            .iter()
+            .zip(&self.policy.public_keys)
+            .enumerate()
+        {
+            let verifies = if i == 0 {
+                signature_verifies.clone()
+            } else {
+                <ledger::SigGadget<S> as SigVerifyGadget<S, _>>::verify(
+                    pp, public_key, message, signature,
+                )?
+            };
+            // Anyone can sign under the identity, which only pads the policy.
+            let is_signer = public_key.is_eq(&identity)?.not();
+            approvals_verify =
+                approvals_verify.and(&verifies.and(&is_signer)?.or(&is_approved.not())?)?;
+            num_approvals += FpVar::from(is_approved.clone());
+        }
+        // Both numbers are far smaller than half of the field, so they can be compared as
+        // field elements.
+        let threshold = Boolean::le_bits_to_fp_var(&self.policy.threshold.to_bits_le()?)?;
+        let has_enough_approvals =
+            num_approvals.is_cmp_unchecked(&threshold, Ordering::Greater, true)?;
+        approvals_verify.and(&has_enough_approvals)
+    }
+
+    /// Encode the kind of the transaction as a byte, like `TransactionKind::to_byte`.
+    fn kind_to_byte(&self) -> Result<UInt8<ConstraintF>, SynthesisError> {
+        let mut bits = vec![
+            self.is_deposit.or(&self.is_registration)?,
+            self.is_withdrawal.or(&self.is_registration)?,
+        ];
+        bits.resize(8, Boolean::FALSE);
+        Ok(UInt8::from_bits_le(&bits))
+    }
+
+    /// Check that the transaction is valid for the given ledger state. For transfers
+    /// and withdrawals, this checks the following conditions:
     /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
+    ///    corresponding to `self.sender`. If the sender is a multisig account, verify
+    ///    instead that the transaction carries the account's policy, and valid approvals
+    ///    from at least as many of its signers as its threshold.
     /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
+    ///    the transaction amount and the fee.
+    /// 3. Verify that the recipient's account exists (only for transfers).
+    /// 4. Verify that the transaction's nonce equals the sender's current nonce.
+    ///
+    /// The paths of the sender and of the recipient must lead to the leaves at positions
+    /// `self.sender` and `self.recipient`, so that no other account can stand in for
+    /// them. The sender's leaf is updated first: the sender's pre-path leads to
+    /// `pre_root`, and its post-path, which must have the same siblings, leads to an
+    /// intermediate root. The recipient's pre-path leads to this intermediate root, and
+    /// its post-path, which must have the same siblings, leads to `post_root`. This way,
+    /// the transaction changes no leaves other than those of its accounts.
+    ///
+    /// Deposits are only checked to pay no fee, and to credit an existing account.
+    /// Deposits leave the sender's account unchanged, and withdrawals leave the
+    /// recipient's account unchanged, so the corresponding inputs are ignored.
+    ///
+    /// Registrations are checked to move no money, to fill the leaf of `self.recipient`,
+    /// which must be empty under `pre_root`, with a fresh account, and to insert the new public key into the
+    /// registry right after the key of the sender, which is the registration's
+    /// predecessor in the registry. Since the registry is sorted, this ensures that the
+    /// public key is not registered yet. Registrations are not signed, and leave the
+    /// predecessor's balance and nonce unchanged.
+    ///
+    /// The fee is only debited from the sender here; crediting it to the fee
+    /// collector is checked separately via `verify_fee_credit`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn validate<C: MerkleConfigGadget>(
+        &self,
+        parameters: &ledger::ParametersVar<C>,
+        pre_sender_acc_info: &AccountInformationVar,
+        pre_sender_path: &AccPathVar<C>,
+        post_sender_path: &AccPathVar<C>,
+        pre_recipient_acc_info: &AccountInformationVar,
+        pre_recipient_path: &AccPathVar<C>,
+        post_recipient_path: &AccPathVar<C>,
+        pre_root: &AccRootVar,
+        post_root: &AccRootVar,
+    ) -> Result<Boolean<ConstraintF>, SynthesisError> {
+        // Verify the signature against the sender pubkey (or the first key of the
+        // sender's multisig policy).
+        let message = self.authorized_message(
+            &pre_sender_acc_info.public_key,
+            &pre_recipient_acc_info.public_key,
+        )?;
+        let sig_verifies = self.verify_signature(
+            &parameters.sig_params,
+            &self.signer_key(&pre_sender_acc_info.public_key)?,
+            &message,
+        )?;
+        self.validate_with_signature(
+            &sig_verifies,
+            parameters,
+            pre_sender_acc_info,
+            pre_sender_path,
+            post_sender_path,
+            pre_recipient_acc_info,
+            pre_recipient_path,
+            post_recipient_path,
+            pre_root,
+            post_root,
+        )
+    }
+
+    /// Like `validate`, but takes whether `signature` is valid under `signer_key` as
+    /// `sig_verifies`, instead of verifying it. `AggregateRollup` verifies these
+    /// signatures all at once. The other approvals of multisig transactions are still
+    /// verified here.
     #[allow(clippy::too_many_arguments)]
     #[tracing::instrument(
         target = "r1cs",
         skip(
             self,
+            sig_verifies,
             parameters,
             pre_sender_acc_info,
             pre_sender_path,
@@ -65,154 +272,469 @@ impl TransactionVar {
             post_root
         )
     )]
-    pub fn validate(
+    pub fn validate_with_signature<C: MerkleConfigGadget>(
         &self,
-        parameters: &ledger::ParametersVar,
+        sig_verifies: &Boolean<ConstraintF>,
+        parameters: &ledger::ParametersVar<C>,
         pre_sender_acc_info: &AccountInformationVar,
-        pre_sender_path: &AccPathVar,
-        post_sender_path: &AccPathVar,
+        pre_sender_path: &AccPathVar<C>,
+        post_sender_path: &AccPathVar<C>,
         pre_recipient_acc_info: &AccountInformationVar,
-        pre_recipient_path: &AccPathVar,
-        post_recipient_path: &AccPathVar,
+        pre_recipient_path: &AccPathVar<C>,
+        post_recipient_path: &AccPathVar<C>,
         pre_root: &AccRootVar,
         post_root: &AccRootVar,
     ) -> Result<Boolean<ConstraintF>, SynthesisError> {
-        // Verify the signature against the sender pubkey.
-        // TODO: FILL IN THE BLANK
-        // let sig_verifies = ???;
+        // Check that the transaction carries the sender's current nonce.
+        let nonce_matches = self.nonce.0.is_eq(&pre_sender_acc_info.nonce.0)?;
 
-        // Compute the new sender balance.
+        // A transaction has at most one kind, and deposits and registrations cannot pay
+        // fees. Registrations cannot move money either.
+        let kind_is_valid = self
+            .is_deposit
+            .and(&self.is_withdrawal)?
+            .or(&self.is_deposit.and(&self.is_registration)?)?
+            .or(&self.is_withdrawal.and(&self.is_registration)?)?
+            .not();
+        let fee_is_valid = self
+            .fee
+            .0
+            .is_eq(&UInt64::constant(0))?
+            .or(&self.is_deposit.or(&self.is_registration)?.not())?;
+        let amount_is_valid = self
+            .amount
+            .0
+            .is_eq(&UInt64::constant(0))?
+            .or(&self.is_registration.not())?;
+
+        // Check that a registered public key is in the prime-order subgroup, which
+        // `TransactionSigScheme::batch_verify` assumes (see
+        // `Transaction::create_registration`), and that it is not the identity.
+        let identity = AccountPublicKeyVar::new_constant(
+            ConstraintSystemRef::None,
+            AccountPublicKey::default(),
+        )?;
+        let key_is_valid = self
+            .public_key
+            .is_in_prime_order_subgroup()?
+            .and(&self.public_key.is_eq(&identity)?.not())?
+            .or(&self.is_registration.not())?;
+
+        // Check that a registered public key fits between the key of its predecessor
+        // (the sender) and the next key in the registry.
+        let new_key = RegistryKeyVar::of(&self.public_key)?;
+        let predecessor_key = RegistryKeyVar::of(&pre_sender_acc_info.public_key)?;
+        let registry_order_is_valid = predecessor_key
+            .is_smaller_than(&new_key)?
+            .and(&new_key.is_smaller_than(&pre_sender_acc_info.next_key)?)?
+            .or(&self.is_registration.not())?;
+
+        // Compute the new sender balance, and bump the sender's nonce. Deposits do not
+        // debit the sender.
         let mut post_sender_acc_info = pre_sender_acc_info.clone();
-        // TODO: Safely subtract amount sent from the sender's balance
-        // post_sender_acc_info.balance = ???;
+        let amount_and_fee = self.amount.checked_add(&self.fee)?;
+        let debit = AmountVar(UInt64::conditionally_select(
+            &self.is_deposit,
+            &UInt64::constant(0),
+            &amount_and_fee.0,
+        )?);
+        post_sender_acc_info.balance = pre_sender_acc_info.balance.checked_sub(&debit)?;
+        post_sender_acc_info.nonce = NonceVar(UInt64::conditionally_select(
+            &self.is_registration,
+            &pre_sender_acc_info.nonce.0,
+            &pre_sender_acc_info.nonce.checked_increment()?.0,
+        )?);
+        // Registrations point the predecessor to the new key.
+        post_sender_acc_info.next_key = RegistryKeyVar(
+            new_key
+                .0
+                .iter()
+                .zip(&pre_sender_acc_info.next_key.0)
+                .map(|(new, old)| UInt8::conditionally_select(&self.is_registration, new, old))
+                .collect::<Result<_, _>>()?,
+        );
 
-        // TODO: Compute the new receiver balance, ensure its overflow safe.
+        // Compute the new receiver balance, ensure its overflow safe. Withdrawals do not
+        // credit the recipient.
         let mut post_recipient_acc_info = pre_recipient_acc_info.clone();
-        // post_recipient_acc_info.balance = ???
+        let credit = AmountVar(UInt64::conditionally_select(
+            &self.is_withdrawal,
+            &UInt64::constant(0),
+            &self.amount.0,
+        )?);
+        post_recipient_acc_info.balance = pre_recipient_acc_info.balance.checked_add(&credit)?;
+
+        // Registrations fill an empty leaf with a fresh account, which takes the place
+        // of its predecessor in the registry.
+        let registered_acc_info = AccountInformationVar {
+            public_key: self.public_key.clone(),
+            balance: AmountVar(UInt64::constant(0)),
+            nonce: NonceVar(UInt64::constant(0)),
+            next_key: pre_sender_acc_info.next_key.clone(),
+            policy: PolicyDigestVar::zero(),
+        };
+        let pre_recipient_leaf = pre_recipient_acc_info.to_bytes_le();
+        let empty_leaf = vec![UInt8::constant(0); pre_recipient_leaf.len()];
+        let pre_recipient_leaf =
+            select_bytes(&self.is_registration, &empty_leaf, &pre_recipient_leaf)?;
+        let post_recipient_leaf = select_bytes(
+            &self.is_registration,
+            &registered_acc_info.to_bytes_le(),
+            &post_recipient_acc_info.to_bytes_le(),
+        )?;
 
         // Check that the pre-tx sender account information is correct with
         // respect to `pre_tx_root`, and that the post-tx sender account
-        // information is correct with respect to `post_tx_root`.
-        // HINT: Use the path structs
-        // TODO: FILL IN THE FOLLOWING
-        // let sender_exists = ???
+        // information is correct with respect to the root after updating the sender,
+        // which only changes the sender's leaf.
+        let sender_exists = pre_sender_path.verify_membership(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            pre_root,
+            &pre_sender_acc_info.to_bytes_le().as_slice(),
+        )?;
 
-        // let sender_updated_correctly = ???
+        let sender_updated_correctly = post_sender_path.has_same_siblings(pre_sender_path)?;
+        let root_after_sender = post_sender_path.calculate_root(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &post_sender_acc_info.to_bytes_le().as_slice(),
+        )?;
+        // Deposits leave the sender's leaf unchanged.
+        let intermediate_root =
+            FpVar::conditionally_select(&self.is_deposit, pre_root, &root_after_sender)?;
 
         // Check that the pre-tx recipient account information is correct with
-        // respect to `pre_tx_root`, and that the post-tx recipient account
-        // information is correct with respect to `post_tx_root`.
-        // TODO: FILL IN THE FOLLOWING
-        // let recipient_exists = ???
-
-        // let recipient_updated_correctly = ???
-
-        // TODO: Uncomment the following
-        // sender_exists
-        //     .and(&sender_updated_correctly)?
-        //     .and(&recipient_exists)?
-        //     .and(&recipient_updated_correctly)?
-        //     .and(&sig_verifies)
-        Err(SynthesisError::Unsatisfiable)
+        // respect to the root after updating the sender, and that the post-tx
+        // recipient account information is correct with respect to `post_tx_root`,
+        // which only changes the recipient's leaf.
+        let recipient_exists = pre_recipient_path.verify_membership(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            &intermediate_root,
+            &pre_recipient_leaf.as_slice(),
+        )?;
+
+        let recipient_updated_correctly = post_recipient_path
+            .has_same_siblings(pre_recipient_path)?
+            .and(&post_recipient_path.verify_membership(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                post_root,
+                &post_recipient_leaf.as_slice(),
+            )?)?;
+        // Withdrawals leave the recipient's leaf unchanged.
+        let recipient_is_unchanged = self
+            .is_withdrawal
+            .and(&intermediate_root.is_eq(post_root)?)?;
+
+        // Transactions from multisig accounts must carry the account's policy and enough
+        // approvals, while those from other accounts must carry a valid signature.
+        let message = self.authorized_message(
+            &pre_sender_acc_info.public_key,
+            &pre_recipient_acc_info.public_key,
+        )?;
+        let policy_digest = pre_sender_acc_info.policy.to_field_element()?;
+        let approvals_are_valid = self
+            .policy
+            .digest::<C>(&parameters.leaf_crh_params)?
+            .is_eq(&policy_digest)?
+            .and(&self.verify_approvals(&parameters.sig_params, &message, sig_verifies)?)?;
+        let signature_is_valid = sig_verifies.and(&policy_digest.is_eq(&FpVar::zero())?)?;
+        let is_authorized = Boolean::conditionally_select(
+            &self.is_multisig,
+            &approvals_are_valid,
+            &signature_is_valid,
+        )?
+        .and(&nonce_matches)?
+        .or(&self.is_registration)?;
+        // The paths must lead to the leaves of the sender and of the recipient, and not
+        // to those of other accounts. The post-paths have the same positions as the
+        // pre-paths.
+        let sender_path_matches = self.sender.is_position_of(pre_sender_path)?;
+        let recipient_path_matches = self.recipient.is_position_of(pre_recipient_path)?;
+        let sender_is_valid = sender_exists
+            .and(&sender_updated_correctly)?
+            .and(&sender_path_matches)?
+            .and(&is_authorized)?
+            .or(&self.is_deposit)?;
+        let recipient_is_valid = recipient_exists
+            .and(&recipient_updated_correctly)?
+            .and(&recipient_path_matches)?
+            .or(&recipient_is_unchanged)?;
+        sender_is_valid
+            .and(&recipient_is_valid)?
+            .and(&kind_is_valid)?
+            .and(&fee_is_valid)?
+            .and(&amount_is_valid)?
+            .and(&key_is_valid)?
+            .and(&registry_order_is_valid)
     }
 }
 
-impl AllocVar<Transaction, ConstraintF> for TransactionVar {
+/// Select `true_bytes` if `cond` is true, and `false_bytes` otherwise.
+fn select_bytes(
+    cond: &Boolean<ConstraintF>,
+    true_bytes: &[UInt8<ConstraintF>],
+    false_bytes: &[UInt8<ConstraintF>],
+) -> Result<Vec<UInt8<ConstraintF>>, SynthesisError> {
+    true_bytes
+        .iter()
+        .zip(false_bytes)
+        .map(|(t, f)| UInt8::conditionally_select(cond, t, f))
+        .collect()
+}
+
+/// Check that `post_root` is obtained from `pre_root` by crediting `fees` to the fee
+/// collector `collector`, whose account information under `pre_root` is
+/// `pre_collector_acc_info`.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(
+    target = "r1cs",
+    skip(
+        parameters,
+        collector,
+        pre_collector_acc_info,
+        pre_collector_path,
+        post_collector_path,
+        pre_root,
+        post_root,
+        fees
+    )
+)]
+pub fn verify_fee_credit<C: MerkleConfigGadget>(
+    parameters: &ledger::ParametersVar<C>,
+    collector: &AccountIdVar,
+    pre_collector_acc_info: &AccountInformationVar,
+    pre_collector_path: &AccPathVar<C>,
+    post_collector_path: &AccPathVar<C>,
+    pre_root: &AccRootVar,
+    post_root: &AccRootVar,
+    fees: &AmountVar,
+) -> Result<Boolean<ConstraintF>, SynthesisError> {
+    let mut post_collector_acc_info = pre_collector_acc_info.clone();
+    post_collector_acc_info.balance = pre_collector_acc_info.balance.checked_add(fees)?;
+
+    let collector_exists = pre_collector_path.verify_membership(
+        &parameters.leaf_crh_params,
+        &parameters.two_to_one_crh_params,
+        pre_root,
+        &pre_collector_acc_info.to_bytes_le().as_slice(),
+    )?;
+
+    // Crediting the fees only changes the collector's leaf.
+    let collector_updated_correctly = post_collector_path
+        .has_same_siblings(pre_collector_path)?
+        .and(&post_collector_path.verify_membership(
+            &parameters.leaf_crh_params,
+            &parameters.two_to_one_crh_params,
+            post_root,
+            &post_collector_acc_info.to_bytes_le().as_slice(),
+        )?)?;
+
+    collector_exists
+        .and(&collector_updated_correctly)?
+        .and(&collector.is_position_of(pre_collector_path)?)
+}
+
+impl<S: SigSchemeGadget> AllocVar<Transaction<S>, ConstraintF> for TransactionVar<S> {
     #[tracing::instrument(target = "r1cs", skip(cs, f, mode))]
-    fn new_variable<T: Borrow<Transaction>>(
+    fn new_variable<T: Borrow<Transaction<S>>>(
         cs: impl Into<Namespace<ConstraintF>>,
         f: impl FnOnce() -> Result<T, SynthesisError>,
         mode: AllocationMode,
     ) -> Result<Self, SynthesisError> {
         let cs = cs.into();
         f().and_then(|tx| {
-            let tx: &Transaction = tx.borrow();
+            let tx: &Transaction<S> = tx.borrow();
+            let is_deposit = Boolean::new_variable(
+                cs.clone(),
+                || Ok(tx.kind == TransactionKind::Deposit),
+                mode,
+            )?;
+            let is_withdrawal = Boolean::new_variable(
+                cs.clone(),
+                || Ok(tx.kind == TransactionKind::Withdrawal),
+                mode,
+            )?;
+            let (is_registration, public_key) = match tx.kind {
+                TransactionKind::Registration(public_key) => (true, public_key),
+                _ => (false, Default::default()),
+            };
+            let is_registration = Boolean::new_variable(cs.clone(), || Ok(is_registration), mode)?;
+            let public_key =
+                AccountPublicKeyVar::new_variable(cs.clone(), || Ok(public_key), mode)?;
             let sender = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.sender), mode)?;
             let recipient = AccountIdVar::new_variable(cs.clone(), || Ok(&tx.recipient), mode)?;
             let amount = AmountVar::new_variable(cs.clone(), || Ok(&tx.amount), mode)?;
-            let signature = SignatureVar::new_variable(cs.clone(), || Ok(&tx.signature), mode)?;
+            let fee = AmountVar::new_variable(cs.clone(), || Ok(&tx.fee), mode)?;
+            let nonce = NonceVar::new_variable(cs.clone(), || Ok(&tx.nonce), mode)?;
+            let is_multisig =
+                Boolean::new_variable(cs.clone(), || Ok(tx.multisig.is_some()), mode)?;
+            let (policy, mut signatures) = match &tx.multisig {
+                Some(approval) => (approval.policy.clone(), approval.signatures.clone()),
+                None => (MultisigPolicy::default(), vec![Some(tx.signature.clone())]),
+            };
+            let policy = MultisigPolicyVar::new_variable(cs.clone(), || Ok(&policy), mode)?;
+            signatures.resize(MAX_SIGNERS, None);
+            let approvals = signatures
+                .into_iter()
+                .map(|signature| {
+                    let is_approved =
+                        Boolean::new_variable(cs.clone(), || Ok(signature.is_some()), mode)?;
+                    let signature = SignatureVar::<S>::new_variable(
+                        cs.clone(),
+                        || Ok(signature.unwrap_or_default()),
+                        mode,
+                    )?;
+                    Ok((is_approved, signature))
+                })
+                .collect::<Result<Vec<_>, SynthesisError>>()?;
+            let signature = approvals[0].1.clone();
             Ok(Self {
+                is_deposit,
+                is_withdrawal,
+                is_registration,
+                public_key,
                 sender,
                 recipient,
                 amount,
+                fee,
+                nonce,
                 signature,
+                is_multisig,
+                policy,
+                approvals,
             })
         })
     }
 }
 
-pub struct UnaryRollup {
+pub struct UnaryRollup<C: MerkleConfigGadget = MerkleConfig, S: SigSchemeGadget = SigScheme> {
     /// The ledger parameters.
-    pub ledger_params: Parameters,
+    pub ledger_params: Parameters<C, S>,
     /// The Merkle tree root before applying this batch of transactions.
     pub initial_root: AccRoot,
     /// The Merkle tree root after applying this batch of transactions.
     pub final_root: AccRoot,
+    /// The digest of the deposit made by the transaction, if it is one.
+    pub deposits_digest: BridgeDigest,
+    /// The digest of the exit emitted by the transaction, if it is a withdrawal.
+    pub exits_digest: BridgeDigest,
     /// The current batch of transactions.
-    pub transaction: Transaction,
+    pub transaction: Transaction<S>,
     /// The sender's account information *before* applying the transaction.
     pub sender_acc_info: AccountInformation,
     /// The sender's authentication path, *before* applying the transaction.
-    pub sender_pre_path: AccPath,
-    /// The authentication path corresponding to the sender's account information *after* applying
-    /// the transactions.
-    pub sender_post_path: AccPath,
+    pub sender_pre_path: AccPath<C>,
+    /// The authentication path corresponding to the sender's account information *after*
+    /// updating the sender, but *before* updating the recipient.
+    pub sender_post_path: AccPath<C>,
     /// The recipient's account information *before* applying the transaction.
     pub recv_acc_info: AccountInformation,
-    /// The recipient's authentication path, *before* applying the transaction.
-    pub recv_pre_path: AccPath,
+    /// The recipient's authentication path, *after* updating the sender but *before*
+    /// updating the recipient.
+    pub recv_pre_path: AccPath<C>,
     /// The authentication path corresponding to the recipient's account information *after*
     /// applying the transactions.
-    pub recv_post_path: AccPath,
+    pub recv_post_path: AccPath<C>,
+    /// The Merkle tree root after applying the transaction, but before crediting its fee.
+    pub post_tx_root: AccRoot,
+    /// The account that the fee is credited to.
+    pub fee_collector: AccountId,
+    /// The fee collector's account information before crediting the fee.
+    pub fee_collector_acc_info: AccountInformation,
+    /// The fee collector's authentication path, before crediting the fee.
+    pub fee_collector_pre_path: AccPath<C>,
+    /// The fee collector's authentication path, after crediting the fee.
+    pub fee_collector_post_path: AccPath<C>,
 }
 
-impl UnaryRollup {
-    pub fn with_state_and_transaction(
-        ledger_params: Parameters,
-        transaction: Transaction,
-        state: &mut State,
-        validate: bool,
-    ) -> Option<UnaryRollup> {
-        if validate && !transaction.validate(&ledger_params, &*state) {
-            return None;
+impl<C: MerkleConfigGadget, S: SigSchemeGadget> UnaryRollup<C, S> {
+    /// Create a rollup with placeholder values for all inputs and witnesses, for a
+    /// ledger whose account Merkle tree has height `tree_height` (as returned by
+    /// `AccMerkleTree::height`). Since the values do not form a valid transaction, this
+    /// is only useful to determine the shape of the circuit, e.g. during SNARK setup.
+    pub fn new_empty(ledger_params: Parameters<C, S>, tree_height: usize) -> Self {
+        let path = placeholder_path(tree_height);
+        Self {
+            ledger_params,
+            initial_root: AccRoot::default(),
+            final_root: AccRoot::default(),
+            deposits_digest: empty_digest(),
+            exits_digest: empty_digest(),
+            transaction: placeholder_transaction(),
+            sender_acc_info: placeholder_account_info(),
+            sender_pre_path: path.clone(),
+            sender_post_path: path.clone(),
+            recv_acc_info: placeholder_account_info(),
+            recv_pre_path: path.clone(),
+            recv_post_path: path.clone(),
+            post_tx_root: AccRoot::default(),
+            fee_collector: AccountId(0),
+            fee_collector_acc_info: placeholder_account_info(),
+            fee_collector_pre_path: path.clone(),
+            fee_collector_post_path: path,
         }
+    }
 
-        let initial_root = state.root();
+    /// Create a rollup that applies `transaction` to `state`, and then credits its fee to
+    /// the fee collector of `state`. Returns `None`, and leaves `state` unchanged, if the
+    /// ledger has no fee collector, or if `validate` is true and the transaction is
+    /// invalid.
+    pub fn with_state_and_transaction(
+        ledger_params: Parameters<C, S>,
+        transaction: Transaction<S>,
+        state: &mut State<C>,
+        validate: bool,
+    ) -> Option<Self> {
+        // As in `Rollup::with_state_and_padded_transactions`, `state` is only replaced
+        // once the transaction has been applied in full.
+        let mut new_state = state.clone();
+        let fee_collector = new_state.fee_collector?;
+        let initial_root = new_state.root();
+        let (deposits_digest, exits_digest) =
+            bridge::digests(&ledger_params, std::slice::from_ref(&transaction));
         let sender_id = transaction.sender;
         let recipient_id = transaction.recipient;
+        let (sender_involved, recipient_involved) = involved_accounts(&transaction);
 
-        let sender_acc_info = *state.id_to_account_info.get(&sender_id)?;
-        let sender_pre_path = state
-            .account_merkle_tree
-            .generate_proof(sender_id.0 as usize)
-            .unwrap();
-
-        let recv_acc_info = *state.id_to_account_info.get(&recipient_id)?;
-        let recv_pre_path = state
-            .account_merkle_tree
-            .generate_proof(recipient_id.0 as usize)
-            .unwrap();
+        let (sender_acc_info, sender_pre_path) =
+            info_and_path(&new_state, sender_id, sender_involved)?;
+        let (recv_acc_info, _) = info_and_path(&new_state, recipient_id, recipient_involved)?;
 
-        if validate {
-            state.apply_transaction(&ledger_params, &transaction)?;
-        } else {
-            let _ = state.apply_transaction(&ledger_params, &transaction);
+        let applied = apply_deferring_fee(&ledger_params, &mut new_state, &transaction).is_ok();
+        if validate && !applied {
+            return None;
         }
 
-        let final_root = state.root();
-        let sender_post_path = state
+        let post_tx_root = new_state.root();
+        // The sender's leaf is updated before the recipient's (see
+        // `Rollup::with_state_and_padded_transactions`).
+        let sender_post_path = sender_pre_path.clone();
+        let (_, recv_post_path) = info_and_path(&new_state, recipient_id, recipient_involved)?;
+        let recv_pre_path = recv_post_path.clone();
+
+        let fee_collector_acc_info = *new_state.id_to_account_info.get(&fee_collector)?;
+        let fee_collector_pre_path = new_state
             .account_merkle_tree
-            .generate_proof(sender_id.0 as usize)
+            .generate_proof(fee_collector.leaf_index())
             .unwrap();
-        let recv_post_path = state
+        if applied {
+            new_state.credit_fees(transaction.fee).ok()?;
+        }
+        let final_root = new_state.root();
+        let fee_collector_post_path = new_state
             .account_merkle_tree
-            .generate_proof(recipient_id.0 as usize)
+            .generate_proof(fee_collector.leaf_index())
             .unwrap();
 
-        Some(UnaryRollup {
+        let rollup = Self {
             ledger_params,
             initial_root,
             final_root,
+            deposits_digest,
+            exits_digest,
             transaction,
             sender_acc_info,
             sender_pre_path,
@@ -220,17 +742,26 @@ impl UnaryRollup {
             recv_acc_info,
             recv_pre_path,
             recv_post_path,
-        })
+            post_tx_root,
+            fee_collector,
+            fee_collector_acc_info,
+            fee_collector_pre_path,
+            fee_collector_post_path,
+        };
+        *state = new_state;
+        Some(rollup)
     }
 }
 
-impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
+impl<C: MerkleConfigGadget, S: SigSchemeGadget> ConstraintSynthesizer<ConstraintF>
+    for UnaryRollup<C, S>
+{
     fn generate_constraints(
         self,
         cs: ConstraintSystemRef<ConstraintF>,
     ) -> Result<(), SynthesisError> {
         // Declare the parameters as constants.
-        let ledger_params = ParametersVar::new_constant(
+        let ledger_params = ParametersVar::<C>::new_constant(
             ark_relations::ns!(cs, "Ledger parameters"),
             &self.ledger_params,
         )?;
@@ -241,9 +772,23 @@ impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
         // Declare the final root as a public input.
         let final_root =
             AccRootVar::new_input(ark_relations::ns!(cs, "Final root"), || Ok(self.final_root))?;
+        // Declare the digests of the deposit and of the exit as public inputs.
+        let deposits_digest =
+            BridgeDigestVar::new_input(ark_relations::ns!(cs, "Deposits digest"), || {
+                Ok(self.deposits_digest)
+            })?;
+        let exits_digest =
+            BridgeDigestVar::new_input(ark_relations::ns!(cs, "Exits digest"), || {
+                Ok(self.exits_digest)
+            })?;
+        // Declare the fee collector as a public input.
+        let fee_collector =
+            AccountIdVar::new_input(ark_relations::ns!(cs, "Fee collector"), || {
+                Ok(self.fee_collector)
+            })?;
 
         // Declare transaction as a witness.
-        let tx = TransactionVar::new_witness(ark_relations::ns!(cs, "Transaction"), || {
+        let tx = TransactionVar::<S>::new_witness(ark_relations::ns!(cs, "Transaction"), || {
             Ok(self.transaction.clone())
         })?;
 
@@ -279,6 +824,11 @@ impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
                 Ok(self.recv_post_path.clone())
             })?;
 
+        // Declare the state root after the transaction, but before crediting the fee.
+        let post_tx_root = AccRootVar::new_witness(ark_relations::ns!(cs, "Post-tx Root"), || {
+            Ok(self.post_tx_root)
+        })?;
+
         // Validate that the transaction signature and amount is correct.
         tx.validate(
             &ledger_params,
@@ -289,7 +839,46 @@ impl ConstraintSynthesizer<ConstraintF> for UnaryRollup {
             &recipient_pre_path,
             &recipient_post_path,
             &initial_root,
+            &post_tx_root,
+        )?
+        .enforce_equal(&Boolean::TRUE)?;
+
+        // Check that the digests commit to the deposit or to the exit.
+        let empty_digest = BridgeDigestVar::constant(empty_digest());
+        let (computed_deposits_digest, computed_exits_digest) = absorb_transaction(
+            &ledger_params,
+            &tx,
+            &Boolean::TRUE,
+            &empty_digest,
+            &empty_digest,
+        )?;
+        computed_deposits_digest.enforce_equal(&deposits_digest)?;
+        computed_exits_digest.enforce_equal(&exits_digest)?;
+
+        // Declare the fee collector's account information and authentication paths...
+        let fee_collector_acc_info = AccountInformationVar::new_witness(
+            ark_relations::ns!(cs, "Fee Collector Account Info"),
+            || Ok(self.fee_collector_acc_info),
+        )?;
+        let fee_collector_pre_path =
+            AccPathVar::new_witness(ark_relations::ns!(cs, "Fee Collector Pre-Path"), || {
+                Ok(self.fee_collector_pre_path.clone())
+            })?;
+        let fee_collector_post_path =
+            AccPathVar::new_witness(ark_relations::ns!(cs, "Fee Collector Post-Path"), || {
+                Ok(self.fee_collector_post_path.clone())
+            })?;
+
+        // ... and check that the final root is obtained by crediting the fee.
+        verify_fee_credit(
+            &ledger_params,
+            &fee_collector,
+            &fee_collector_acc_info,
+            &fee_collector_pre_path,
+            &fee_collector_post_path,
+            &post_tx_root,
             &final_root,
+            &tx.fee,
         )?
         .enforce_equal(&Boolean::TRUE)
     }
@@ -301,11 +890,15 @@ mod test {
     use ark_relations::r1cs::{
         ConstraintLayer, ConstraintSynthesizer, ConstraintSystem, TracingMode::OnlyConstraints,
     };
-    use ark_simple_payments::ledger::{Amount, Parameters, State};
-    use ark_simple_payments::transaction::Transaction;
+    use ark_simple_payments::account::Nonce;
+    use ark_simple_payments::bridge::{empty_digest, extend_digest};
+    use ark_simple_payments::ledger::{
+        Amount, EdDSASigScheme, MerkleConfig, Parameters, PoseidonMerkleConfig, State,
+    };
+    use ark_simple_payments::transaction::{Transaction, TransactionKind};
     use tracing_subscriber::layer::SubscriberExt;
 
-    fn test_cs(rollup: UnaryRollup) -> bool {
+    fn test_cs<C: MerkleConfigGadget, S: SigSchemeGadget>(rollup: UnaryRollup<C, S>) -> bool {
         let mut layer = ConstraintLayer::default();
         layer.mode = OnlyConstraints;
         let subscriber = tracing_subscriber::Registry::default().with(layer);
@@ -331,25 +924,624 @@ mod test {
         state
             .update_balance(alice_id, Amount(20))
             .expect("Alice's account should exist");
+        state.set_fee_collector(alice_id).unwrap();
         // Let's make an account for Bob.
-        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
 
         // Alice wants to transfer 5 units to Bob.
         let mut temp_state = state.clone();
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(tx1.validate(&pp, &temp_state));
         let rollup =
-            UnaryRollup::with_state_and_transaction(pp.clone(), tx1, &mut temp_state, true)
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx1.clone(), &mut temp_state, true)
                 .unwrap();
         assert!(test_cs(rollup));
 
         let mut temp_state = state.clone();
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &bob_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &temp_state));
-        assert!(matches!(temp_state.apply_transaction(&pp, &bad_tx), None));
+        assert!(temp_state.apply_transaction(&pp, &bad_tx).is_err());
         let rollup =
             UnaryRollup::with_state_and_transaction(pp.clone(), bad_tx, &mut temp_state, false)
                 .unwrap();
         assert!(!test_cs(rollup));
+
+        // Replaying an applied transaction must not satisfy the circuit either.
+        let mut temp_state = state.clone();
+        temp_state.apply_transaction(&pp, &tx1).unwrap();
+        assert!(!tx1.validate(&pp, &temp_state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx1, &mut temp_state, false)
+                .unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_poseidon_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<PoseidonMerkleConfig>::sample_with_schemes(0, &mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut state, true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // Replaying the transaction does not satisfy the circuit.
+        let rollup = UnaryRollup::with_state_and_transaction(pp, tx, &mut state, false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_eddsa_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<MerkleConfig, EdDSASigScheme>::sample_with_schemes(0, &mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let create = |signer_sk, rng: &mut _| {
+            Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(0),
+                Nonce(0),
+                signer_sk,
+                rng,
+            )
+        };
+        let tx = create(&alice_sk, &mut rng);
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // A transaction that Bob signs on Alice's behalf does not satisfy the circuit.
+        let bad_tx = create(&bob_sk, &mut rng);
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), bad_tx, &mut state, false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn authorized_message_matches_signed_message() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let policy = MultisigPolicy::new(1, vec![alice_pk, bob_pk]).unwrap();
+        let treasury_id = state.register_multisig(&policy).unwrap();
+
+        let transactions = [
+            Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(1),
+                Nonce(2),
+                &alice_sk,
+                &mut rng,
+            ),
+            Transaction::create_withdrawal(
+                &pp,
+                bob_id,
+                Amount(3),
+                Amount(0),
+                Nonce(0),
+                &bob_sk,
+                &mut rng,
+            ),
+            Transaction::create_multisig(
+                &pp,
+                TransactionKind::Transfer,
+                treasury_id,
+                alice_id,
+                &alice_pk,
+                Amount(4),
+                Amount(0),
+                Nonce(1),
+                &policy,
+                &[&bob_sk],
+                &mut rng,
+            ),
+        ];
+        let cs = ConstraintSystem::new_ref();
+        for tx in &transactions {
+            let tx_var = TransactionVar::new_witness(cs.clone(), || Ok(tx)).unwrap();
+            let sender_pk = state.id_to_account_info[&tx.sender].public_key;
+            // Withdrawals ignore the recipient's public key.
+            let recipient_pk = match tx.kind {
+                TransactionKind::Withdrawal => alice_pk,
+                _ => state.id_to_account_info[&tx.recipient].public_key,
+            };
+            let sender_pk = AccountPublicKeyVar::new_witness(cs.clone(), || Ok(sender_pk)).unwrap();
+            let recipient_pk =
+                AccountPublicKeyVar::new_witness(cs.clone(), || Ok(recipient_pk)).unwrap();
+            let message = tx_var
+                .authorized_message(&sender_pk, &recipient_pk)
+                .unwrap()
+                .value()
+                .unwrap();
+            assert_eq!(Some(message), tx.signed_message(&state));
+        }
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn unary_rollup_binds_recipient_key() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        // On a fork of the ledger, the account that Bob has gets another public key.
+        let mut fork = state.clone();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            fork.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(bob_id, carol_id);
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut temp_state, true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // Replaying the transaction on the fork pays Carol, whose key Alice did not sign.
+        assert!(!tx.validate(&pp, &fork));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut fork, false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_rejects_other_chains() {
+        // Both ledgers have the same hash parameters and accounts, but other chain IDs.
+        let pp = Parameters::sample_for_chain(1, &mut ark_std::test_rng());
+        let other_pp = Parameters::sample_for_chain(2, &mut ark_std::test_rng());
+        let mut rng = ark_std::test_rng();
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut temp_state, true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // Replaying the transaction on the other chain does not satisfy its circuit.
+        let mut temp_state = state.clone();
+        assert!(!tx.validate(&other_pp, &temp_state));
+        let rollup =
+            UnaryRollup::with_state_and_transaction(other_pp, tx, &mut temp_state, false).unwrap();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_rejects_extra_credits() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, _carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut temp_state, true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // Claim a post-tx root that also credits 100 units to Carol, with the post-paths
+        // of the sender and of the recipient under that root.
+        let mut temp_state = state.clone();
+        let mut rollup =
+            UnaryRollup::with_state_and_transaction(pp, tx, &mut temp_state, true).unwrap();
+        temp_state.update_balance(carol_id, Amount(100)).unwrap();
+        let path = |id: AccountId| {
+            temp_state
+                .account_merkle_tree
+                .generate_proof(id.leaf_index())
+                .unwrap()
+        };
+        rollup.sender_post_path = path(alice_id);
+        rollup.recv_post_path = path(bob_id);
+        rollup.post_tx_root = temp_state.root();
+        rollup.fee_collector_acc_info = temp_state.id_to_account_info[&alice_id];
+        rollup.fee_collector_pre_path = path(alice_id);
+        rollup.fee_collector_post_path = path(alice_id);
+        rollup.final_root = temp_state.root();
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_rejects_low_order_registration() {
+        use ark_ed_on_bls12_381::{EdwardsAffine, Fq};
+        use ark_ff::{One, Zero};
+        use ark_simple_payments::account::{AccountInformation, PolicyDigest, RegistryKey};
+        use ark_simple_payments::ledger::SigScheme;
+        use ark_simple_payments::signature::SignatureScheme;
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let (public_key, _) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+
+        let tx = Transaction::create_registration(&state, public_key).unwrap();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), true)
+                .unwrap();
+        assert!(test_cs(rollup));
+
+        // The ledger refuses to register keys outside the prime-order subgroup, so build
+        // the state that registering `bad_key` would lead to by hand.
+        let forge_registration = |bad_key: AccountPublicKey| {
+            let key = RegistryKey::of(&bad_key);
+            let sender = state.registry_predecessor(key);
+            let recipient = state.next_available_account.unwrap();
+            let predecessor_info = state.leaf_info(sender).unwrap();
+            let registered_info = AccountInformation {
+                public_key: bad_key,
+                balance: Amount(0),
+                nonce: Nonce(0),
+                next_key: predecessor_info.next_key,
+                policy: PolicyDigest::default(),
+            };
+            let mut forged_state = state.clone();
+            for (id, info) in [
+                (
+                    sender,
+                    AccountInformation {
+                        next_key: key,
+                        ..predecessor_info
+                    },
+                ),
+                (recipient, registered_info),
+            ] {
+                forged_state
+                    .account_merkle_tree
+                    .update(id.leaf_index(), &info.to_bytes_le())
+                    .unwrap();
+                forged_state.id_to_account_info.insert(id, info);
+            }
+            let path = |id: AccountId| {
+                forged_state
+                    .account_merkle_tree
+                    .generate_proof(id.leaf_index())
+                    .unwrap()
+            };
+
+            let tx = Transaction {
+                kind: TransactionKind::Registration(bad_key),
+                sender,
+                recipient,
+                ..placeholder_transaction()
+            };
+            let mut rollup =
+                UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut state.clone(), false)
+                    .unwrap();
+            rollup.recv_pre_path = path(recipient);
+            rollup.recv_post_path = path(recipient);
+            rollup.post_tx_root = forged_state.root();
+            rollup.fee_collector_acc_info = forged_state.id_to_account_info[&alice_id];
+            rollup.fee_collector_pre_path = path(alice_id);
+            rollup.fee_collector_post_path = path(alice_id);
+            rollup.final_root = forged_state.root();
+            rollup
+        };
+        // (0, -1) has order 2, so adding it takes the key out of the prime-order subgroup.
+        let torsion = EdwardsAffine::new(Fq::zero(), -Fq::one());
+        let bad_key = public_key + torsion;
+        assert!(bad_key.is_on_curve());
+        assert!(!bad_key.is_in_correct_subgroup_assuming_on_curve());
+        assert!(!test_cs(forge_registration(bad_key)));
+        // Neither the order-2 point itself nor the identity can be registered either.
+        assert!(!test_cs(forge_registration(torsion)));
+        assert!(!test_cs(forge_registration(EdwardsAffine::zero())));
+    }
+
+    #[test]
+    fn unary_rollup_multisig_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(bob_id).unwrap();
+        // Three signers share a treasury, which needs two of their approvals.
+        let signers = (0..3)
+            .map(|_| state.sample_keys_and_register(&pp, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let public_keys = signers.iter().map(|(_, pk, _)| *pk).collect();
+        let policy = MultisigPolicy::new(2, public_keys).unwrap();
+        let treasury_id = state.register_multisig(&policy).unwrap();
+        state.update_balance(treasury_id, Amount(20)).unwrap();
+
+        let transfer = |policy: &MultisigPolicy, signers: &[&_], rng: &mut _| {
+            Transaction::create_multisig(
+                &pp,
+                TransactionKind::Transfer,
+                treasury_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(0),
+                Nonce(0),
+                policy,
+                signers,
+                rng,
+            )
+        };
+        let proves = |tx: Transaction| {
+            let mut temp_state = state.clone();
+            let is_valid = tx.validate(&pp, &temp_state);
+            let rollup =
+                UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut temp_state, false)
+                    .unwrap();
+            assert_eq!(test_cs(rollup), is_valid);
+            is_valid
+        };
+        assert!(proves(transfer(
+            &policy,
+            &[&signers[0].2, &signers[2].2],
+            &mut rng
+        )));
+        // One approval is not enough.
+        assert!(!proves(transfer(&policy, &[&signers[1].2], &mut rng)));
+        // Every approval must verify.
+        let mut bad_tx = transfer(&policy, &[&signers[0].2, &signers[1].2], &mut rng);
+        let approval = bad_tx.multisig.as_mut().unwrap();
+        approval.signatures[1] = approval.signatures[0].clone();
+        assert!(!proves(bad_tx));
+        // The transaction must carry the account's policy.
+        let other_policy = MultisigPolicy::new(1, vec![signers[0].1]).unwrap();
+        assert!(!proves(transfer(&other_policy, &[&signers[0].2], &mut rng)));
+    }
+
+    #[test]
+    fn unary_rollup_fee_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Let's make an account for the operator, who collects the fees.
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        // Alice transfers 5 units to Bob, and pays a fee of 2 units.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(2),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx.clone(), &mut temp_state, true)
+                .unwrap();
+        assert_eq!(
+            temp_state.id_to_account_info[&operator_id].balance,
+            Amount(2)
+        );
+        assert!(test_cs(rollup));
+
+        // Claiming that the fee was never credited must not satisfy the circuit.
+        let mut temp_state = state.clone();
+        let mut rollup =
+            UnaryRollup::with_state_and_transaction(pp.clone(), tx, &mut temp_state, true).unwrap();
+        rollup.final_root = rollup.post_tx_root;
+        assert!(!test_cs(rollup));
+    }
+
+    #[test]
+    fn unary_rollup_bridge_test() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(operator_id).unwrap();
+
+        // Bob deposits 7 units.
+        let deposit = Transaction::create_deposit(bob_id, Amount(7));
+        let mut temp_state = state.clone();
+        let rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            deposit.clone(),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(temp_state.id_to_account_info[&bob_id].balance, Amount(7));
+        assert_eq!(
+            rollup.deposits_digest,
+            extend_digest(&pp, &empty_digest(), bob_id, Amount(7))
+        );
+        assert_eq!(rollup.exits_digest, empty_digest());
+        assert!(test_cs(rollup));
+
+        // The deposit must be committed to in the deposits digest.
+        let mut temp_state = state.clone();
+        let mut rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            deposit.clone(),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.deposits_digest = empty_digest();
+        assert!(!test_cs(rollup));
+
+        // Bob's deposit cannot be credited to Alice's account instead.
+        let mut temp_state = state.clone();
+        let mut rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            Transaction::create_deposit(alice_id, Amount(7)),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        rollup.transaction = deposit.clone();
+        rollup.deposits_digest = extend_digest(&pp, &empty_digest(), bob_id, Amount(7));
+        assert!(!test_cs(rollup));
+
+        // Deposits cannot pay fees.
+        let mut bad_deposit = deposit;
+        bad_deposit.fee = Amount(1);
+        let mut temp_state = state.clone();
+        assert!(!bad_deposit.validate(&pp, &temp_state));
+        let rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            bad_deposit,
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        assert!(!test_cs(rollup));
+
+        // Alice withdraws 3 units, and pays a fee of 1 unit.
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            Amount(3),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut temp_state = state.clone();
+        let rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            withdrawal.clone(),
+            &mut temp_state,
+            true,
+        )
+        .unwrap();
+        assert_eq!(temp_state.id_to_account_info[&alice_id].balance, Amount(16));
+        assert_eq!(rollup.deposits_digest, empty_digest());
+        assert_eq!(
+            rollup.exits_digest,
+            extend_digest(&pp, &empty_digest(), alice_id, Amount(3))
+        );
+        assert!(test_cs(rollup));
+
+        // The signature covers the kind of the transaction, so a withdrawal cannot be
+        // turned into a deposit.
+        let mut bad_withdrawal = withdrawal;
+        bad_withdrawal.kind = TransactionKind::Deposit;
+        bad_withdrawal.fee = Amount(0);
+        let mut temp_state = state.clone();
+        let mut rollup = UnaryRollup::with_state_and_transaction(
+            pp.clone(),
+            bad_withdrawal,
+            &mut temp_state,
+            false,
+        )
+        .unwrap();
+        rollup.transaction.kind = TransactionKind::Withdrawal;
+        assert!(!test_cs(rollup));
     }
 }
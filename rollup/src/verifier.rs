@@ -0,0 +1,239 @@
+use crate::prover::{public_inputs, RollupSNARK};
+use crate::sequencer::BatchRecord;
+use ark_simple_payments::account::AccountId;
+use ark_simple_payments::bridge::BridgeDigest;
+use ark_simple_payments::ledger::{AccRoot, TransactionSigScheme};
+use std::fmt;
+
+/// The reasons for which a `RollupVerifier` rejects a submission.
+#[derive(Debug)]
+pub enum VerifierError {
+    /// The submission builds on a root other than the current one, e.g. because it is
+    /// out of order, or because it belongs to a fork of the ledger.
+    UnexpectedInitialRoot,
+    /// The proof does not show a valid transition from the current root to the new one.
+    InvalidProof,
+    /// The SNARK failed to check the proof.
+    Verification(Box<dyn std::error::Error>),
+}
+
+impl fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedInitialRoot => {
+                write!(f, "the batch does not start from the current root")
+            }
+            Self::InvalidProof => write!(f, "the proof is invalid"),
+            Self::Verification(e) => write!(f, "verification error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VerifierError {}
+
+/// Follows a rollup while only knowing its latest root, like a contract on the L1
+/// would. Each submission consists of a new root and a proof that a valid batch of
+/// transactions leads from the current root to the new one. The verifier advances to
+/// the new root if the proof is valid, and rejects the submission otherwise.
+///
+/// The verifier also knows the account of the rollup's operator, to which the fees of
+/// every batch must be credited.
+pub struct RollupVerifier<S: RollupSNARK> {
+    processed_vk: S::ProcessedVerifyingKey,
+    root: AccRoot,
+    fee_collector: AccountId,
+    num_batches: u64,
+}
+
+impl<S: RollupSNARK> RollupVerifier<S> {
+    /// Create a verifier that starts at `initial_root`, and accepts batches that are
+    /// proven for the verifying key `vk`, and that credit their fees to `fee_collector`.
+    pub fn new(
+        vk: &S::VerifyingKey,
+        initial_root: AccRoot,
+        fee_collector: AccountId,
+    ) -> Result<Self, VerifierError> {
+        let processed_vk =
+            S::process_vk(vk).map_err(|e| VerifierError::Verification(Box::new(e)))?;
+        Ok(Self {
+            processed_vk,
+            root: initial_root,
+            fee_collector,
+            num_batches: 0,
+        })
+    }
+
+    /// The current root.
+    pub fn root(&self) -> AccRoot {
+        self.root
+    }
+
+    /// The number of batches accepted so far.
+    pub fn num_batches(&self) -> u64 {
+        self.num_batches
+    }
+
+    /// Advance to `final_root` if `proof` shows that a valid batch leads from the
+    /// current root to `final_root`, while crediting the deposits committed to by
+    /// `deposits_digest`, emitting the exits committed to by `exits_digest`, and
+    /// crediting the fees to the fee collector.
+    pub fn submit(
+        &mut self,
+        final_root: AccRoot,
+        deposits_digest: &BridgeDigest,
+        exits_digest: &BridgeDigest,
+        proof: &S::Proof,
+    ) -> Result<(), VerifierError> {
+        let inputs = public_inputs(
+            &self.root,
+            &final_root,
+            deposits_digest,
+            exits_digest,
+            self.fee_collector,
+        );
+        let valid = S::verify_with_processed_vk(&self.processed_vk, &inputs, proof)
+            .map_err(|e| VerifierError::Verification(Box::new(e)))?;
+        if !valid {
+            return Err(VerifierError::InvalidProof);
+        }
+        self.root = final_root;
+        self.num_batches += 1;
+        Ok(())
+    }
+
+    /// Like `submit`, but for a batch written by a `Sequencer`. This first checks that
+    /// the batch starts from the current root.
+    pub fn submit_batch<Sig: TransactionSigScheme>(
+        &mut self,
+        record: &BatchRecord<S::Proof, Sig>,
+    ) -> Result<(), VerifierError> {
+        if record.initial_root != self.root {
+            return Err(VerifierError::UnexpectedInitialRoot);
+        }
+        self.submit(
+            record.final_root,
+            &record.deposits_digest,
+            &record.exits_digest,
+            &record.proof,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prover::Groth16;
+    use crate::rollup::Rollup;
+    use ark_simple_payments::account::Nonce;
+    use ark_simple_payments::bridge::empty_digest;
+    use ark_simple_payments::ledger::{Amount, Parameters, State};
+    use ark_simple_payments::transaction::Transaction;
+    use ark_snark::SNARK;
+
+    #[test]
+    fn follows_the_chain_of_roots() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(20)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, carol_pk, _carol_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.set_fee_collector(alice_id).unwrap();
+        let tree_height = state.account_merkle_tree.height();
+        let (pk, vk) = Groth16::setup_rollup::<_, _, _, 1>(&pp, tree_height, &mut rng).unwrap();
+
+        // Prove a batch that applies `tx` to `state`.
+        let mut prove = |tx: Transaction, state: &mut State| -> BatchRecord<_> {
+            let initial_root = state.root();
+            let rollup =
+                Rollup::<1>::with_state_and_transactions(pp.clone(), &[tx], state, true).unwrap();
+            let proof = Groth16::prove(&pk, rollup, &mut rng).unwrap();
+            BatchRecord {
+                transactions: vec![],
+                initial_root,
+                final_root: state.root(),
+                deposits_digest: empty_digest(),
+                exits_digest: empty_digest(),
+                proof,
+            }
+        };
+        let pay = |recipient, recipient_pk, nonce, rng: &mut _| {
+            Transaction::create(
+                &pp,
+                alice_id,
+                recipient,
+                recipient_pk,
+                Amount(5),
+                Amount(0),
+                Nonce(nonce),
+                &alice_sk,
+                rng,
+            )
+        };
+
+        // Alice pays Bob twice, but on a fork of the ledger, she pays Carol instead.
+        let genesis_root = state.root();
+        let mut fork = state.clone();
+        let first = prove(
+            pay(bob_id, &bob_pk, 0, &mut ark_std::test_rng()),
+            &mut state,
+        );
+        let second = prove(
+            pay(bob_id, &bob_pk, 1, &mut ark_std::test_rng()),
+            &mut state,
+        );
+        let forked = prove(
+            pay(carol_id, &carol_pk, 0, &mut ark_std::test_rng()),
+            &mut fork,
+        );
+
+        let empty = empty_digest();
+        let mut verifier = RollupVerifier::<Groth16>::new(&vk, genesis_root, alice_id).unwrap();
+        // Batches that are submitted out of order are rejected...
+        assert!(matches!(
+            verifier.submit_batch(&second),
+            Err(VerifierError::UnexpectedInitialRoot)
+        ));
+        assert!(matches!(
+            verifier.submit(second.final_root, &empty, &empty, &second.proof),
+            Err(VerifierError::InvalidProof)
+        ));
+        assert_eq!(verifier.root(), genesis_root);
+
+        // ... and are accepted once their predecessor is.
+        verifier.submit_batch(&first).unwrap();
+        assert_eq!(verifier.root(), first.final_root);
+        // A proof for a different root is rejected.
+        assert!(matches!(
+            verifier.submit(forked.final_root, &empty, &empty, &second.proof),
+            Err(VerifierError::InvalidProof)
+        ));
+        verifier
+            .submit(second.final_root, &empty, &empty, &second.proof)
+            .unwrap();
+        assert_eq!(verifier.root(), state.root());
+        assert_eq!(verifier.num_batches(), 2);
+
+        // Once the verifier has followed one branch, the fork is rejected.
+        assert!(matches!(
+            verifier.submit_batch(&forked),
+            Err(VerifierError::UnexpectedInitialRoot)
+        ));
+        assert!(matches!(
+            verifier.submit(forked.final_root, &empty, &empty, &forked.proof),
+            Err(VerifierError::InvalidProof)
+        ));
+        // A verifier that starts at the genesis root accepts the fork, though.
+        let mut fork_verifier =
+            RollupVerifier::<Groth16>::new(&vk, genesis_root, alice_id).unwrap();
+        fork_verifier.submit_batch(&forked).unwrap();
+        assert_eq!(fork_verifier.root(), fork.root());
+        assert!(matches!(
+            fork_verifier.submit_batch(&second),
+            Err(VerifierError::UnexpectedInitialRoot)
+        ));
+    }
+}
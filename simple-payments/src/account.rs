@@ -1,20 +1,51 @@
 use crate::ledger::*;
 use crate::signature::schnorr;
-use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_crypto_primitives::crh::CRH;
+use ark_ed_on_bls12_381::{EdwardsProjective, Fq};
+use ark_ff::{PrimeField, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
 
 /// Account public key used to verify transaction signatures.
 pub type AccountPublicKey = schnorr::PublicKey<EdwardsProjective>;
 /// Account secret key used to create transaction signatures.
 pub type AccountSecretKey = schnorr::SecretKey<EdwardsProjective>;
 
-/// Account identifier. This prototype supports only 256 accounts at a time.
+/// Convert a public key to bytes, which are the bytes of its x-coordinate. Public keys lie
+/// in the prime-order subgroup, where the x-coordinate determines the point.
+pub fn public_key_to_bytes_le(public_key: &AccountPublicKey) -> Vec<u8> {
+    ark_ff::to_bytes![public_key.x].unwrap()
+}
+
+/// Account identifier. Identifier 0 is never assigned, so a ledger holds at most
+/// 2^32 - 1 accounts.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Ord, PartialOrd, Debug)]
-pub struct AccountId(pub u8);
+pub struct AccountId(pub u32);
 
 impl AccountId {
     /// Convert the account identifier to bytes.
     pub fn to_bytes_le(&self) -> Vec<u8> {
-        vec![self.0]
+        self.0.to_le_bytes().to_vec()
+    }
+
+    /// The index of the account's leaf in the account Merkle tree.
+    pub fn leaf_index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl CanonicalSerialize for AccountId {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for AccountId {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        u32::deserialize(reader).map(Self)
     }
 }
 
@@ -25,18 +56,300 @@ impl AccountId {
     }
 }
 
+/// The number of transactions an account has sent so far. A transaction is only
+/// valid if it carries the sender's current nonce, which prevents replays.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug, Default)]
+pub struct Nonce(pub u64);
+
+impl Nonce {
+    /// Convert the nonce to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        self.0.to_le_bytes().to_vec()
+    }
+
+    /// Return the next nonce, or `None` if the nonce space is exhausted.
+    pub fn checked_increment(self) -> Option<Self> {
+        self.0.checked_add(1).map(Self)
+    }
+}
+
+impl CanonicalSerialize for Nonce {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for Nonce {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        u64::deserialize(reader).map(Self)
+    }
+}
+
+/// The position of a public key in the registry of all registered public keys, which
+/// is sorted by these keys. The key of a public key consists of the low 248 bits of its
+/// x-coordinate, so that keys fit in a field element and can be compared in-circuit.
+///
+/// Since the top bits are dropped, two public keys whose x-coordinates agree on their low
+/// 248 bits have the same key, and only the first of them to register can do so; the
+/// second one is rejected with `LedgerError::DuplicatePublicKey`. Finding such a pair
+/// takes about 2^124 key generations, and finding a key that collides with a given one
+/// about 2^248.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug, Default)]
+pub struct RegistryKey(pub Fq);
+
+impl RegistryKey {
+    /// The number of bytes that determine a key.
+    pub const NUM_BYTES: usize = 31;
+
+    /// The key of `public_key`.
+    pub fn of(public_key: &AccountPublicKey) -> Self {
+        let x_bytes = ark_ff::to_bytes![public_key.x].unwrap();
+        Self(Fq::from_le_bytes_mod_order(&x_bytes[..Self::NUM_BYTES]))
+    }
+
+    /// A key that is larger than the key of every public key that can be registered.
+    pub fn max() -> Self {
+        Self(Fq::from_le_bytes_mod_order(&[u8::MAX; Self::NUM_BYTES]))
+    }
+
+    /// Convert the key to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        ark_ff::to_bytes![self.0].unwrap()
+    }
+}
+
+impl CanonicalSerialize for RegistryKey {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for RegistryKey {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        let key = Fq::deserialize(reader)?;
+        if key > Self::max().0 {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(Self(key))
+    }
+}
+
+/// The largest number of public keys in a `MultisigPolicy`. The rollup circuit checks
+/// an approval for each of them in every transaction, so this is kept small.
+pub const MAX_SIGNERS: usize = 3;
+
+/// The authorization policy of a multisig account: transfers and withdrawals from the
+/// account must be approved by at least `threshold` of `public_keys`.
+#[derive(Hash, Eq, PartialEq, Clone, Debug, Default)]
+pub struct MultisigPolicy {
+    /// The number of approvals that a transaction needs.
+    pub threshold: u8,
+    /// The public keys of the signers.
+    pub public_keys: Vec<AccountPublicKey>,
+}
+
+impl MultisigPolicy {
+    /// Create a policy that requires `threshold` approvals among `public_keys`. Fails
+    /// unless `1 <= threshold <= public_keys.len() <= MAX_SIGNERS`, and the public keys
+    /// are distinct, not the identity, and in the prime-order subgroup.
+    pub fn new(threshold: u8, public_keys: Vec<AccountPublicKey>) -> Result<Self, LedgerError> {
+        let policy = Self {
+            threshold,
+            public_keys,
+        };
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// Check the conditions of `MultisigPolicy::new`.
+    pub fn check(&self) -> Result<(), LedgerError> {
+        let num_keys = self.public_keys.len();
+        let keys_are_distinct = self
+            .public_keys
+            .iter()
+            .enumerate()
+            .all(|(i, key)| !key.is_zero() && !self.public_keys[..i].contains(key));
+        let keys_are_valid = self
+            .public_keys
+            .iter()
+            .all(|key| key.is_in_correct_subgroup_assuming_on_curve());
+        if self.threshold == 0
+            || usize::from(self.threshold) > num_keys
+            || num_keys > MAX_SIGNERS
+            || !keys_are_distinct
+            || !keys_are_valid
+        {
+            return Err(LedgerError::InvalidPolicy);
+        }
+        Ok(())
+    }
+
+    /// Convert the policy to bytes: the threshold, followed by each public key (see
+    /// `public_key_to_bytes_le`), where missing keys are padded with the identity up to
+    /// `MAX_SIGNERS` keys. The identity (the only point whose x-coordinate is 0) is never
+    /// a signer.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut bytes = vec![self.threshold];
+        for i in 0..MAX_SIGNERS {
+            let key = self.public_keys.get(i).copied().unwrap_or_default();
+            bytes.extend(public_key_to_bytes_le(&key));
+        }
+        bytes
+    }
+
+    /// The digest that the leaf of an account with this policy commits to, which is the
+    /// leaf hash of the account Merkle tree (hashed according to `C`) applied to
+    /// `to_bytes_le`.
+    pub fn digest<C: AccountMerkleConfig>(
+        &self,
+        leaf_crh_params: &<C::LeafHash as CRH>::Parameters,
+    ) -> PolicyDigest {
+        PolicyDigest(C::LeafHash::evaluate(leaf_crh_params, &self.to_bytes_le()).unwrap())
+    }
+
+    /// The public key of an account with this policy, which is the sum of the signers'
+    /// public keys. It keeps the account in the registry of public keys, but it does not
+    /// authorize transactions on its own.
+    pub fn account_key(&self) -> AccountPublicKey {
+        self.public_keys
+            .iter()
+            .fold(AccountPublicKey::zero(), |sum, key| sum + key)
+    }
+}
+
+impl CanonicalSerialize for MultisigPolicy {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.threshold.serialize(&mut writer)?;
+        self.public_keys.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.threshold.serialized_size() + self.public_keys.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for MultisigPolicy {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let threshold = u8::deserialize(&mut reader)?;
+        let public_keys = Vec::<AccountPublicKey>::deserialize(&mut reader)?;
+        Self::new(threshold, public_keys).map_err(|_| SerializationError::InvalidData)
+    }
+}
+
+/// The digest of the multisig policy of an account (see `MultisigPolicy::digest`), or
+/// zero if the account's public key authorizes its transactions.
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug, Default)]
+pub struct PolicyDigest(pub Fq);
+
+impl PolicyDigest {
+    /// Whether the account is a multisig account.
+    pub fn is_multisig(&self) -> bool {
+        !self.0.is_zero()
+    }
+
+    /// Convert the digest to bytes.
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        ark_ff::to_bytes![self.0].unwrap()
+    }
+}
+
+impl CanonicalSerialize for PolicyDigest {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for PolicyDigest {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        Fq::deserialize(reader).map(Self)
+    }
+}
+
 /// Information about the account, such as the balance and the associated public key.
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
 pub struct AccountInformation {
-    /// The account public key.
+    /// The account public key. For multisig accounts, this is
+    /// `MultisigPolicy::account_key`.
     pub public_key: AccountPublicKey,
     /// The balance associated with this this account.
     pub balance: Amount,
+    /// The nonce that the next transaction sent from this account must carry.
+    pub nonce: Nonce,
+    /// The smallest registry key that is larger than the key of `public_key`, or
+    /// `RegistryKey::max()` if there is none. This turns the account Merkle tree into
+    /// a sorted list of public keys, so that the rollup circuit can check that a
+    /// public key is not registered yet. See `State::register`.
+    pub next_key: RegistryKey,
+    /// The digest of the account's multisig policy, or zero if `public_key` authorizes
+    /// the account's transactions.
+    pub policy: PolicyDigest,
 }
 
 impl AccountInformation {
     /// Convert the account information to bytes.
     pub fn to_bytes_le(&self) -> Vec<u8> {
-        ark_ff::to_bytes![self.public_key, self.balance.to_bytes_le()].unwrap()
+        ark_ff::to_bytes![
+            self.public_key,
+            self.balance.to_bytes_le(),
+            self.nonce.to_bytes_le(),
+            self.next_key.to_bytes_le(),
+            self.policy.to_bytes_le()
+        ]
+        .unwrap()
+    }
+
+    /// The information stored in the leaf of identifier 0, which is never assigned to
+    /// an account. Its public key is the identity, whose registry key is 0, so it
+    /// heads the sorted list of public keys.
+    pub fn registry_sentinel(next_key: RegistryKey) -> Self {
+        Self {
+            public_key: AccountPublicKey::default(),
+            balance: Amount(0),
+            nonce: Nonce(0),
+            next_key,
+            policy: PolicyDigest::default(),
+        }
+    }
+}
+
+impl CanonicalSerialize for AccountInformation {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.public_key.serialize(&mut writer)?;
+        self.balance.serialize(&mut writer)?;
+        self.nonce.serialize(&mut writer)?;
+        self.next_key.serialize(&mut writer)?;
+        self.policy.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.public_key.serialized_size()
+            + self.balance.serialized_size()
+            + self.nonce.serialized_size()
+            + self.next_key.serialized_size()
+            + self.policy.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for AccountInformation {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        Ok(Self {
+            public_key: AccountPublicKey::deserialize(&mut reader)?,
+            balance: Amount::deserialize(&mut reader)?,
+            nonce: Nonce::deserialize(&mut reader)?,
+            next_key: RegistryKey::deserialize(&mut reader)?,
+            policy: PolicyDigest::deserialize(&mut reader)?,
+        })
     }
 }
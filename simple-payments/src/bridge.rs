@@ -0,0 +1,305 @@
+use crate::account::AccountId;
+use crate::ledger::{
+    AccRoot, AccountMerkleConfig, Amount, MerkleConfig, Parameters, SigScheme, TransactionSigScheme,
+};
+use crate::transaction::{Transaction, TransactionKind};
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_ff::to_bytes;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A commitment to a sequence of deposits or exits. This is a hash chain, which starts
+/// at `empty_digest()`, and which is extended via `extend_digest`.
+pub type BridgeDigest = AccRoot;
+
+/// The digest of an empty sequence of deposits or exits.
+pub fn empty_digest() -> BridgeDigest {
+    BridgeDigest::default()
+}
+
+/// Extend `digest` with an operation that moves `amount` into or out of `account`.
+/// The new digest is `TwoToOneHash(digest, LeafHash(AccountId || Amount))`, where the hash
+/// functions are those of the account Merkle tree.
+pub fn extend_digest<C: AccountMerkleConfig, S: TransactionSigScheme>(
+    parameters: &Parameters<C, S>,
+    digest: &BridgeDigest,
+    account: AccountId,
+    amount: Amount,
+) -> BridgeDigest {
+    let mut entry = account.to_bytes_le();
+    entry.extend(amount.to_bytes_le());
+    let entry_hash = C::LeafHash::evaluate(&parameters.leaf_crh_params, &entry).unwrap();
+    C::TwoToOneHash::evaluate(
+        &parameters.two_to_one_crh_params,
+        &to_bytes!(digest).unwrap(),
+        &to_bytes!(entry_hash).unwrap(),
+    )
+    .unwrap()
+}
+
+/// Compute the digests of the deposits and of the withdrawals in `transactions`, in
+/// the order in which they appear.
+pub fn digests<C: AccountMerkleConfig, S: TransactionSigScheme>(
+    parameters: &Parameters<C, S>,
+    transactions: &[Transaction<S>],
+) -> (BridgeDigest, BridgeDigest) {
+    let mut deposits_digest = empty_digest();
+    let mut exits_digest = empty_digest();
+    for tx in transactions {
+        match tx.kind {
+            TransactionKind::Transfer | TransactionKind::Registration(_) => {}
+            TransactionKind::Deposit => {
+                deposits_digest =
+                    extend_digest(parameters, &deposits_digest, tx.recipient, tx.amount)
+            }
+            TransactionKind::Withdrawal => {
+                exits_digest = extend_digest(parameters, &exits_digest, tx.sender, tx.amount)
+            }
+        }
+    }
+    (deposits_digest, exits_digest)
+}
+
+/// Money that was locked on the L1, to be credited to `recipient` on the rollup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deposit {
+    pub recipient: AccountId,
+    pub amount: Amount,
+}
+
+impl Deposit {
+    /// The rollup transaction that credits the deposit.
+    pub fn to_transaction<S: TransactionSigScheme>(&self) -> Transaction<S> {
+        Transaction::create_deposit(self.recipient, self.amount)
+    }
+
+    /// The deposit that `tx` credits, or `None` if `tx` is not a deposit, or if it pays
+    /// a fee. This is the inverse of `to_transaction`.
+    pub fn from_transaction<S: TransactionSigScheme>(tx: &Transaction<S>) -> Option<Self> {
+        if tx.kind != TransactionKind::Deposit || tx.fee != Amount(0) {
+            return None;
+        }
+        Some(Self {
+            recipient: tx.recipient,
+            amount: tx.amount,
+        })
+    }
+}
+
+/// Money that was withdrawn from `account` on the rollup, to be paid out on the L1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exit {
+    pub account: AccountId,
+    pub amount: Amount,
+}
+
+impl Exit {
+    /// The exits of the withdrawals in `transactions`, in order.
+    pub fn from_transactions<S: TransactionSigScheme>(
+        transactions: &[Transaction<S>],
+    ) -> Vec<Self> {
+        transactions
+            .iter()
+            .filter(|tx| tx.kind == TransactionKind::Withdrawal)
+            .map(|tx| Self {
+                account: tx.sender,
+                amount: tx.amount,
+            })
+            .collect()
+    }
+}
+
+/// The reasons for which the L1 rejects a batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BridgeError {
+    /// The deposits of the batch are not the next ones in the deposit queue.
+    UnknownDeposits,
+    /// The exits do not match the digest that the batch committed to.
+    ExitsMismatch,
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownDeposits => write!(f, "the deposits are not next in the queue"),
+            Self::ExitsMismatch => write!(f, "the exits do not match their digest"),
+        }
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+/// The L1 side of the bridge, simulated locally. It holds the queue of deposits that
+/// have not been credited on the rollup yet, and pays out exits.
+///
+/// Each proven batch commits to the digest of the deposits that it credits, and to the
+/// digest of the exits that it emits. Once the proof of a batch has been verified, the
+/// batch is passed to `process_batch`, which checks that its deposits are the next
+/// ones in the queue, and pays out its exits.
+pub struct SimulatedL1<C: AccountMerkleConfig = MerkleConfig, S: TransactionSigScheme = SigScheme> {
+    parameters: Parameters<C, S>,
+    deposit_queue: VecDeque<Deposit>,
+    paid_exits: Vec<Exit>,
+}
+
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> SimulatedL1<C, S> {
+    pub fn new(parameters: Parameters<C, S>) -> Self {
+        Self {
+            parameters,
+            deposit_queue: VecDeque::new(),
+            paid_exits: Vec::new(),
+        }
+    }
+
+    /// Lock `amount` on the L1, to be credited to `recipient` on the rollup.
+    pub fn deposit(&mut self, recipient: AccountId, amount: Amount) {
+        self.deposit_queue.push_back(Deposit { recipient, amount });
+    }
+
+    /// The deposits that have not been credited yet, in order.
+    pub fn pending_deposits(&self) -> impl Iterator<Item = &Deposit> {
+        self.deposit_queue.iter()
+    }
+
+    /// The exits that have been paid out so far, in order.
+    pub fn paid_exits(&self) -> &[Exit] {
+        &self.paid_exits
+    }
+
+    /// Process a batch whose proof has been verified, and which commits to
+    /// `deposits_digest` and `exits_digest`. This removes the deposits that the batch
+    /// credits from the queue, and pays out `exits`. Fails, without changing anything,
+    /// if the deposits are not a prefix of the queue, or if `exits` do not match
+    /// `exits_digest`.
+    pub fn process_batch(
+        &mut self,
+        deposits_digest: &BridgeDigest,
+        exits_digest: &BridgeDigest,
+        exits: &[Exit],
+    ) -> Result<(), BridgeError> {
+        let expected_exits_digest = exits.iter().fold(empty_digest(), |digest, exit| {
+            extend_digest(&self.parameters, &digest, exit.account, exit.amount)
+        });
+        if expected_exits_digest != *exits_digest {
+            return Err(BridgeError::ExitsMismatch);
+        }
+
+        let mut digest = empty_digest();
+        let mut num_deposits = 0;
+        while digest != *deposits_digest {
+            let deposit = self
+                .deposit_queue
+                .get(num_deposits)
+                .ok_or(BridgeError::UnknownDeposits)?;
+            digest = extend_digest(&self.parameters, &digest, deposit.recipient, deposit.amount);
+            num_deposits += 1;
+        }
+        self.deposit_queue.drain(..num_deposits);
+        self.paid_exits.extend_from_slice(exits);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::account::Nonce;
+    use crate::ledger::{LedgerError, State};
+
+    #[test]
+    fn deposits_and_withdrawals() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, _bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut l1 = SimulatedL1::new(pp.clone());
+
+        // Alice deposits 10 units, and Bob 5.
+        l1.deposit(alice_id, Amount(10));
+        l1.deposit(bob_id, Amount(5));
+        let deposits: Vec<_> = l1.pending_deposits().map(Deposit::to_transaction).collect();
+        // Deposits are not signed, so anyone could issue them as transactions. They are
+        // only credited out of the deposit queue.
+        assert_eq!(
+            state.apply_transaction(&pp, &deposits[0]),
+            Err(LedgerError::SelfIssuedDeposit)
+        );
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(0));
+        for deposit in l1.pending_deposits() {
+            state.apply_deposit(deposit).unwrap();
+        }
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(10));
+        // Deposits cannot pay fees, or credit accounts that do not exist.
+        assert_eq!(
+            Deposit::from_transaction(&deposits[0]),
+            Some(Deposit {
+                recipient: alice_id,
+                amount: Amount(10)
+            })
+        );
+        let mut bad_deposit = deposits[0].clone();
+        bad_deposit.fee = Amount(1);
+        assert_eq!(Deposit::from_transaction(&bad_deposit), None);
+        let bad_deposit = Deposit {
+            recipient: AccountId(7),
+            amount: Amount(1),
+        };
+        assert_eq!(
+            state.apply_deposit(&bad_deposit),
+            Err(LedgerError::UnknownRecipient)
+        );
+
+        // Alice withdraws 4 units, which need to be signed by her.
+        let withdrawal = Transaction::create_withdrawal(
+            &pp,
+            alice_id,
+            Amount(4),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut bad_withdrawal = withdrawal.clone();
+        bad_withdrawal.amount = Amount(5);
+        assert_eq!(
+            bad_withdrawal.check_validity(&pp, &state),
+            Err(LedgerError::InvalidSignature)
+        );
+        state.apply_transaction(&pp, &withdrawal).unwrap();
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(6));
+        assert_eq!(state.id_to_account_info[&alice_id].nonce, Nonce(1));
+
+        // The L1 only accepts the batch if it commits to the right deposits and exits.
+        let mut batch = deposits.clone();
+        batch.push(withdrawal);
+        let (deposits_digest, exits_digest) = digests(&pp, &batch);
+        let exits = Exit::from_transactions(&batch);
+        assert_eq!(
+            exits,
+            vec![Exit {
+                account: alice_id,
+                amount: Amount(4)
+            }]
+        );
+        let (reordered_digest, _) = digests(&pp, &[deposits[1].clone(), deposits[0].clone()]);
+        assert_eq!(
+            l1.process_batch(&reordered_digest, &exits_digest, &exits),
+            Err(BridgeError::UnknownDeposits)
+        );
+        assert_eq!(
+            l1.process_batch(&deposits_digest, &exits_digest, &[]),
+            Err(BridgeError::ExitsMismatch)
+        );
+        l1.process_batch(&deposits_digest, &exits_digest, &exits)
+            .unwrap();
+        assert_eq!(l1.pending_deposits().count(), 0);
+        assert_eq!(l1.paid_exits(), &exits[..]);
+        // The deposits cannot be credited twice.
+        assert_eq!(
+            l1.process_batch(&deposits_digest, &empty_digest(), &[]),
+            Err(BridgeError::UnknownDeposits)
+        );
+    }
+}
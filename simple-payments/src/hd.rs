@@ -0,0 +1,306 @@
+//! Hierarchical deterministic derivation of account secret keys, in the style of
+//! [BIP32](https://github.com/bitcoin/bips/blob/master/bip-0032.mediawiki), so that a
+//! wallet can back up all of its keys as a single seed.
+//!
+//! An extended secret key is a pair `(k, c)` of a secret scalar `k` and a 32-byte chain
+//! code `c`. The master key is derived from the seed `S` with `H_"Jubjub seed"(S)`, and the
+//! child at index `i` of `(k, c)` with `H_c(0x00 || k || i)`, where `H_key` is Blake2b keyed
+//! with `key`, `k` is encoded in 32 little-endian bytes, and `i` in 4 big-endian bytes.
+//! Each hash is computed twice, with the personalizations "hd-secret-key" and
+//! "hd-chain-code", and the child's `k` is read from the whole 64-byte output of the
+//! first, like in `schnorr::nonce`, and its `c` from the first 32 bytes of the second.
+//!
+//! This scheme is specific to this crate, so there are no published known-answer tests
+//! for it. Instead, its tests check it against an independent computation of the hashes
+//! above.
+//!
+//! Only hardened derivation is supported, so indices must be at least `HARDENED_OFFSET`.
+//! Unlike in BIP32, the child's scalar is not the parent's plus a tweak: without
+//! non-hardened derivation, there are no public keys to derive in step.
+use crate::account::AccountSecretKey;
+use crate::signature::schnorr::{self, SecretKey};
+use ark_ec::AffineCurve;
+use ark_ed_on_bls12_381::{EdwardsProjective, Fr};
+use ark_ff::{to_bytes, PrimeField};
+use blake2::Blake2b;
+use digest::Digest;
+use std::fmt;
+use std::str::FromStr;
+
+/// The first index of a hardened child, which is written `0'` in a path.
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+
+/// The key of the hash that derives the master key from the seed.
+const MASTER_KEY: &[u8] = b"Jubjub seed";
+/// The personalization of the hash that derives secret scalars.
+const SECRET_KEY_PERSONA: &[u8] = b"hd-secret-key";
+/// The personalization of the hash that derives chain codes.
+const CHAIN_CODE_PERSONA: &[u8] = b"hd-chain-code";
+
+/// The reasons for which a key derivation can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HdError {
+    /// The seed is shorter than 16 bytes or longer than 64 bytes, like in BIP32.
+    InvalidSeedLength(usize),
+    /// The index is not hardened, and non-hardened derivation is not supported.
+    UnhardenedIndex(u32),
+    /// The path is not of the form `m/i'/j'/...`.
+    InvalidPath,
+}
+
+impl fmt::Display for HdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSeedLength(len) => {
+                write!(f, "seed of {} bytes is not between 16 and 64 bytes", len)
+            }
+            Self::UnhardenedIndex(index) => write!(f, "index {} is not hardened", index),
+            Self::InvalidPath => write!(f, "invalid derivation path"),
+        }
+    }
+}
+
+impl std::error::Error for HdError {}
+
+/// A path of hardened indices from the master key to one of its descendants, such as
+/// `m/44'/0'/1'`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// Create the path through `indices`, which must all be hardened.
+    pub fn new(indices: Vec<u32>) -> Result<Self, HdError> {
+        if let Some(&index) = indices.iter().find(|&&index| index < HARDENED_OFFSET) {
+            return Err(HdError::UnhardenedIndex(index));
+        }
+        Ok(Self(indices))
+    }
+
+    /// The indices along the path, starting from the master key.
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = HdError;
+
+    /// Parse a path such as `m/44'/0'/1'`. Hardened indices may also be written `44h`.
+    fn from_str(s: &str) -> Result<Self, HdError> {
+        let mut components = s.split('/');
+        if components.next() != Some("m") {
+            return Err(HdError::InvalidPath);
+        }
+        let indices = components
+            .map(|component| {
+                let (index, hardened) = match component.strip_suffix(|c| c == '\'' || c == 'h') {
+                    Some(index) => (index, true),
+                    None => (component, false),
+                };
+                let index = index.parse::<u32>().map_err(|_| HdError::InvalidPath)?;
+                if index >= HARDENED_OFFSET {
+                    Err(HdError::InvalidPath)
+                } else if !hardened {
+                    Err(HdError::UnhardenedIndex(index))
+                } else {
+                    Ok(index + HARDENED_OFFSET)
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self(indices))
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            write!(f, "/{}'", index - HARDENED_OFFSET)?;
+        }
+        Ok(())
+    }
+}
+
+/// A secret key along with the chain code from which its children are derived.
+#[derive(Clone, Debug)]
+pub struct ExtendedSecretKey {
+    pub secret_key: AccountSecretKey,
+    pub chain_code: [u8; 32],
+}
+
+impl ExtendedSecretKey {
+    /// Derive the master key from `seed`, which should be sampled uniformly at random.
+    /// Fails if `seed` is shorter than 16 bytes or longer than 64 bytes.
+    pub fn from_seed(
+        parameters: &schnorr::Parameters<EdwardsProjective>,
+        seed: &[u8],
+    ) -> Result<Self, HdError> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(HdError::InvalidSeedLength(seed.len()));
+        }
+        Ok(Self::derive(parameters, MASTER_KEY, seed))
+    }
+
+    /// Derive the child at the hardened index `index`. Fails if `index` is not hardened.
+    pub fn derive_child(
+        &self,
+        parameters: &schnorr::Parameters<EdwardsProjective>,
+        index: u32,
+    ) -> Result<Self, HdError> {
+        if index < HARDENED_OFFSET {
+            return Err(HdError::UnhardenedIndex(index));
+        }
+        let mut data = vec![0u8];
+        data.extend_from_slice(&to_bytes![self.secret_key.secret_key].unwrap());
+        data.extend_from_slice(&index.to_be_bytes());
+        Ok(Self::derive(parameters, &self.chain_code, &data))
+    }
+
+    /// Derive the descendant at `path`. Fails like `derive_child` if an index of `path`
+    /// is not hardened.
+    pub fn derive_path(
+        &self,
+        parameters: &schnorr::Parameters<EdwardsProjective>,
+        path: &DerivationPath,
+    ) -> Result<Self, HdError> {
+        path.indices().iter().try_fold(self.clone(), |key, &index| {
+            key.derive_child(parameters, index)
+        })
+    }
+
+    /// Derive an extended secret key from `data` with Blake2b keyed with `key`.
+    fn derive(
+        parameters: &schnorr::Parameters<EdwardsProjective>,
+        key: &[u8],
+        data: &[u8],
+    ) -> Self {
+        let hash = |persona: &[u8]| {
+            let mut h = Blake2b::with_params(key, &[], persona);
+            h.update(data);
+            h.finalize()
+        };
+        // The secret scalar is zero with negligible probability, so we do not check it.
+        let secret_key = Fr::from_le_bytes_mod_order(&hash(SECRET_KEY_PERSONA));
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&hash(CHAIN_CODE_PERSONA)[..32]);
+        Self {
+            secret_key: SecretKey {
+                secret_key,
+                public_key: parameters.generator.mul(secret_key).into(),
+            },
+            chain_code,
+        }
+    }
+}
+
+/// Derive the secret key at `path` from `seed`. See `ExtendedSecretKey`.
+pub fn derive_secret_key(
+    parameters: &schnorr::Parameters<EdwardsProjective>,
+    seed: &[u8],
+    path: &DerivationPath,
+) -> Result<AccountSecretKey, HdError> {
+    let master_key = ExtendedSecretKey::from_seed(parameters, seed)?;
+    Ok(master_key.derive_path(parameters, path)?.secret_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ledger::{Parameters, SigScheme};
+    use crate::signature::SignatureScheme;
+
+    #[test]
+    fn derivation_matches_test_vectors() {
+        // The expected keys and chain code were reproduced independently with Python's
+        // `hashlib.blake2b`, keyed and personalized as in the module docs, by reducing the
+        // secret key hashes modulo the order of Jubjub's scalar field.
+        let pp = Parameters::sample(&mut ark_std::test_rng());
+        let seed = (0u8..32).collect::<Vec<_>>();
+        let master_key = ExtendedSecretKey::from_seed(&pp.sig_params, &seed).unwrap();
+        assert_eq!(
+            master_key.secret_key.secret_key,
+            Fr::from_str(
+                "3224389558009750014414120443763750643992876495743000142521754332372578070039"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            master_key.chain_code,
+            [
+                30, 214, 223, 101, 94, 23, 94, 50, 182, 58, 188, 48, 102, 109, 122, 57, 19, 79,
+                189, 52, 110, 198, 185, 123, 85, 124, 117, 94, 49, 71, 220, 241
+            ]
+        );
+
+        let test_vectors = [
+            (
+                "m/0'",
+                "3435684385611382353934891642823806270538691399933543513702120972801153744489",
+            ),
+            (
+                "m/44'/0'/1'",
+                "2534662879275213549563549838088620039186184255005148573420562048699303905498",
+            ),
+        ];
+        for (path, secret_key) in test_vectors {
+            let path = path.parse::<DerivationPath>().unwrap();
+            let derived = derive_secret_key(&pp.sig_params, &seed, &path).unwrap();
+            assert_eq!(derived.secret_key, Fr::from_str(secret_key).unwrap());
+            // The key is the same when derived one child at a time...
+            let mut key = master_key.clone();
+            for &index in path.indices() {
+                key = key.derive_child(&pp.sig_params, index).unwrap();
+            }
+            assert_eq!(key.secret_key.secret_key, derived.secret_key);
+            // ...and it signs like a sampled key.
+            let sig = SigScheme::sign(&pp.sig_params, &derived, b"hello", &mut ark_std::test_rng())
+                .unwrap();
+            assert!(
+                SigScheme::verify(&pp.sig_params, &derived.public_key, b"hello", &sig).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn only_hardened_paths_are_derived() {
+        let pp = Parameters::sample(&mut ark_std::test_rng());
+        let path = "m/44'/0h/1'".parse::<DerivationPath>().unwrap();
+        assert_eq!(
+            path,
+            DerivationPath::new(vec![
+                HARDENED_OFFSET + 44,
+                HARDENED_OFFSET,
+                HARDENED_OFFSET + 1
+            ])
+            .unwrap()
+        );
+        assert_eq!(path.to_string(), "m/44'/0'/1'");
+        assert_eq!("m".parse(), Ok(DerivationPath::default()));
+
+        assert_eq!(
+            "m/44'/0".parse::<DerivationPath>(),
+            Err(HdError::UnhardenedIndex(0))
+        );
+        assert_eq!(
+            DerivationPath::new(vec![HARDENED_OFFSET, 1]),
+            Err(HdError::UnhardenedIndex(1))
+        );
+        for path in ["", "44'/0'", "m/", "m/x'", "m/2147483648'"] {
+            assert_eq!(path.parse::<DerivationPath>(), Err(HdError::InvalidPath));
+        }
+
+        assert_eq!(
+            ExtendedSecretKey::from_seed(&pp.sig_params, &[0u8; 15]).unwrap_err(),
+            HdError::InvalidSeedLength(15)
+        );
+        assert_eq!(
+            ExtendedSecretKey::from_seed(&pp.sig_params, &[0u8; 65]).unwrap_err(),
+            HdError::InvalidSeedLength(65)
+        );
+        let master_key = ExtendedSecretKey::from_seed(&pp.sig_params, &[0u8; 16]).unwrap();
+        assert_eq!(
+            master_key.derive_child(&pp.sig_params, 1).unwrap_err(),
+            HdError::UnhardenedIndex(1)
+        );
+    }
+}
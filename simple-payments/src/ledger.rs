@@ -1,14 +1,25 @@
-use crate::account::{AccountId, AccountInformation, AccountPublicKey, AccountSecretKey};
-use crate::signature::{schnorr, SignatureScheme};
-use crate::transaction::Transaction;
+use crate::account::{
+    AccountId, AccountInformation, AccountPublicKey, AccountSecretKey, MultisigPolicy, Nonce,
+    PolicyDigest, RegistryKey,
+};
+use crate::bridge::Deposit;
+use crate::hd::{DerivationPath, ExtendedSecretKey, HdError};
+use crate::poseidon;
+use crate::random_oracle::{blake2s, RandomOracle};
+use crate::signature::{eddsa, schnorr, SignatureScheme};
+use crate::sparse_merkle_tree::SparseMerkleTree;
+use crate::transaction::{Transaction, TransactionKind};
 use ark_crypto_primitives::crh::{
     injective_map::{PedersenCRHCompressor, TECompressor},
     pedersen, TwoToOneCRH, CRH,
 };
-use ark_crypto_primitives::merkle_tree::{self, MerkleTree, Path};
-use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_crypto_primitives::merkle_tree::{self, Path};
+use ark_crypto_primitives::Error;
+use ark_ed_on_bls12_381::{EdwardsProjective, Fq};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
 use ark_std::rand::Rng;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 
 /// Represents transaction amounts and account balances.
 #[derive(Hash, Eq, PartialEq, Copy, Clone, PartialOrd, Ord, Debug)]
@@ -28,28 +39,349 @@ impl Amount {
     }
 }
 
-/// The parameters that are used in transaction creation and validation.
-#[derive(Clone)]
-pub struct Parameters {
-    pub sig_params: schnorr::Parameters<EdwardsProjective>,
-    pub leaf_crh_params: <TwoToOneHash as CRH>::Parameters,
-    pub two_to_one_crh_params: <TwoToOneHash as TwoToOneCRH>::Parameters,
+impl CanonicalSerialize for Amount {
+    fn serialize<W: Write>(&self, writer: W) -> Result<(), SerializationError> {
+        self.0.serialize(writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.0.serialized_size()
+    }
+}
+
+impl CanonicalDeserialize for Amount {
+    fn deserialize<R: Read>(reader: R) -> Result<Self, SerializationError> {
+        u64::deserialize(reader).map(Self)
+    }
+}
+
+/// The reasons for which the ledger can reject an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LedgerError {
+    /// The transaction signature does not verify under the sender's public key.
+    InvalidSignature,
+    /// The sender cannot afford the transaction amount plus the fee.
+    InsufficientBalance,
+    /// The transaction's sender does not have an account.
+    UnknownSender,
+    /// The transaction's recipient does not have an account.
+    UnknownRecipient,
+    /// The transfer's sender and recipient are the same account.
+    SelfTransfer,
+    /// The account that the operation refers to does not exist.
+    UnknownAccount,
+    /// The transaction does not carry the sender's current nonce.
+    InvalidNonce { expected: Nonce, found: Nonce },
+    /// The transaction pays a fee, but no fee collector has been set.
+    MissingFeeCollector,
+    /// A balance or a nonce would overflow.
+    Overflow,
+    /// There is no space left in the account Merkle tree.
+    LedgerFull,
+    /// An account with the given public key exists already.
+    DuplicatePublicKey,
+    /// The public key is not in the prime-order subgroup of Jubjub.
+    InvalidPublicKey,
+    /// The account information does not match the account Merkle tree.
+    InvalidMembershipProof,
+    /// The deposit pays a fee, which deposits cannot do.
+    InvalidDeposit,
+    /// The transaction is a deposit. Deposits are not signed, so they can only be
+    /// credited out of the L1 deposit queue, via `State::apply_deposit`.
+    SelfIssuedDeposit,
+    /// The registration does not fill the next free leaf, or does not insert the public
+    /// key at the right place in the registry. See `Transaction::create_registration`.
+    InvalidRegistration,
+    /// The multisig policy is malformed (see `MultisigPolicy::new`), or the transaction
+    /// does not carry the policy of the sender's account.
+    InvalidPolicy,
+    /// The transaction carries fewer approvals than the threshold of the sender's
+    /// multisig policy.
+    InsufficientApprovals,
+    /// The account's key could not be derived.
+    Derivation(HdError),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => write!(f, "invalid transaction signature"),
+            Self::InsufficientBalance => write!(f, "insufficient sender balance"),
+            Self::UnknownSender => write!(f, "unknown sender account"),
+            Self::UnknownRecipient => write!(f, "unknown recipient account"),
+            Self::SelfTransfer => write!(f, "sender and recipient are the same account"),
+            Self::UnknownAccount => write!(f, "unknown account"),
+            Self::InvalidNonce { expected, found } => write!(
+                f,
+                "invalid nonce: expected {}, found {}",
+                expected.0, found.0
+            ),
+            Self::MissingFeeCollector => write!(f, "no fee collector to pay the fee to"),
+            Self::Overflow => write!(f, "balance or nonce overflow"),
+            Self::LedgerFull => write!(f, "no space left for new accounts"),
+            Self::DuplicatePublicKey => write!(f, "public key is registered already"),
+            Self::InvalidPublicKey => write!(f, "public key is not in the prime-order subgroup"),
+            Self::InvalidMembershipProof => {
+                write!(f, "account information is not in the Merkle tree")
+            }
+            Self::InvalidDeposit => write!(f, "deposits cannot pay fees"),
+            Self::SelfIssuedDeposit => {
+                write!(
+                    f,
+                    "deposits can only be credited out of the L1 deposit queue"
+                )
+            }
+            Self::InvalidRegistration => write!(f, "registration does not match the ledger"),
+            Self::InvalidPolicy => write!(f, "invalid multisig policy"),
+            Self::InsufficientApprovals => write!(f, "not enough multisig approvals"),
+            Self::Derivation(e) => write!(f, "key derivation failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+/// The random oracle that computes the verifier challenges of transaction signatures.
+/// Its Blake2s hashes take most of the constraints of checking a signature in a circuit,
+/// so `random_oracle::poseidon::RO<Fq>` is a SNARK-friendly alternative.
+pub type ChallengeRO = blake2s::RO;
+
+/// Schnorr signatures over Jubjub, whose challenges are computed with `ChallengeRO`.
+pub type SchnorrSigScheme = schnorr::Schnorr<EdwardsProjective, ChallengeRO>;
+
+/// EdDSA signatures over Jubjub, whose challenges are computed with `ChallengeRO`. They
+/// use the same parameters and keys as `SchnorrSigScheme`.
+pub type EdDSASigScheme = eddsa::EdDSA<EdwardsProjective, ChallengeRO>;
+
+/// A signature scheme that transactions can be signed with, such as `SchnorrSigScheme` and
+/// `EdDSASigScheme`. Such schemes share the parameters and keys of accounts, so the choice
+/// of scheme does not affect accounts.
+pub trait TransactionSigScheme:
+    SignatureScheme<
+    Parameters = schnorr::Parameters<EdwardsProjective>,
+    PublicKey = AccountPublicKey,
+    SecretKey = AccountSecretKey,
+    Signature: CanonicalSerialize + CanonicalDeserialize + fmt::Debug,
+>
+{
+    /// Verify the signatures of a batch of `(public key, message, signature)` triples at
+    /// once, like `Schnorr::batch_verify` and `EdDSA::batch_verify`.
+    fn batch_verify<R: Rng>(
+        parameters: &Self::Parameters,
+        batch: &[(AccountPublicKey, &[u8], &Self::Signature)],
+        rng: &mut R,
+    ) -> Result<bool, Error>;
+}
+
+impl TransactionSigScheme for SchnorrSigScheme {
+    fn batch_verify<R: Rng>(
+        parameters: &Self::Parameters,
+        batch: &[(AccountPublicKey, &[u8], &Self::Signature)],
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        schnorr::Schnorr::<EdwardsProjective, ChallengeRO>::batch_verify(parameters, batch, rng)
+    }
+}
+
+impl TransactionSigScheme for EdDSASigScheme {
+    fn batch_verify<R: Rng>(
+        parameters: &Self::Parameters,
+        batch: &[(AccountPublicKey, &[u8], &Self::Signature)],
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        eddsa::EdDSA::<EdwardsProjective, ChallengeRO>::batch_verify(parameters, batch, rng)
+    }
+}
+
+/// The signature scheme of transactions, unless `Parameters` are given another
+/// `TransactionSigScheme`.
+pub type SigScheme = SchnorrSigScheme;
+
+/// A signature of the transaction signature scheme `S`.
+pub type Signature<S = SigScheme> = <S as SignatureScheme>::Signature;
+
+/// Separates the domain tags of transaction signatures from other uses of the keys.
+const DOMAIN_SEPARATOR: &[u8] = b"ark-simple-payments/transaction";
+
+/// The parameters that are used in transaction creation and validation, on a ledger
+/// whose account Merkle tree is hashed according to `C`, and whose transactions are
+/// signed with `S`.
+pub struct Parameters<C: AccountMerkleConfig = MerkleConfig, S: TransactionSigScheme = SigScheme> {
+    /// Identifies the deployment of the ledger. The salt of `sig_params` is the domain tag
+    /// of the chain (see `Parameters::domain_tag`), so transaction signatures do not verify
+    /// on ledgers with other chain IDs.
+    pub chain_id: u64,
+    pub sig_params: S::Parameters,
+    pub leaf_crh_params: <C::LeafHash as CRH>::Parameters,
+    pub two_to_one_crh_params: <C::TwoToOneHash as TwoToOneCRH>::Parameters,
+}
+
+// Deriving `Clone` would require the hash functions themselves to be `Clone`.
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> Clone for Parameters<C, S> {
+    fn clone(&self) -> Self {
+        Self {
+            chain_id: self.chain_id,
+            sig_params: self.sig_params.clone(),
+            leaf_crh_params: self.leaf_crh_params.clone(),
+            two_to_one_crh_params: self.two_to_one_crh_params.clone(),
+        }
+    }
 }
 
 impl Parameters {
+    /// Samples the parameters of a ledger with chain ID 0.
     pub fn sample<R: Rng>(rng: &mut R) -> Self {
-        let sig_params = schnorr::Schnorr::setup(rng).unwrap();
-        let leaf_crh_params = <LeafHash as CRH>::setup(rng).unwrap();
-        let two_to_one_crh_params = <TwoToOneHash as TwoToOneCRH>::setup(rng).unwrap();
+        Self::sample_for_chain(0, rng)
+    }
+
+    /// Samples the parameters of a ledger with chain ID `chain_id`.
+    pub fn sample_for_chain<R: Rng>(chain_id: u64, rng: &mut R) -> Self {
+        Self::sample_with_schemes(chain_id, rng)
+    }
+
+    /// The domain tag of the transaction signatures of the chain with ID `chain_id`, i.e.,
+    /// the Blake2s hash of `DOMAIN_SEPARATOR || chain_id`. Both signature schemes hash
+    /// their salt into the challenges and nonces of signatures.
+    pub fn domain_tag(chain_id: u64) -> [u8; 32] {
+        let mut input = DOMAIN_SEPARATOR.to_vec();
+        input.extend_from_slice(&chain_id.to_le_bytes());
+        blake2s::RO::evaluate(&(), &input).unwrap()
+    }
+}
+
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> Parameters<C, S> {
+    /// Samples the parameters of a ledger with chain ID `chain_id`, whose account Merkle
+    /// tree is hashed according to `C`, and whose transactions are signed with `S`, e.g.
+    /// `Parameters::<PoseidonMerkleConfig, EdDSASigScheme>::sample_with_schemes(0, rng)`.
+    pub fn sample_with_schemes<R: Rng>(chain_id: u64, rng: &mut R) -> Self {
+        let mut sig_params = S::setup(rng).unwrap();
+        sig_params.salt = Some(Parameters::domain_tag(chain_id));
+        let leaf_crh_params = <C::LeafHash as CRH>::setup(rng).unwrap();
+        let two_to_one_crh_params = <C::TwoToOneHash as TwoToOneCRH>::setup(rng).unwrap();
         Self {
+            chain_id,
+            sig_params,
+            leaf_crh_params,
+            two_to_one_crh_params,
+        }
+    }
+}
+
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> CanonicalSerialize for Parameters<C, S> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.chain_id.serialize(&mut writer)?;
+        self.sig_params.serialize(&mut writer)?;
+        C::serialize_params(&self.leaf_crh_params, &self.two_to_one_crh_params, writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.chain_id.serialized_size()
+            + self.sig_params.serialized_size()
+            + C::params_serialized_size(&self.leaf_crh_params, &self.two_to_one_crh_params)
+    }
+}
+
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> CanonicalDeserialize for Parameters<C, S> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let chain_id = u64::deserialize(&mut reader)?;
+        let sig_params = schnorr::Parameters::deserialize(&mut reader)?;
+        if sig_params.salt != Some(Parameters::domain_tag(chain_id)) {
+            return Err(SerializationError::InvalidData);
+        }
+        let (leaf_crh_params, two_to_one_crh_params) = C::deserialize_params(reader)?;
+        Ok(Self {
+            chain_id,
             sig_params,
             leaf_crh_params,
             two_to_one_crh_params,
+        })
+    }
+}
+
+/// The hash functions of the account Merkle tree, whose parameters `Parameters` and `State`
+/// serialize.
+trait SerializableParams {
+    type Params;
+
+    fn serialize_params<W: Write>(
+        params: &Self::Params,
+        writer: W,
+    ) -> Result<(), SerializationError>;
+    fn params_serialized_size(params: &Self::Params) -> usize;
+    fn deserialize_params<R: Read>(reader: R) -> Result<Self::Params, SerializationError>;
+}
+
+/// Pedersen parameters are not `CanonicalSerialize`, so we serialize their generators,
+/// and check that there is the right number of them for the window `W` when deserializing.
+impl<W: pedersen::Window> SerializableParams
+    for PedersenCRHCompressor<EdwardsProjective, TECompressor, W>
+{
+    type Params = pedersen::Parameters<EdwardsProjective>;
+
+    fn serialize_params<Wr: Write>(
+        params: &Self::Params,
+        writer: Wr,
+    ) -> Result<(), SerializationError> {
+        params.generators.serialize(writer)
+    }
+
+    fn params_serialized_size(params: &Self::Params) -> usize {
+        params.generators.serialized_size()
+    }
+
+    fn deserialize_params<R: Read>(reader: R) -> Result<Self::Params, SerializationError> {
+        let generators = Vec::<Vec<EdwardsProjective>>::deserialize(reader)?;
+        if generators.len() != W::NUM_WINDOWS
+            || generators
+                .iter()
+                .any(|window| window.len() != W::WINDOW_SIZE)
+        {
+            return Err(SerializationError::InvalidData);
         }
+        Ok(pedersen::Parameters { generators })
+    }
+}
+
+impl<I: poseidon::InputSize> SerializableParams for poseidon::CRH<Fq, I> {
+    type Params = poseidon::Parameters<Fq>;
+
+    fn serialize_params<W: Write>(
+        params: &Self::Params,
+        writer: W,
+    ) -> Result<(), SerializationError> {
+        params.serialize(writer)
+    }
+
+    fn params_serialized_size(params: &Self::Params) -> usize {
+        params.serialized_size()
+    }
+
+    fn deserialize_params<R: Read>(reader: R) -> Result<Self::Params, SerializationError> {
+        poseidon::Parameters::deserialize(reader)
+    }
+}
+
+impl SerializableParams for poseidon::TwoToOneCRH<Fq> {
+    type Params = poseidon::Parameters<Fq>;
+
+    fn serialize_params<W: Write>(
+        params: &Self::Params,
+        writer: W,
+    ) -> Result<(), SerializationError> {
+        params.serialize(writer)
+    }
+
+    fn params_serialized_size(params: &Self::Params) -> usize {
+        params.serialized_size()
+    }
+
+    fn deserialize_params<R: Read>(reader: R) -> Result<Self::Params, SerializationError> {
+        poseidon::Parameters::deserialize(reader)
     }
 }
 
-pub type TwoToOneHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
+pub type PedersenTwoToOneHash =
+    PedersenCRHCompressor<EdwardsProjective, TECompressor, TwoToOneWindow>;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct TwoToOneWindow;
@@ -60,62 +392,204 @@ impl pedersen::Window for TwoToOneWindow {
     const NUM_WINDOWS: usize = 4;
 }
 
-pub type LeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
+pub type PedersenLeafHash = PedersenCRHCompressor<EdwardsProjective, TECompressor, LeafWindow>;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct LeafWindow;
 
-// `WINDOW_SIZE * NUM_WINDOWS` = 2 * 256 bits = enough for hashing two outputs.
+// `WINDOW_SIZE * NUM_WINDOWS` = 1152 bits = enough for hashing a public key,
+// a balance, a nonce, a registry key and a policy digest.
 impl pedersen::Window for LeafWindow {
-    const WINDOW_SIZE: usize = 144;
-    const NUM_WINDOWS: usize = 4;
+    const WINDOW_SIZE: usize = 128;
+    const NUM_WINDOWS: usize = 9;
 }
 
+/// Hashes the account Merkle tree with Pedersen hashes over Jubjub.
 #[derive(Clone)]
-pub struct MerkleConfig;
-impl merkle_tree::Config for MerkleConfig {
-    type LeafHash = LeafHash;
-    type TwoToOneHash = TwoToOneHash;
+pub struct PedersenMerkleConfig;
+impl merkle_tree::Config for PedersenMerkleConfig {
+    type LeafHash = PedersenLeafHash;
+    type TwoToOneHash = PedersenTwoToOneHash;
+}
+
+pub type PoseidonTwoToOneHash = poseidon::TwoToOneCRH<Fq>;
+
+pub type PoseidonLeafHash = poseidon::CRH<Fq, LeafSize>;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct LeafSize;
+
+// As many bytes as `PedersenLeafHash` hashes.
+impl poseidon::InputSize for LeafSize {
+    const NUM_BYTES: usize = <PedersenLeafHash as CRH>::INPUT_SIZE_BITS / 8;
 }
 
+/// Hashes the account Merkle tree with Poseidon over the scalar field of BLS12-381 (which
+/// is also the base field of Jubjub). Checking a path then takes far fewer constraints in
+/// a circuit over that field.
+#[derive(Clone)]
+pub struct PoseidonMerkleConfig;
+impl merkle_tree::Config for PoseidonMerkleConfig {
+    type LeafHash = PoseidonLeafHash;
+    type TwoToOneHash = PoseidonTwoToOneHash;
+}
+
+/// The hash functions of an account Merkle tree, such as `PedersenMerkleConfig` and
+/// `PoseidonMerkleConfig`. Both hash functions output elements of `Fq`, so the roots of
+/// every account Merkle tree have the same type.
+pub trait AccountMerkleConfig:
+    merkle_tree::Config<LeafHash: CRH<Output = Fq>, TwoToOneHash: TwoToOneCRH<Output = Fq>> + Clone
+{
+    /// Serialize the parameters of both hash functions.
+    fn serialize_params<W: Write>(
+        leaf_crh_params: &<Self::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+        writer: W,
+    ) -> Result<(), SerializationError>;
+    fn params_serialized_size(
+        leaf_crh_params: &<Self::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+    ) -> usize;
+    /// Deserialize the parameters of both hash functions, as written by
+    /// `serialize_params`.
+    #[allow(clippy::type_complexity)]
+    fn deserialize_params<R: Read>(
+        reader: R,
+    ) -> Result<
+        (
+            <Self::LeafHash as CRH>::Parameters,
+            <Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+        ),
+        SerializationError,
+    >;
+}
+
+impl<C> AccountMerkleConfig for C
+where
+    C: merkle_tree::Config + Clone,
+    C::LeafHash: CRH<Output = Fq> + SerializableParams<Params = <C::LeafHash as CRH>::Parameters>,
+    C::TwoToOneHash: TwoToOneCRH<Output = Fq>
+        + SerializableParams<Params = <C::TwoToOneHash as TwoToOneCRH>::Parameters>,
+{
+    fn serialize_params<W: Write>(
+        leaf_crh_params: &<Self::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+        mut writer: W,
+    ) -> Result<(), SerializationError> {
+        C::LeafHash::serialize_params(leaf_crh_params, &mut writer)?;
+        C::TwoToOneHash::serialize_params(two_to_one_crh_params, writer)
+    }
+
+    fn params_serialized_size(
+        leaf_crh_params: &<Self::LeafHash as CRH>::Parameters,
+        two_to_one_crh_params: &<Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+    ) -> usize {
+        C::LeafHash::params_serialized_size(leaf_crh_params)
+            + C::TwoToOneHash::params_serialized_size(two_to_one_crh_params)
+    }
+
+    fn deserialize_params<R: Read>(
+        mut reader: R,
+    ) -> Result<
+        (
+            <Self::LeafHash as CRH>::Parameters,
+            <Self::TwoToOneHash as TwoToOneCRH>::Parameters,
+        ),
+        SerializationError,
+    > {
+        Ok((
+            C::LeafHash::deserialize_params(&mut reader)?,
+            C::TwoToOneHash::deserialize_params(reader)?,
+        ))
+    }
+}
+
+/// The hash functions of the account Merkle tree of `Parameters`, `State`, and the rollup
+/// circuits, unless they are given another `AccountMerkleConfig`.
+pub type MerkleConfig = PedersenMerkleConfig;
+
 /// A Merkle tree containing account information.
-pub type AccMerkleTree = MerkleTree<MerkleConfig>;
-/// The root of the account Merkle tree.
-pub type AccRoot = <TwoToOneHash as TwoToOneCRH>::Output;
+pub type AccMerkleTree<C = MerkleConfig> = SparseMerkleTree<C>;
+/// The root of an account Merkle tree.
+pub type AccRoot = Fq;
 /// A membership proof for a given account.
-pub type AccPath = Path<MerkleConfig>;
+pub type AccPath<C = MerkleConfig> = Path<C>;
+
+/// The maximum height of the account Merkle tree (not counting the leaf level), which
+/// is enough to hold every `AccountId`.
+pub const MAX_TREE_HEIGHT: usize = 32;
 
 #[derive(Clone)]
-pub struct State {
+pub struct State<C: AccountMerkleConfig = MerkleConfig> {
     /// What is the next available account identifier?
     pub next_available_account: Option<AccountId>,
     /// A merkle tree mapping where the i-th leaf corresponds to the i-th account's
     /// information (= balance and public key).
-    pub account_merkle_tree: AccMerkleTree,
+    pub account_merkle_tree: AccMerkleTree<C>,
     /// A mapping from an account's identifier to its information (= balance and public key).
     pub id_to_account_info: HashMap<AccountId, AccountInformation>,
     /// A mapping from a public key to an account's identifier.
     pub pub_key_to_id: HashMap<schnorr::PublicKey<EdwardsProjective>, AccountId>,
+    /// The registry of public keys, which maps the `RegistryKey` of every registered
+    /// public key to its account's identifier, in sorted order.
+    pub registry: BTreeMap<RegistryKey, AccountId>,
+    /// The account that transaction fees are credited to. Transactions that pay a
+    /// non-zero fee are only valid if a fee collector has been set.
+    pub fee_collector: Option<AccountId>,
 }
 
-impl State {
+impl<C: AccountMerkleConfig> State<C> {
     /// Create an empty ledger that supports `num_accounts` accounts.
-    pub fn new(num_accounts: usize, parameters: &Parameters) -> Self {
-        let height = ark_std::log2(num_accounts);
-        let account_merkle_tree = MerkleTree::blank(
+    /// The account Merkle tree has `2^h` leaves, where `h` is the smallest height such
+    /// that identifiers `1..=num_accounts` fit, and `h` can be at most `MAX_TREE_HEIGHT`.
+    /// Only the parts of the tree that are in use are kept in memory.
+    pub fn new<S: TransactionSigScheme>(
+        num_accounts: usize,
+        parameters: &Parameters<C, S>,
+    ) -> Self {
+        let height = (ark_std::log2(num_accounts.saturating_add(1)) as usize).max(1);
+        assert!(
+            height <= MAX_TREE_HEIGHT,
+            "cannot support more than 2^{} accounts",
+            MAX_TREE_HEIGHT
+        );
+        let account_merkle_tree = SparseMerkleTree::blank(
             &parameters.leaf_crh_params,
             &parameters.two_to_one_crh_params,
-            height as usize,
+            height + 1,
         )
         .unwrap();
-        let pub_key_to_id = HashMap::with_capacity(num_accounts);
-        let id_to_account_info = HashMap::with_capacity(num_accounts);
-        Self {
+        let mut state = Self {
             next_available_account: Some(AccountId(1)),
             account_merkle_tree,
-            id_to_account_info,
-            pub_key_to_id,
+            id_to_account_info: HashMap::new(),
+            pub_key_to_id: HashMap::new(),
+            registry: BTreeMap::new(),
+            fee_collector: None,
+        };
+        state.write_registry_sentinel();
+        state
+    }
+
+    /// Designate the account `id` as the recipient of all transaction fees.
+    /// Fails if no account with identifier `id` exists.
+    pub fn set_fee_collector(&mut self, id: AccountId) -> Result<(), LedgerError> {
+        if !self.id_to_account_info.contains_key(&id) {
+            return Err(LedgerError::UnknownAccount);
         }
+        self.fee_collector = Some(id);
+        Ok(())
+    }
+
+    /// Return all accounts, ordered by their identifiers.
+    fn sorted_accounts(&self) -> Vec<(AccountId, AccountInformation)> {
+        let mut accounts: Vec<_> = self
+            .id_to_account_info
+            .iter()
+            .map(|(id, info)| (*id, *info))
+            .collect();
+        accounts.sort_by_key(|(id, _)| *id);
+        accounts
     }
 
     /// Return the root of the account Merkle tree.
@@ -123,72 +597,457 @@ impl State {
         self.account_merkle_tree.root()
     }
 
-    /// Create a new account with public key `pub_key`. Returns a fresh account identifier
-    /// if there is space for a new account, and returns `None` otherwise.
-    /// The initial balance of the new account is 0.
-    pub fn register(&mut self, public_key: AccountPublicKey) -> Option<AccountId> {
-        self.next_available_account.and_then(|id| {
-            // Construct account information for the new account.
-            let account_info = AccountInformation {
-                public_key,
-                balance: Amount(0),
+    /// The information stored in the leaf of `id`. This is the account information of
+    /// `id`, or the registry sentinel if `id` is 0 (see
+    /// `AccountInformation::registry_sentinel`), or `None` if the leaf is empty.
+    pub fn leaf_info(&self, id: AccountId) -> Option<AccountInformation> {
+        if id == AccountId(0) {
+            let next_key = self.registry.keys().next().copied();
+            return Some(AccountInformation::registry_sentinel(
+                next_key.unwrap_or_else(RegistryKey::max),
+            ));
+        }
+        self.id_to_account_info.get(&id).copied()
+    }
+
+    /// The account whose public key precedes `key` in the registry, or the registry
+    /// sentinel (identifier 0) if there is none.
+    pub fn registry_predecessor(&self, key: RegistryKey) -> AccountId {
+        self.registry
+            .range(..key)
+            .next_back()
+            .map_or(AccountId(0), |(_, id)| *id)
+    }
+
+    /// Write the registry sentinel to the leaf of identifier 0.
+    fn write_registry_sentinel(&mut self) {
+        let sentinel = self.leaf_info(AccountId(0)).unwrap();
+        self.account_merkle_tree
+            .update(0, &sentinel.to_bytes_le())
+            .expect("should exist");
+    }
+
+    /// Create a new account with public key `pub_key`, and return its fresh account
+    /// identifier. Fails if there is no space for a new account, if `pub_key` belongs
+    /// to an existing account, or if it is not in the prime-order subgroup. The initial
+    /// balance of the new account is 0.
+    ///
+    /// This applies the transaction returned by `Transaction::create_registration`, so
+    /// registrations can also be proven as part of a rollup batch.
+    pub fn register(&mut self, public_key: AccountPublicKey) -> Result<AccountId, LedgerError> {
+        let tx = Transaction::<SigScheme>::create_registration(self, public_key)?;
+        self.apply_registration(public_key, PolicyDigest::default(), tx.sender, tx.recipient);
+        Ok(tx.recipient)
+    }
+
+    /// Create a multisig account whose transactions must be approved according to
+    /// `policy`, and return its fresh account identifier. The account's public key is
+    /// `policy.account_key()`. Fails if the policy is malformed, and otherwise like
+    /// `register`.
+    ///
+    /// Unlike `register`, this cannot be proven as part of a rollup batch.
+    pub fn register_multisig(&mut self, policy: &MultisigPolicy) -> Result<AccountId, LedgerError> {
+        policy.check()?;
+        let public_key = policy.account_key();
+        let tx = Transaction::<SigScheme>::create_registration(self, public_key)?;
+        let digest = policy.digest::<C>(self.account_merkle_tree.leaf_hash_param());
+        self.apply_registration(public_key, digest, tx.sender, tx.recipient);
+        Ok(tx.recipient)
+    }
+
+    /// Insert `public_key` into the registry after the account `predecessor`, and create
+    /// the account `id` for it with the policy digest `policy`, without validating
+    /// anything.
+    fn apply_registration(
+        &mut self,
+        public_key: AccountPublicKey,
+        policy: PolicyDigest,
+        predecessor: AccountId,
+        id: AccountId,
+    ) {
+        let key = RegistryKey::of(&public_key);
+        // Construct account information for the new account, which takes the place of
+        // its predecessor in the registry.
+        let predecessor_info = self.leaf_info(predecessor).expect("should exist");
+        let account_info = AccountInformation {
+            public_key,
+            balance: Amount(0),
+            nonce: Nonce(0),
+            next_key: predecessor_info.next_key,
+            policy,
+        };
+        // Insert information into the relevant accounts.
+        self.pub_key_to_id.insert(public_key, id);
+        self.registry.insert(key, id);
+        self.account_merkle_tree
+            .update(id.leaf_index(), &account_info.to_bytes_le())
+            .expect("should exist");
+        self.id_to_account_info.insert(id, account_info);
+        if predecessor == AccountId(0) {
+            self.write_registry_sentinel();
+        } else {
+            let predecessor_info = AccountInformation {
+                next_key: key,
+                ..predecessor_info
             };
-            // Insert information into the relevant accounts.
-            self.pub_key_to_id.insert(public_key, id);
-            self.account_merkle_tree
-                .update(id.0 as usize, &account_info.to_bytes_le())
+            self.update_account_info(predecessor, predecessor_info)
                 .expect("should exist");
-            self.id_to_account_info.insert(id, account_info);
-            // Increment the next account identifier.
-            self.next_available_account
-                .as_mut()
-                .and_then(|cur| cur.checked_increment())?;
-            Some(id)
-        })
+        }
+        // Increment the next account identifier.
+        let mut next = id;
+        self.next_available_account = next.checked_increment().map(|_| next);
     }
 
     /// Samples keys and registers these in the ledger.
-    pub fn sample_keys_and_register<R: Rng>(
+    pub fn sample_keys_and_register<S: TransactionSigScheme, R: Rng>(
         &mut self,
-        ledger_params: &Parameters,
+        ledger_params: &Parameters<C, S>,
         rng: &mut R,
-    ) -> Option<(AccountId, AccountPublicKey, AccountSecretKey)> {
-        let (pub_key, secret_key) =
-            schnorr::Schnorr::keygen(&ledger_params.sig_params, rng).unwrap();
+    ) -> Result<(AccountId, AccountPublicKey, AccountSecretKey), LedgerError> {
+        let (pub_key, secret_key) = S::keygen(&ledger_params.sig_params, rng).unwrap();
+        self.register(pub_key).map(|id| (id, pub_key, secret_key))
+    }
+
+    /// Derives the secret key at `path` from `master_key` (see `hd`), and registers its
+    /// public key in the ledger. Fails with `Derivation` if the key cannot be derived,
+    /// with `DuplicatePublicKey` if the key at `path` is registered already, and
+    /// otherwise like `register`.
+    pub fn register_derived<S: TransactionSigScheme>(
+        &mut self,
+        ledger_params: &Parameters<C, S>,
+        master_key: &ExtendedSecretKey,
+        path: &DerivationPath,
+    ) -> Result<(AccountId, AccountPublicKey, AccountSecretKey), LedgerError> {
+        let secret_key = master_key
+            .derive_path(&ledger_params.sig_params, path)
+            .map_err(LedgerError::Derivation)?
+            .secret_key;
+        let pub_key = secret_key.public_key;
         self.register(pub_key).map(|id| (id, pub_key, secret_key))
     }
 
     /// Update the balance of `id` to `new_amount`.
-    /// Returns `Some(())` if an account with identifier `id` exists already, and `None`
-    /// otherwise.
-    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Option<()> {
+    /// Fails if no account with identifier `id` exists.
+    ///
+    /// This is not backed by any proof, so it should only be used to set up a ledger.
+    /// Afterwards, money should only enter the ledger via deposits, and leave it via
+    /// withdrawals (see `TransactionKind`).
+    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Result<(), LedgerError> {
+        let mut account_info = *self
+            .id_to_account_info
+            .get(&id)
+            .ok_or(LedgerError::UnknownAccount)?;
+        account_info.balance = new_amount;
+        self.update_account_info(id, account_info)
+    }
+
+    /// Overwrite the information of `id` with `new_info`, and update the Merkle tree.
+    /// Fails if no account with identifier `id` exists.
+    fn update_account_info(
+        &mut self,
+        id: AccountId,
+        new_info: AccountInformation,
+    ) -> Result<(), LedgerError> {
         let tree = &mut self.account_merkle_tree;
-        self.id_to_account_info.get_mut(&id).map(|account_info| {
-            account_info.balance = new_amount;
-            tree.update(id.0 as usize, &account_info.to_bytes_le())
-                .expect("should exist");
-        })
+        let account_info = self
+            .id_to_account_info
+            .get_mut(&id)
+            .ok_or(LedgerError::UnknownAccount)?;
+        *account_info = new_info;
+        tree.update(id.leaf_index(), &account_info.to_bytes_le())
+            .expect("should exist");
+        Ok(())
+    }
+
+    /// Check that `transactions` can be applied to the current state one after the
+    /// other, like `apply_transaction` would, but verify all of their signatures at once
+    /// with `TransactionSigScheme::batch_verify`. If some transaction is invalid, we fall
+    /// back to checking them one by one, and return the index of the first invalid
+    /// transaction along with the reason why it is invalid.
+    ///
+    /// Each transaction is checked against the state left behind by the ones before it,
+    /// so a sender can have several transactions with consecutive nonces in the batch.
+    /// The transactions are applied to a copy of the state, which leaves `self` unchanged.
+    pub fn validate_batch<S: TransactionSigScheme, R: Rng>(
+        &self,
+        pp: &Parameters<C, S>,
+        transactions: &[Transaction<S>],
+        rng: &mut R,
+    ) -> Result<(), (usize, LedgerError)> {
+        let mut state = self.clone();
+        let mut signed = Vec::with_capacity(transactions.len());
+        let mut all_valid = true;
+        for tx in transactions {
+            if tx.check_validity_with(pp, &state, false).is_err() {
+                all_valid = false;
+                break;
+            }
+            if let Some(message) = tx.signed_message(&state) {
+                let sender_acc_info = &state.id_to_account_info[&tx.sender];
+                let signatures = tx.authorizing_signatures(pp, sender_acc_info).unwrap();
+                for (public_key, signature) in signatures {
+                    signed.push((public_key, message.clone(), signature));
+                }
+            }
+            if state.apply_updates(tx, true).is_err() {
+                all_valid = false;
+                break;
+            }
+        }
+        if all_valid {
+            let batch = signed
+                .iter()
+                .map(|(public_key, message, signature)| (*public_key, &message[..], *signature))
+                .collect::<Vec<_>>();
+            if S::batch_verify(&pp.sig_params, &batch, rng).unwrap() {
+                return Ok(());
+            }
+        }
+        // Find the culprit.
+        let mut state = self.clone();
+        for (i, tx) in transactions.iter().enumerate() {
+            state.apply_transaction(pp, tx).map_err(|e| (i, e))?;
+        }
+        Ok(())
     }
 
     /// Update the state by applying the transaction `tx`, if `tx` is valid.
-    pub fn apply_transaction(&mut self, pp: &Parameters, tx: &Transaction) -> Option<()> {
-        if tx.validate(pp, self) {
-            let old_sender_bal = self.id_to_account_info.get(&tx.sender)?.balance;
-            let old_receiver_bal = self.id_to_account_info.get(&tx.recipient)?.balance;
-            let new_sender_bal = old_sender_bal.checked_sub(tx.amount)?;
-            let new_receiver_bal = old_receiver_bal.checked_add(tx.amount)?;
-            self.update_balance(tx.sender, new_sender_bal);
-            self.update_balance(tx.recipient, new_receiver_bal);
-            Some(())
-        } else {
-            None
+    /// Applying a transaction increments the sender's nonce, so the same transaction
+    /// cannot be applied twice. The fee is credited to the fee collector immediately.
+    pub fn apply_transaction<S: TransactionSigScheme>(
+        &mut self,
+        pp: &Parameters<C, S>,
+        tx: &Transaction<S>,
+    ) -> Result<(), LedgerError> {
+        tx.check_validity(pp, self)?;
+        self.apply_updates(tx, true)
+    }
+
+    /// Like `apply_transaction`, but only debits the fee from the sender without
+    /// crediting it to the fee collector. This is used when the fees of a batch of
+    /// transactions are credited all at once afterwards, via `credit_fees`.
+    pub fn apply_transaction_deferring_fee<S: TransactionSigScheme>(
+        &mut self,
+        pp: &Parameters<C, S>,
+        tx: &Transaction<S>,
+    ) -> Result<(), LedgerError> {
+        tx.check_validity(pp, self)?;
+        self.apply_updates(tx, false)
+    }
+
+    /// Credit `deposit` to its recipient. Unlike transactions, deposits are not signed,
+    /// so the caller must make sure that `deposit` comes from the L1 deposit queue
+    /// (see `bridge::SimulatedL1`). Fails if the recipient does not exist, or if its
+    /// balance would overflow.
+    pub fn apply_deposit(&mut self, deposit: &Deposit) -> Result<(), LedgerError> {
+        self.apply_updates(&deposit.to_transaction::<SigScheme>(), false)
+    }
+
+    /// Credit `fees` to the fee collector.
+    /// Fails if the collector's balance would overflow, or if `fees` is non-zero and
+    /// no fee collector has been set.
+    pub fn credit_fees(&mut self, fees: Amount) -> Result<(), LedgerError> {
+        if fees == Amount(0) {
+            return Ok(());
+        }
+        let collector = self.fee_collector.ok_or(LedgerError::MissingFeeCollector)?;
+        let mut collector_info = *self
+            .id_to_account_info
+            .get(&collector)
+            .ok_or(LedgerError::UnknownAccount)?;
+        collector_info.balance = collector_info
+            .balance
+            .checked_add(fees)
+            .ok_or(LedgerError::Overflow)?;
+        self.update_account_info(collector, collector_info)
+    }
+
+    /// Apply the balance and nonce changes caused by `tx`, without validating it.
+    /// All updates are computed before any of them are written, so that the state
+    /// is left untouched if one of them fails (e.g., due to an overflow).
+    fn apply_updates<S: TransactionSigScheme>(
+        &mut self,
+        tx: &Transaction<S>,
+        credit_fee: bool,
+    ) -> Result<(), LedgerError> {
+        if let TransactionKind::Registration(public_key) = tx.kind {
+            self.apply_registration(public_key, PolicyDigest::default(), tx.sender, tx.recipient);
+            return Ok(());
         }
+        let mut updates = Vec::with_capacity(3);
+        // Deposits do not involve the sender, and withdrawals do not involve the recipient.
+        if tx.kind != TransactionKind::Deposit {
+            self.stage_update(
+                &mut updates,
+                tx.sender,
+                LedgerError::UnknownSender,
+                |info| {
+                    let total = tx.amount.checked_add(tx.fee).ok_or(LedgerError::Overflow)?;
+                    info.balance = info
+                        .balance
+                        .checked_sub(total)
+                        .ok_or(LedgerError::InsufficientBalance)?;
+                    info.nonce = info
+                        .nonce
+                        .checked_increment()
+                        .ok_or(LedgerError::Overflow)?;
+                    Ok(())
+                },
+            )?;
+        }
+        if tx.kind != TransactionKind::Withdrawal {
+            self.stage_update(
+                &mut updates,
+                tx.recipient,
+                LedgerError::UnknownRecipient,
+                |info| {
+                    info.balance = info
+                        .balance
+                        .checked_add(tx.amount)
+                        .ok_or(LedgerError::Overflow)?;
+                    Ok(())
+                },
+            )?;
+        }
+        if credit_fee && tx.fee != Amount(0) {
+            let collector = self.fee_collector.ok_or(LedgerError::MissingFeeCollector)?;
+            self.stage_update(
+                &mut updates,
+                collector,
+                LedgerError::UnknownAccount,
+                |info| {
+                    info.balance = info
+                        .balance
+                        .checked_add(tx.fee)
+                        .ok_or(LedgerError::Overflow)?;
+                    Ok(())
+                },
+            )?;
+        }
+        for (id, info) in updates {
+            self.update_account_info(id, info)?;
+        }
+        Ok(())
+    }
+
+    /// Modify the staged information of `id` via `f`, staging the account's current
+    /// information first if it is not in `updates` yet. Fails with `unknown` if there
+    /// is no account with identifier `id`.
+    fn stage_update(
+        &self,
+        updates: &mut Vec<(AccountId, AccountInformation)>,
+        id: AccountId,
+        unknown: LedgerError,
+        f: impl FnOnce(&mut AccountInformation) -> Result<(), LedgerError>,
+    ) -> Result<(), LedgerError> {
+        let position = match updates.iter().position(|(staged_id, _)| *staged_id == id) {
+            Some(position) => position,
+            None => {
+                updates.push((id, *self.id_to_account_info.get(&id).ok_or(unknown)?));
+                updates.len() - 1
+            }
+        };
+        f(&mut updates[position].1)
+    }
+}
+
+/// A serialized `State` consists of the account Merkle tree's hash parameters and height,
+/// the accounts, and the tree's root. The tree is rebuilt from the accounts when
+/// deserializing, and deserialization fails if it does not have the stored root.
+impl<C: AccountMerkleConfig> CanonicalSerialize for State<C> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        let tree = &self.account_merkle_tree;
+        C::serialize_params(
+            tree.leaf_hash_param(),
+            tree.two_to_one_hash_param(),
+            &mut writer,
+        )?;
+        tree.height().serialize(&mut writer)?;
+        self.next_available_account.serialize(&mut writer)?;
+        self.fee_collector.serialize(&mut writer)?;
+        self.sorted_accounts().serialize(&mut writer)?;
+        self.root().serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        let tree = &self.account_merkle_tree;
+        C::params_serialized_size(tree.leaf_hash_param(), tree.two_to_one_hash_param())
+            + tree.height().serialized_size()
+            + self.next_available_account.serialized_size()
+            + self.fee_collector.serialized_size()
+            + self.sorted_accounts().serialized_size()
+            + self.root().serialized_size()
+    }
+}
+
+impl<C: AccountMerkleConfig> CanonicalDeserialize for State<C> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let (leaf_crh_params, two_to_one_crh_params) = C::deserialize_params(&mut reader)?;
+        let height = usize::deserialize(&mut reader)?;
+        if height > MAX_TREE_HEIGHT + 1 {
+            return Err(SerializationError::InvalidData);
+        }
+        let next_available_account = Option::<AccountId>::deserialize(&mut reader)?;
+        let fee_collector = Option::<AccountId>::deserialize(&mut reader)?;
+        let accounts = Vec::<(AccountId, AccountInformation)>::deserialize(&mut reader)?;
+        let root = AccRoot::deserialize(&mut reader)?;
+
+        let mut state = Self {
+            next_available_account,
+            account_merkle_tree: SparseMerkleTree::blank(
+                &leaf_crh_params,
+                &two_to_one_crh_params,
+                height,
+            )
+            .map_err(|_| SerializationError::InvalidData)?,
+            id_to_account_info: HashMap::with_capacity(accounts.len()),
+            pub_key_to_id: HashMap::with_capacity(accounts.len()),
+            registry: BTreeMap::new(),
+            fee_collector,
+        };
+        for (id, account_info) in accounts {
+            let is_new_id =
+                id != AccountId(0) && state.id_to_account_info.insert(id, account_info).is_none();
+            let is_new_key = state
+                .pub_key_to_id
+                .insert(account_info.public_key, id)
+                .is_none();
+            let key = RegistryKey::of(&account_info.public_key);
+            let is_new_registry_key = state.registry.insert(key, id).is_none();
+            if !is_new_id || !is_new_key || !is_new_registry_key {
+                return Err(SerializationError::InvalidData);
+            }
+            state
+                .account_merkle_tree
+                .update(id.leaf_index(), &account_info.to_bytes_le())
+                .map_err(|_| SerializationError::InvalidData)?;
+        }
+        state.write_registry_sentinel();
+        // Every account must point to the next key in the registry.
+        let mut next_keys = state.registry.keys().skip(1).copied();
+        let registry_is_sorted = state.registry.values().all(|id| {
+            state.id_to_account_info[id].next_key
+                == next_keys.next().unwrap_or_else(RegistryKey::max)
+        });
+        let fee_collector_exists = state
+            .fee_collector
+            .is_none_or(|id| state.id_to_account_info.contains_key(&id));
+        if state.root() != root || !fee_collector_exists || !registry_is_sorted {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(state)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{AccountId, Amount, Parameters, State};
+    use super::{
+        AccountId, Amount, EdDSASigScheme, LedgerError, MerkleConfig, Parameters, SchnorrSigScheme,
+        SigScheme, State, TransactionSigScheme,
+    };
+    use crate::account::{Nonce, RegistryKey};
+    use crate::hd::{self, DerivationPath, ExtendedSecretKey};
+    use crate::signature::SignatureScheme;
     use crate::transaction::Transaction;
 
     #[test]
@@ -204,26 +1063,913 @@ mod test {
             .update_balance(alice_id, Amount(10))
             .expect("Alice's account should exist");
         // Let's make an account for Bob.
-        let (bob_id, _bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        // Bob cannot register a second account with the same key.
+        assert_eq!(state.register(bob_pk), Err(LedgerError::DuplicatePublicKey));
+        assert_eq!(
+            state.update_balance(AccountId(10), Amount(1)),
+            Err(LedgerError::UnknownAccount)
+        );
 
         // Alice wants to transfer 5 units to Bob.
-        let tx1 = Transaction::create(&pp, alice_id, bob_id, Amount(5), &alice_sk, &mut rng);
+        let tx1 = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(tx1.validate(&pp, &state));
         state.apply_transaction(&pp, &tx1).expect("should work");
         // Let's try creating invalid transactions:
         // First, let's try a transaction where the amount is larger than Alice's balance.
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(6), &alice_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(6),
+            Amount(0),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::InsufficientBalance)
+        );
         // Next, let's try a transaction where the signature is incorrect:
-        let bad_tx = Transaction::create(&pp, alice_id, bob_id, Amount(5), &bob_sk, &mut rng);
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(1),
+            &bob_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::InvalidSignature)
+        );
 
-        // Finally, let's try a transaction to an non-existant account:
-        let bad_tx =
-            Transaction::create(&pp, alice_id, AccountId(10), Amount(5), &alice_sk, &mut rng);
+        // Next, let's try a transaction to an non-existant account:
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            AccountId(10),
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
         assert!(!bad_tx.validate(&pp, &state));
-        assert!(matches!(state.apply_transaction(&pp, &bad_tx), None));
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::UnknownRecipient)
+        );
+
+        // Finally, let's try replaying the first transaction:
+        assert!(!tx1.validate(&pp, &state));
+        assert_eq!(
+            state.apply_transaction(&pp, &tx1),
+            Err(LedgerError::InvalidNonce {
+                expected: Nonce(1),
+                found: Nonce(0)
+            })
+        );
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(5));
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+    }
+
+    #[test]
+    fn nonces_are_sequential() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // A transaction from the future is rejected...
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(1),
+            Amount(0),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!tx.validate(&pp, &state));
+        // ... until the transactions before it have been applied.
+        for nonce in 0..3 {
+            let tx = Transaction::create(
+                &pp,
+                alice_id,
+                bob_id,
+                &bob_pk,
+                Amount(1),
+                Amount(0),
+                Nonce(nonce),
+                &alice_sk,
+                &mut rng,
+            );
+            state.apply_transaction(&pp, &tx).expect("should work");
+            assert_eq!(state.id_to_account_info[&alice_id].nonce, Nonce(nonce + 1));
+        }
+        // Receiving funds does not change the recipient's nonce.
+        assert_eq!(state.id_to_account_info[&bob_id].nonce, Nonce(0));
+
+        // A self-transfer is rejected, and does not use up the nonce.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            alice_id,
+            &alice_pk,
+            Amount(2),
+            Amount(0),
+            Nonce(3),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            state.apply_transaction(&pp, &tx),
+            Err(LedgerError::SelfTransfer)
+        );
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(7));
+        assert_eq!(state.id_to_account_info[&alice_id].nonce, Nonce(3));
+    }
+
+    #[test]
+    fn fees_are_paid_to_collector() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (operator_id, _operator_pk, _operator_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Without a fee collector, only fee-less transactions are valid.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            tx.check_validity(&pp, &state),
+            Err(LedgerError::MissingFeeCollector)
+        );
+        assert_eq!(
+            state.set_fee_collector(AccountId(10)),
+            Err(LedgerError::UnknownAccount)
+        );
+        state.set_fee_collector(operator_id).unwrap();
+        assert!(tx.validate(&pp, &state));
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&alice_id].balance, Amount(4));
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+        assert_eq!(state.id_to_account_info[&operator_id].balance, Amount(1));
+
+        // The fee counts towards the sender's spending.
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(4),
+            Amount(1),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        // An overflowing amount and fee is rejected rather than wrapping around.
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(u64::MAX),
+            Amount(1),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(!bad_tx.validate(&pp, &state));
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::Overflow)
+        );
+
+        // If the recipient's balance would overflow, nothing changes, not even the fee.
+        let root = state.root();
+        state.update_balance(bob_id, Amount(u64::MAX)).unwrap();
+        let root_with_rich_bob = state.root();
+        let bad_tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(1),
+            Amount(1),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::Overflow)
+        );
+        assert_eq!(state.root(), root_with_rich_bob);
+        assert_ne!(state.root(), root);
+
+        // Deferring the fee and crediting it later has the same effect.
+        state.update_balance(bob_id, Amount(5)).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(1),
+            Amount(2),
+            Nonce(1),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut deferred_state = state.clone();
+        state.apply_transaction(&pp, &tx).unwrap();
+        deferred_state
+            .apply_transaction_deferring_fee(&pp, &tx)
+            .unwrap();
+        assert_eq!(
+            deferred_state.id_to_account_info[&operator_id].balance,
+            Amount(1)
+        );
+        deferred_state.credit_fees(tx.fee).unwrap();
+        assert_eq!(deferred_state.root(), state.root());
+    }
+
+    /// Checks that `State::validate_batch` accepts a valid batch of transactions signed
+    /// with `S`, and finds the invalid transaction in a batch otherwise.
+    fn validate_batch_finds_invalid_transaction_with<S: TransactionSigScheme>() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<MerkleConfig, S>::sample_with_schemes(0, &mut rng);
+        let mut state = State::new(32, &pp);
+        let accounts = (0..4)
+            .map(|_| {
+                let (id, _pk, sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+                state.update_balance(id, Amount(10)).unwrap();
+                (id, sk)
+            })
+            .collect::<Vec<_>>();
+        // Every account pays the next one.
+        let transactions = accounts
+            .iter()
+            .zip(accounts.iter().cycle().skip(1))
+            .map(|((sender, sk), (recipient, recipient_sk))| {
+                Transaction::create(
+                    &pp,
+                    *sender,
+                    *recipient,
+                    &recipient_sk.public_key,
+                    Amount(5),
+                    Amount(0),
+                    Nonce(0),
+                    sk,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(state.validate_batch(&pp, &transactions, &mut rng), Ok(()));
+
+        // Deposits are not signed, so anyone could append one to the batch.
+        let mut bad_transactions = transactions.clone();
+        bad_transactions.push(Transaction::create_deposit(accounts[0].0, Amount(3)));
+        assert_eq!(
+            state.validate_batch(&pp, &bad_transactions, &mut rng),
+            Err((4, LedgerError::SelfIssuedDeposit))
+        );
+
+        // The third transaction is signed with the wrong key.
+        let mut bad_transactions = transactions.clone();
+        bad_transactions[2] = Transaction::create(
+            &pp,
+            accounts[2].0,
+            accounts[3].0,
+            &accounts[3].1.public_key,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &accounts[1].1,
+            &mut rng,
+        );
+        assert_eq!(
+            state.validate_batch(&pp, &bad_transactions, &mut rng),
+            Err((2, LedgerError::InvalidSignature))
+        );
+
+        // The second transaction carries a stale nonce.
+        let mut bad_transactions = transactions;
+        bad_transactions[1] = Transaction::create(
+            &pp,
+            accounts[1].0,
+            accounts[2].0,
+            &accounts[2].1.public_key,
+            Amount(5),
+            Amount(0),
+            Nonce(1),
+            &accounts[1].1,
+            &mut rng,
+        );
+        assert_eq!(
+            state.validate_batch(&pp, &bad_transactions, &mut rng),
+            Err((
+                1,
+                LedgerError::InvalidNonce {
+                    expected: Nonce(0),
+                    found: Nonce(1)
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn validate_batch_applies_transactions_in_order() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+
+        // Alice pays Bob three times, with consecutive nonces.
+        let mut transactions = (0..3)
+            .map(|nonce| {
+                Transaction::create(
+                    &pp,
+                    alice_id,
+                    bob_id,
+                    &bob_pk,
+                    Amount(3),
+                    Amount(0),
+                    Nonce(nonce),
+                    &alice_sk,
+                    &mut rng,
+                )
+            })
+            .collect::<Vec<_>>();
+        let root = state.root();
+        assert_eq!(state.validate_batch(&pp, &transactions, &mut rng), Ok(()));
+        assert_eq!(state.root(), root);
+
+        // A fourth payment cannot be afforded after the first three.
+        transactions.push(Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(3),
+            Amount(0),
+            Nonce(3),
+            &alice_sk,
+            &mut rng,
+        ));
+        assert_eq!(
+            state.validate_batch(&pp, &transactions, &mut rng),
+            Err((3, LedgerError::InsufficientBalance))
+        );
+
+        // Replaying a transaction within the batch is caught too.
+        let replayed = [transactions[0].clone(), transactions[0].clone()];
+        assert_eq!(
+            state.validate_batch(&pp, &replayed, &mut rng),
+            Err((
+                1,
+                LedgerError::InvalidNonce {
+                    expected: Nonce(1),
+                    found: Nonce(0)
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn validate_batch_finds_invalid_transaction() {
+        validate_batch_finds_invalid_transaction_with::<SchnorrSigScheme>();
+    }
+
+    #[test]
+    fn validate_batch_finds_invalid_eddsa_transaction() {
+        validate_batch_finds_invalid_transaction_with::<EdDSASigScheme>();
+    }
+
+    #[test]
+    fn multisig_accounts_need_enough_approvals() {
+        use crate::account::MultisigPolicy;
+        use crate::transaction::TransactionKind;
+        use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (bob_id, bob_pk, bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let signers = (0..3)
+            .map(|_| SigScheme::keygen(&pp.sig_params, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let public_keys = signers.iter().map(|(pk, _)| *pk).collect::<Vec<_>>();
+        assert_eq!(
+            MultisigPolicy::new(4, public_keys.clone()),
+            Err(LedgerError::InvalidPolicy)
+        );
+        assert_eq!(
+            MultisigPolicy::new(1, vec![bob_pk, bob_pk]),
+            Err(LedgerError::InvalidPolicy)
+        );
+        let policy = MultisigPolicy::new(2, public_keys).unwrap();
+        let treasury_id = state.register_multisig(&policy).unwrap();
+        assert_eq!(
+            state.register_multisig(&policy),
+            Err(LedgerError::DuplicatePublicKey)
+        );
+        state.update_balance(treasury_id, Amount(10)).unwrap();
+
+        let transfer = |signers: &[&_], rng: &mut _| {
+            Transaction::create_multisig(
+                &pp,
+                TransactionKind::Transfer,
+                treasury_id,
+                bob_id,
+                &bob_pk,
+                Amount(5),
+                Amount(0),
+                Nonce(0),
+                &policy,
+                signers,
+                rng,
+            )
+        };
+        // One approval is not enough, ...
+        let tx = transfer(&[&signers[1].1], &mut rng);
+        assert_eq!(
+            tx.check_validity(&pp, &state),
+            Err(LedgerError::InsufficientApprovals)
+        );
+        // ... neither is a signature under the account's public key, ...
+        let mut tx = transfer(&[&signers[0].1, &signers[2].1], &mut rng);
+        let mut bad_tx = tx.clone();
+        bad_tx.multisig = None;
+        assert_eq!(
+            bad_tx.check_validity(&pp, &state),
+            Err(LedgerError::InvalidPolicy)
+        );
+        // ... and the approvals must be valid.
+        let mut bad_tx = tx.clone();
+        let approval = bad_tx.multisig.as_mut().unwrap();
+        approval.signatures[1] = approval.signatures[0].clone();
+        assert_eq!(
+            bad_tx.check_validity(&pp, &state),
+            Err(LedgerError::InvalidSignature)
+        );
+        assert_eq!(
+            state.validate_batch(&pp, &[tx.clone(), bad_tx], &mut rng),
+            Err((1, LedgerError::InvalidSignature))
+        );
+        // Two approvals are enough.
+        let mut tx_bytes = Vec::new();
+        tx.serialize(&mut tx_bytes).unwrap();
+        assert_eq!(tx_bytes.len(), tx.serialized_size());
+        tx = Transaction::deserialize(&tx_bytes[..]).unwrap();
+        assert_eq!(state.validate_batch(&pp, &[tx.clone()], &mut rng), Ok(()));
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+
+        // Accounts without a policy cannot send multisig transactions.
+        let tx = Transaction::create_multisig(
+            &pp,
+            TransactionKind::Transfer,
+            bob_id,
+            treasury_id,
+            &policy.account_key(),
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &policy,
+            &[&bob_sk],
+            &mut rng,
+        );
+        assert_eq!(
+            tx.check_validity(&pp, &state),
+            Err(LedgerError::InvalidPolicy)
+        );
+    }
+
+    #[test]
+    fn large_tree() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new((1 << 20) - 1, &pp);
+        assert_eq!(state.account_merkle_tree.num_leaves(), 1 << 20);
+        // Skip ahead to the last two identifiers that fit in the tree.
+        state.next_available_account = Some(AccountId((1 << 20) - 2));
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(bob_id, AccountId((1 << 20) - 1));
+        // The tree is full now.
+        assert_eq!(
+            state.sample_keys_and_register(&pp, &mut rng).err(),
+            Some(LedgerError::LedgerFull)
+        );
+
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).expect("should work");
+        assert_eq!(state.id_to_account_info[&bob_id].balance, Amount(5));
+    }
+
+    #[test]
+    fn registrations_keep_the_registry_sorted() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        for _ in 0..5 {
+            let _ = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        }
+
+        // Registering via a transaction works just like `State::register`.
+        let (public_key, _) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let tx = Transaction::create_registration(&state, public_key).unwrap();
+        assert_eq!(tx.recipient, AccountId(6));
+        let mut bad_tx = tx.clone();
+        bad_tx.recipient = AccountId(7);
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::InvalidRegistration)
+        );
+        let mut bad_tx = tx.clone();
+        bad_tx.sender = AccountId(tx.sender.0 ^ 1);
+        assert_eq!(
+            state.apply_transaction(&pp, &bad_tx),
+            Err(LedgerError::InvalidRegistration)
+        );
+        state.apply_transaction(&pp, &tx).unwrap();
+        assert_eq!(state.pub_key_to_id[&public_key], AccountId(6));
+        assert_eq!(
+            state.apply_transaction(&pp, &tx),
+            Err(LedgerError::DuplicatePublicKey)
+        );
+
+        // Starting at the sentinel, the leaves form a sorted list of all public keys.
+        let mut info = state.leaf_info(AccountId(0)).unwrap();
+        let mut keys = Vec::new();
+        while info.next_key != RegistryKey::max() {
+            let id = state.registry[&info.next_key];
+            info = state.leaf_info(id).unwrap();
+            keys.push(RegistryKey::of(&info.public_key));
+        }
+        assert_eq!(keys.len(), 6);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        // The sentinel is part of the tree.
+        let path = state.account_merkle_tree.generate_proof(0).unwrap();
+        assert!(path
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &state.leaf_info(AccountId(0)).unwrap().to_bytes_le(),
+            )
+            .unwrap());
+    }
+
+    #[test]
+    fn registrations_reject_keys_outside_the_subgroup() {
+        use crate::account::MultisigPolicy;
+        use ark_ed_on_bls12_381::{EdwardsAffine, Fq};
+        use ark_ff::{One, Zero};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (public_key, _) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        // (0, -1) has order 2, so adding it takes the key out of the prime-order subgroup.
+        let torsion = EdwardsAffine::new(Fq::zero(), -Fq::one());
+        let bad_key = public_key + torsion;
+        assert!(bad_key.is_on_curve());
+        assert_eq!(state.register(bad_key), Err(LedgerError::InvalidPublicKey));
+        assert_eq!(
+            Transaction::<SigScheme>::create_registration(&state, bad_key).unwrap_err(),
+            LedgerError::InvalidPublicKey
+        );
+        assert_eq!(
+            MultisigPolicy::new(1, vec![public_key, bad_key]),
+            Err(LedgerError::InvalidPolicy)
+        );
+        state.register(public_key).unwrap();
+    }
+
+    #[test]
+    fn derived_keys_are_recovered_from_the_seed() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let seed = [7u8; 32];
+        let master_key = ExtendedSecretKey::from_seed(&pp.sig_params, &seed).unwrap();
+        let alice_path = "m/0'".parse::<DerivationPath>().unwrap();
+        let bob_path = "m/1'".parse::<DerivationPath>().unwrap();
+        let (alice_id, _alice_pk, _alice_sk) = state
+            .register_derived(&pp, &master_key, &alice_path)
+            .unwrap();
+        let (bob_id, bob_pk, _bob_sk) =
+            state.register_derived(&pp, &master_key, &bob_path).unwrap();
+        assert_ne!(alice_id, bob_id);
+        assert_eq!(
+            state
+                .register_derived(&pp, &master_key, &bob_path)
+                .unwrap_err(),
+            LedgerError::DuplicatePublicKey
+        );
+        state.update_balance(alice_id, Amount(10)).unwrap();
+
+        // After losing Alice's key, the wallet derives it again from the seed, and it can
+        // still spend from her account.
+        let alice_sk = hd::derive_secret_key(&pp.sig_params, &seed, &alice_path).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(state.apply_transaction(&pp, &tx).is_ok());
+    }
+
+    #[test]
+    fn signatures_bind_public_keys() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        // On a fork of the ledger, the account that Bob has belongs to Carol instead.
+        let mut fork = state.clone();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (carol_id, carol_pk, _carol_sk) = fork.sample_keys_and_register(&pp, &mut rng).unwrap();
+        assert_eq!(bob_id, carol_id);
+
+        // A payment to Bob cannot be replayed to pay Carol.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(tx.validate(&pp, &state));
+        assert_ne!(tx.signed_message(&state), tx.signed_message(&fork));
+        assert_eq!(
+            tx.check_validity(&pp, &fork),
+            Err(LedgerError::InvalidSignature)
+        );
+        assert_eq!(
+            state.validate_batch(&pp, std::slice::from_ref(&tx), &mut rng),
+            Ok(())
+        );
+        assert_eq!(
+            fork.validate_batch(&pp, &[tx], &mut rng),
+            Err((0, LedgerError::InvalidSignature))
+        );
+
+        // A payment to Carol is only valid where the account belongs to her.
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            carol_id,
+            &carol_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(tx.validate(&pp, &fork));
+        assert_eq!(
+            tx.check_validity(&pp, &state),
+            Err(LedgerError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn signatures_do_not_replay_across_chains() {
+        use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+        // Two deployments with the same hash parameters, and thus the same account layout,
+        // which only differ in their chain IDs.
+        let pp = Parameters::sample_for_chain(1, &mut ark_std::test_rng());
+        let other_pp = Parameters::sample_for_chain(2, &mut ark_std::test_rng());
+        assert_ne!(pp.sig_params.salt, other_pp.sig_params.salt);
+        assert_eq!(Parameters::domain_tag(1), pp.sig_params.salt.unwrap());
+
+        let mut rng = ark_std::test_rng();
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let other_state = state.clone();
+        assert_eq!(State::new(32, &other_pp).root(), State::new(32, &pp).root());
+
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(tx.validate(&pp, &state));
+        assert_eq!(
+            tx.check_validity(&other_pp, &other_state),
+            Err(LedgerError::InvalidSignature)
+        );
+        let other_tx = Transaction::create(
+            &other_pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        assert!(other_tx.validate(&other_pp, &other_state));
+        assert!(!other_tx.validate(&pp, &state));
+
+        // The chain ID must match the salt of the signature parameters.
+        let mut pp_bytes = Vec::new();
+        pp.serialize(&mut pp_bytes).unwrap();
+        assert_eq!(
+            Parameters::<MerkleConfig>::deserialize(&pp_bytes[..])
+                .unwrap()
+                .chain_id,
+            1
+        );
+        pp_bytes[0] = 2;
+        assert!(Parameters::<MerkleConfig>::deserialize(&pp_bytes[..]).is_err());
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut pp_bytes = Vec::new();
+        pp.serialize(&mut pp_bytes).unwrap();
+        assert_eq!(pp_bytes.len(), pp.serialized_size());
+        let pp: Parameters = Parameters::deserialize(&pp_bytes[..]).unwrap();
+
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        state.set_fee_collector(bob_id).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(1),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        let mut tx_bytes = Vec::new();
+        tx.serialize(&mut tx_bytes).unwrap();
+        assert_eq!(tx_bytes.len(), tx.serialized_size());
+        let tx = Transaction::deserialize(&tx_bytes[..]).unwrap();
+        state.apply_transaction(&pp, &tx).unwrap();
+
+        let mut state_bytes = Vec::new();
+        state.serialize(&mut state_bytes).unwrap();
+        assert_eq!(state_bytes.len(), state.serialized_size());
+        let restored: State = State::deserialize(&state_bytes[..]).unwrap();
+        assert_eq!(restored.root(), state.root());
+        assert_eq!(restored.id_to_account_info, state.id_to_account_info);
+        assert_eq!(restored.pub_key_to_id, state.pub_key_to_id);
+        assert_eq!(restored.registry, state.registry);
+        assert_eq!(
+            restored.next_available_account,
+            state.next_available_account
+        );
+        assert_eq!(restored.fee_collector, Some(bob_id));
+
+        // A state whose accounts do not match the stored root is rejected.
+        let root_size = state.root().serialized_size();
+        let root_start = state_bytes.len() - root_size;
+        state_bytes[root_start] ^= 1;
+        assert!(State::<MerkleConfig>::deserialize(&state_bytes[..]).is_err());
+    }
+
+    #[test]
+    fn poseidon_ledger() {
+        use super::PoseidonMerkleConfig;
+        use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::<PoseidonMerkleConfig>::sample_with_schemes(0, &mut rng);
+        let mut state = State::new(32, &pp);
+        let (alice_id, alice_pk, alice_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let (bob_id, bob_pk, _bob_sk) = state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        state.update_balance(alice_id, Amount(10)).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(5),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        state.apply_transaction(&pp, &tx).unwrap();
+        let bob_info = state.leaf_info(bob_id).unwrap();
+        assert_eq!(bob_info.balance, Amount(5));
+        let bob_path = state
+            .account_merkle_tree
+            .generate_proof(bob_id.0 as usize)
+            .unwrap();
+        assert!(bob_path
+            .verify(
+                &pp.leaf_crh_params,
+                &pp.two_to_one_crh_params,
+                &state.root(),
+                &bob_info.to_bytes_le(),
+            )
+            .unwrap());
+
+        // The Pedersen and Poseidon trees of the same accounts have different roots.
+        let pedersen_pp = Parameters::sample(&mut rng);
+        let mut pedersen_state = State::new(32, &pedersen_pp);
+        pedersen_state.register(alice_pk).unwrap();
+        assert_ne!(pedersen_state.root(), state.root());
+
+        let mut pp_bytes = Vec::new();
+        pp.serialize(&mut pp_bytes).unwrap();
+        let restored_pp = Parameters::<PoseidonMerkleConfig>::deserialize(&pp_bytes[..]).unwrap();
+        let mut state_bytes = Vec::new();
+        state.serialize(&mut state_bytes).unwrap();
+        let restored = State::<PoseidonMerkleConfig>::deserialize(&state_bytes[..]).unwrap();
+        assert_eq!(restored.root(), state.root());
+        // Alice's nonce has moved on, so the transaction cannot be replayed.
+        assert!(!tx.validate(&restored_pp, &restored));
     }
 }
@@ -1,5 +1,10 @@
 pub mod account;
+pub mod bridge;
+pub mod hd;
 pub mod ledger;
+pub mod poseidon;
+pub mod sparse_merkle_tree;
+pub mod store;
 pub mod transaction;
 
 pub mod random_oracle;
@@ -0,0 +1,180 @@
+use super::{bytes_per_element, InputSize, Parameters, TwoToOneCRH, CRH, RATE, WIDTH};
+use ark_crypto_primitives::crh::constraints::{
+    CRHGadget as CRHGadgetTrait, TwoToOneCRHGadget as TwoToOneCRHGadgetTrait,
+};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{Namespace, SynthesisError};
+use ark_std::marker::PhantomData;
+use ark_std::vec::Vec;
+
+use core::borrow::Borrow;
+
+/// The Poseidon parameters are always constants of the circuit.
+#[derive(Clone)]
+pub struct ParametersVar<F: PrimeField> {
+    pub parameters: Parameters<F>,
+}
+
+impl<F: PrimeField> ParametersVar<F> {
+    /// Apply the Poseidon permutation to `state`. Each S-box costs three constraints,
+    /// while adding the round constants and mixing the state are free.
+    pub fn permute(&self, state: &mut [FpVar<F>]) -> Result<(), SynthesisError> {
+        // `x^ALPHA = x^5 = (x^2)^2 * x`.
+        let sbox =
+            |x: &FpVar<F>| -> Result<FpVar<F>, SynthesisError> { Ok(x.square()?.square()? * x) };
+        for (round, constants) in self.parameters.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants) {
+                *s += *c;
+            }
+            if Parameters::<F>::is_full_round(round) {
+                for s in state.iter_mut() {
+                    *s = sbox(s)?;
+                }
+            } else {
+                state[0] = sbox(&state[0])?;
+            }
+            let mixed = self
+                .parameters
+                .mds
+                .iter()
+                .map(|row| {
+                    state
+                        .iter()
+                        .zip(row)
+                        .fold(FpVar::zero(), |acc, (s, entry)| acc + s * *entry)
+                })
+                .collect::<Vec<_>>();
+            state.clone_from_slice(&mixed);
+        }
+        Ok(())
+    }
+
+    /// Hash `elements` with a sponge whose capacity element starts out as `domain`,
+    /// like `Parameters::hash`.
+    pub fn hash(&self, domain: F, elements: &[FpVar<F>]) -> Result<FpVar<F>, SynthesisError> {
+        let mut state = vec![FpVar::zero(); WIDTH];
+        state[0] = FpVar::constant(domain);
+        for chunk in elements.chunks(RATE) {
+            for (s, e) in state[1..].iter_mut().zip(chunk) {
+                *s += e;
+            }
+            self.permute(&mut state)?;
+        }
+        Ok(state[1].clone())
+    }
+}
+
+/// Read `bytes` as a little-endian integer modulo the field size, like
+/// `PrimeField::from_le_bytes_mod_order`. This only takes linear combinations of the bits
+/// of `bytes`, and so adds no constraints.
+pub fn bytes_to_fp_var_mod_order<F: PrimeField>(
+    bytes: &[UInt8<F>],
+) -> Result<FpVar<F>, SynthesisError> {
+    let chunk_size = bytes_per_element::<F>();
+    let shift = F::from(2u64).pow([8 * chunk_size as u64]);
+    bytes
+        .chunks(chunk_size)
+        .rev()
+        .try_fold(FpVar::zero(), |acc, chunk| {
+            Ok(acc * shift + Boolean::le_bits_to_fp_var(&chunk.to_bits_le()?)?)
+        })
+}
+
+pub struct CRHGadget<F: PrimeField, I: InputSize> {
+    _field: PhantomData<F>,
+    _input_size: PhantomData<I>,
+}
+
+impl<F: PrimeField, I: InputSize> CRHGadgetTrait<CRH<F, I>, F> for CRHGadget<F, I> {
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        assert!(input.len() <= I::NUM_BYTES);
+        let mut padded = input.to_vec();
+        padded.resize(I::NUM_BYTES, UInt8::constant(0));
+        let elements = padded
+            .chunks(bytes_per_element::<F>())
+            .map(bytes_to_fp_var_mod_order)
+            .collect::<Result<Vec<_>, _>>()?;
+        parameters.hash(F::from(I::NUM_BYTES as u64), &elements)
+    }
+}
+
+pub struct TwoToOneCRHGadget<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField> TwoToOneCRHGadgetTrait<TwoToOneCRH<F>, F> for TwoToOneCRHGadget<F> {
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        left_input: &[UInt8<F>],
+        right_input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let left = bytes_to_fp_var_mod_order(left_input)?;
+        let right = bytes_to_fp_var_mod_order(right_input)?;
+        parameters.hash(F::zero(), &[left, right])
+    }
+}
+
+impl<F: PrimeField> AllocVar<Parameters<F>, F> for ParametersVar<F> {
+    fn new_variable<T: Borrow<Parameters<F>>>(
+        _cs: impl Into<Namespace<F>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        _mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        let parameters = f()?.borrow().clone();
+        Ok(Self { parameters })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_bls12_381::Fr;
+    use ark_crypto_primitives::crh::{TwoToOneCRH as TwoToOneCRHTrait, CRH as CRHTrait};
+    use ark_ff::to_bytes;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    struct TestInput;
+    impl InputSize for TestInput {
+        const NUM_BYTES: usize = 80;
+    }
+
+    #[test]
+    fn poseidon_gadgets_match_native_hashes() {
+        let pp = Parameters::<Fr>::new();
+        let input = [7u8; 70];
+        let leaf = <CRH<Fr, TestInput> as CRHTrait>::evaluate(&pp, &input).unwrap();
+        let node = <TwoToOneCRH<Fr> as TwoToOneCRHTrait>::evaluate(
+            &pp,
+            &to_bytes![leaf].unwrap(),
+            &to_bytes![-leaf].unwrap(),
+        )
+        .unwrap();
+
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let pp_var = ParametersVar::new_constant(cs.clone(), &pp).unwrap();
+        let input_var = UInt8::new_witness_vec(cs.clone(), &input).unwrap();
+        let leaf_var =
+            <CRHGadget<Fr, TestInput> as CRHGadgetTrait<_, _>>::evaluate(&pp_var, &input_var)
+                .unwrap();
+        assert_eq!(leaf_var.value().unwrap(), leaf);
+        let node_var = <TwoToOneCRHGadget<Fr> as TwoToOneCRHGadgetTrait<_, _>>::evaluate(
+            &pp_var,
+            &leaf_var.to_bytes().unwrap(),
+            &leaf_var.negate().unwrap().to_bytes().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(node_var.value().unwrap(), node);
+        assert!(cs.is_satisfied().unwrap());
+    }
+}
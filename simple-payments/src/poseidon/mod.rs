@@ -0,0 +1,268 @@
+use ark_crypto_primitives::crh::{TwoToOneCRH as TwoToOneCRHTrait, CRH as CRHTrait};
+use ark_crypto_primitives::Error;
+use ark_ff::{BigInteger, PrimeField};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_std::marker::PhantomData;
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+use blake2::Blake2s;
+use digest::Digest;
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// The number of field elements in the Poseidon state.
+pub const WIDTH: usize = 3;
+/// The number of state elements that absorb input; the remaining one is the capacity.
+pub const RATE: usize = 2;
+/// The number of rounds that apply the S-box to the whole state. Half of them come before
+/// the partial rounds, and half of them after.
+pub const FULL_ROUNDS: usize = 8;
+/// The number of rounds that apply the S-box to the first state element only.
+pub const PARTIAL_ROUNDS: usize = 57;
+/// The S-box is `x -> x^ALPHA`, which is a permutation of the scalar field of BLS12-381.
+pub const ALPHA: u64 = 5;
+
+/// The round constants and MDS matrix of the Poseidon permutation.
+///
+/// These are derived deterministically from a fixed seed, so `Parameters::new` (and
+/// `Parameters::default`) always returns the same parameters.
+#[derive(Clone, Debug, PartialEq, Eq, CanonicalSerialize)]
+pub struct Parameters<F: PrimeField> {
+    /// `round_constants[i]` is added to the state at the start of round `i`.
+    pub round_constants: Vec<Vec<F>>,
+    /// The matrix that mixes the state at the end of every round.
+    pub mds: Vec<Vec<F>>,
+}
+
+impl<F: PrimeField> Parameters<F> {
+    pub fn new() -> Self {
+        let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+            .map(|round| {
+                (0..WIDTH)
+                    .map(|i| {
+                        let mut h = Blake2s::new();
+                        h.update(b"ark-simple-payments/poseidon");
+                        h.update((round as u32).to_le_bytes());
+                        h.update((i as u32).to_le_bytes());
+                        F::from_le_bytes_mod_order(&h.finalize())
+                    })
+                    .collect()
+            })
+            .collect();
+        // A Cauchy matrix `1 / (x_i + y_j)`, with `x_i = i` and `y_j = WIDTH + j`, is MDS.
+        let mds = (0..WIDTH)
+            .map(|i| {
+                (0..WIDTH)
+                    .map(|j| F::from((i + WIDTH + j) as u64).inverse().unwrap())
+                    .collect()
+            })
+            .collect();
+        Self {
+            round_constants,
+            mds,
+        }
+    }
+
+    /// Is round `round` a full round?
+    pub fn is_full_round(round: usize) -> bool {
+        !(FULL_ROUNDS / 2..FULL_ROUNDS / 2 + PARTIAL_ROUNDS).contains(&round)
+    }
+
+    /// Apply the Poseidon permutation to `state`.
+    pub fn permute(&self, state: &mut [F; WIDTH]) {
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (s, c) in state.iter_mut().zip(constants) {
+                *s += c;
+            }
+            if Self::is_full_round(round) {
+                for s in state.iter_mut() {
+                    *s = s.pow([ALPHA]);
+                }
+            } else {
+                state[0] = state[0].pow([ALPHA]);
+            }
+            let mut mixed = [F::zero(); WIDTH];
+            for (m, row) in mixed.iter_mut().zip(&self.mds) {
+                for (s, entry) in state.iter().zip(row) {
+                    *m += *s * entry;
+                }
+            }
+            *state = mixed;
+        }
+    }
+
+    /// Hash `elements` with a sponge whose capacity element starts out as `domain`.
+    /// Callers hash a fixed number of elements for each `domain`.
+    pub fn hash(&self, domain: F, elements: &[F]) -> F {
+        let mut state = [F::zero(); WIDTH];
+        state[0] = domain;
+        for chunk in elements.chunks(RATE) {
+            for (s, e) in state[1..].iter_mut().zip(chunk) {
+                *s += e;
+            }
+            self.permute(&mut state);
+        }
+        state[1]
+    }
+}
+
+impl<F: PrimeField> Default for Parameters<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Deserialization checks that the parameters have the right dimensions, but not that
+/// they equal `Parameters::new()`.
+impl<F: PrimeField> CanonicalDeserialize for Parameters<F> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let round_constants = Vec::<Vec<F>>::deserialize(&mut reader)?;
+        let mds = Vec::<Vec<F>>::deserialize(&mut reader)?;
+        if round_constants.len() != FULL_ROUNDS + PARTIAL_ROUNDS
+            || mds.len() != WIDTH
+            || round_constants.iter().chain(&mds).any(|v| v.len() != WIDTH)
+        {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(Self {
+            round_constants,
+            mds,
+        })
+    }
+}
+
+/// The number of input bytes that we pack into one field element, such that
+/// every packed element is smaller than the field size.
+pub fn bytes_per_element<F: PrimeField>() -> usize {
+    (F::size_in_bits() - 1) / 8
+}
+
+/// The number of bytes that a `CRH` hashes. Shorter inputs are padded with zeros.
+pub trait InputSize {
+    const NUM_BYTES: usize;
+}
+
+/// A Poseidon hash of byte strings of up to `I::NUM_BYTES` bytes. It packs the
+/// (zero-padded) input into field elements, and absorbs them into a sponge whose capacity
+/// element starts out as `I::NUM_BYTES`.
+pub struct CRH<F: PrimeField, I: InputSize> {
+    _field: PhantomData<F>,
+    _input_size: PhantomData<I>,
+}
+
+impl<F: PrimeField, I: InputSize> CRHTrait for CRH<F, I> {
+    const INPUT_SIZE_BITS: usize = I::NUM_BYTES * 8;
+    type Output = F;
+    type Parameters = Parameters<F>;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(Parameters::new())
+    }
+
+    fn evaluate(parameters: &Self::Parameters, input: &[u8]) -> Result<Self::Output, Error> {
+        if input.len() > I::NUM_BYTES {
+            return Err(format!(
+                "incorrect input length {} for Poseidon CRH of {} bytes",
+                input.len(),
+                I::NUM_BYTES
+            )
+            .into());
+        }
+        let mut padded = input.to_vec();
+        padded.resize(I::NUM_BYTES, 0);
+        let elements: Vec<F> = padded
+            .chunks(bytes_per_element::<F>())
+            .map(F::from_le_bytes_mod_order)
+            .collect();
+        Ok(parameters.hash(F::from(I::NUM_BYTES as u64), &elements))
+    }
+}
+
+/// A Poseidon hash of two field elements, given as their little-endian byte encodings,
+/// with one permutation. Its capacity element starts out as zero, which no `CRH` uses.
+///
+/// The inputs are read modulo the field size, so only canonical encodings (such as those
+/// of the nodes of a Merkle tree) are hashed injectively.
+pub struct TwoToOneCRH<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+impl<F: PrimeField> TwoToOneCRHTrait for TwoToOneCRH<F> {
+    const LEFT_INPUT_SIZE_BITS: usize = <F::BigInt as BigInteger>::NUM_LIMBS * 64;
+    const RIGHT_INPUT_SIZE_BITS: usize = <F::BigInt as BigInteger>::NUM_LIMBS * 64;
+    type Output = F;
+    type Parameters = Parameters<F>;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(Parameters::new())
+    }
+
+    fn evaluate(
+        parameters: &Self::Parameters,
+        left_input: &[u8],
+        right_input: &[u8],
+    ) -> Result<Self::Output, Error> {
+        if left_input.len() * 8 > Self::LEFT_INPUT_SIZE_BITS
+            || right_input.len() * 8 > Self::RIGHT_INPUT_SIZE_BITS
+        {
+            return Err("incorrect input length for Poseidon two-to-one CRH".into());
+        }
+        let left = F::from_le_bytes_mod_order(left_input);
+        let right = F::from_le_bytes_mod_order(right_input);
+        Ok(parameters.hash(F::zero(), &[left, right]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ark_bls12_381::Fr;
+    use ark_ff::to_bytes;
+
+    struct TestInput;
+    impl InputSize for TestInput {
+        const NUM_BYTES: usize = 80;
+    }
+    type TestCRH = CRH<Fr, TestInput>;
+
+    #[test]
+    fn poseidon_pads_and_separates_inputs() {
+        let pp = <TestCRH as CRHTrait>::setup(&mut ark_std::test_rng()).unwrap();
+        assert_eq!(pp, Parameters::default());
+
+        let hash = |input: &[u8]| <TestCRH as CRHTrait>::evaluate(&pp, input).unwrap();
+        // Inputs are padded with zeros.
+        assert_eq!(hash(&[1, 2, 3]), hash(&[1, 2, 3, 0, 0]));
+        assert_ne!(hash(&[1, 2, 3]), hash(&[1, 2, 4]));
+        assert!(<TestCRH as CRHTrait>::evaluate(&pp, &[0u8; 81]).is_err());
+
+        let left = hash(&[1]);
+        let right = hash(&[2]);
+        let two_to_one = |l: &Fr, r: &Fr| {
+            <TwoToOneCRH<Fr> as TwoToOneCRHTrait>::evaluate(
+                &pp,
+                &to_bytes![l].unwrap(),
+                &to_bytes![r].unwrap(),
+            )
+            .unwrap()
+        };
+        assert_ne!(two_to_one(&left, &right), two_to_one(&right, &left));
+        // The two-to-one hash of two elements differs from the `CRH` of their encodings.
+        assert_ne!(
+            two_to_one(&left, &right),
+            <CRH<Fr, TwoToOneInput> as CRHTrait>::evaluate(&pp, &to_bytes![left, right].unwrap())
+                .unwrap()
+        );
+
+        let mut bytes = Vec::new();
+        pp.serialize(&mut bytes).unwrap();
+        assert_eq!(Parameters::<Fr>::deserialize(&bytes[..]).unwrap(), pp);
+        assert!(Parameters::<Fr>::deserialize(&bytes[..bytes.len() - 32]).is_err());
+    }
+
+    struct TwoToOneInput;
+    impl InputSize for TwoToOneInput {
+        const NUM_BYTES: usize = 64;
+    }
+}
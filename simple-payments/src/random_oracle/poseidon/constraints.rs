@@ -0,0 +1,74 @@
+use crate::poseidon::{bytes_per_element, constraints::bytes_to_fp_var_mod_order};
+use crate::random_oracle::{poseidon, RandomOracleGadget};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::SynthesisError;
+use ark_std::vec::Vec;
+
+pub use crate::poseidon::constraints::ParametersVar;
+
+pub struct ROGadget;
+
+impl<F: PrimeField> RandomOracleGadget<poseidon::RO<F>, F> for ROGadget {
+    type OutputVar = FpVar<F>;
+    type ParametersVar = ParametersVar<F>;
+
+    fn evaluate(
+        parameters: &Self::ParametersVar,
+        input: &[UInt8<F>],
+    ) -> Result<Self::OutputVar, SynthesisError> {
+        let mut elements = input
+            .chunks(bytes_per_element::<F>())
+            .map(bytes_to_fp_var_mod_order)
+            .collect::<Result<Vec<_>, _>>()?;
+        if elements.is_empty() {
+            elements.push(FpVar::zero());
+        }
+        parameters.hash(poseidon::domain(input.len()), &elements)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::random_oracle::{
+        poseidon::{constraints::ROGadget, RO},
+        RandomOracle, RandomOracleGadget,
+    };
+    use ark_ed_on_bls12_381::Fq as Fr;
+    use ark_r1cs_std::prelude::*;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    #[test]
+    fn random_oracle_gadget_test() {
+        type TestRO = RO<Fr>;
+        type TestROGadget = ROGadget;
+
+        for input in [&[][..], &[1u8; 32], &[2u8; 100]] {
+            let cs = ConstraintSystem::<Fr>::new_ref();
+            let parameters = TestRO::setup(&mut ark_std::test_rng()).unwrap();
+            let primitive_result = TestRO::evaluate(&parameters, input).unwrap();
+
+            let input_var = UInt8::new_witness_vec(cs.clone(), input).unwrap();
+            let parameters_var =
+                <TestROGadget as RandomOracleGadget<TestRO, Fr>>::ParametersVar::new_constant(
+                    ark_relations::ns!(cs, "gadget_parameters"),
+                    &parameters,
+                )
+                .unwrap();
+            let result_var = <TestROGadget as RandomOracleGadget<TestRO, Fr>>::evaluate(
+                &parameters_var,
+                &input_var,
+            )
+            .unwrap();
+            assert_eq!(result_var.value().unwrap(), primitive_result);
+            assert!(cs.is_satisfied().unwrap());
+        }
+        // Inputs of different lengths are separated, even if they pack to the same elements.
+        let parameters = TestRO::setup(&mut ark_std::test_rng()).unwrap();
+        assert_ne!(
+            TestRO::evaluate(&parameters, &[]).unwrap(),
+            TestRO::evaluate(&parameters, &[0]).unwrap()
+        );
+    }
+}
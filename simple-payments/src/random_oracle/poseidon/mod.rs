@@ -0,0 +1,44 @@
+use super::RandomOracle;
+use crate::poseidon::{bytes_per_element, Parameters};
+use ark_crypto_primitives::Error;
+use ark_ff::PrimeField;
+use ark_std::marker::PhantomData;
+use ark_std::rand::Rng;
+use ark_std::vec::Vec;
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// A random oracle that packs its input into elements of `F`, and absorbs them into a
+/// Poseidon sponge. Unlike Blake2s, this is cheap to evaluate in a circuit over `F`.
+pub struct RO<F: PrimeField> {
+    _field: PhantomData<F>,
+}
+
+/// The capacity element of the sponge starts out as `2^64 + input_len`. This separates
+/// inputs of different lengths from each other, and from the inputs of `poseidon::CRH`
+/// and `poseidon::TwoToOneCRH`.
+pub fn domain<F: PrimeField>(input_len: usize) -> F {
+    F::from(2u64).pow([64]) + F::from(input_len as u64)
+}
+
+impl<F: PrimeField> RandomOracle for RO<F> {
+    type Parameters = Parameters<F>;
+    type Output = F;
+
+    fn setup<R: Rng>(_: &mut R) -> Result<Self::Parameters, Error> {
+        Ok(Parameters::new())
+    }
+
+    fn evaluate(parameters: &Self::Parameters, input: &[u8]) -> Result<Self::Output, Error> {
+        let mut elements: Vec<F> = input
+            .chunks(bytes_per_element::<F>())
+            .map(F::from_le_bytes_mod_order)
+            .collect();
+        // The sponge has to absorb at least one element.
+        if elements.is_empty() {
+            elements.push(F::zero());
+        }
+        Ok(parameters.hash(domain(input.len()), &elements))
+    }
+}
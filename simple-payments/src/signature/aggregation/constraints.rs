@@ -0,0 +1,246 @@
+use super::{AggregateSignature, COEFFICIENT_BYTES};
+use crate::poseidon::{bytes_per_element, constraints::bytes_to_fp_var_mod_order};
+use crate::random_oracle::{RandomOracle, RandomOracleGadget};
+use crate::signature::schnorr::constraints::{challenge, ParametersVar, PublicKeyVar};
+use ark_ec::ProjectiveCurve;
+use ark_ff::{to_bytes, Field};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
+use ark_std::vec::Vec;
+
+use derivative::Derivative;
+
+use core::{borrow::Borrow, marker::PhantomData};
+
+type ConstraintF<C> = <<C as ProjectiveCurve>::BaseField as Field>::BasePrimeField;
+
+#[derive(Derivative)]
+#[derivative(
+    Debug(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>"),
+    Clone(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>")
+)]
+pub struct AggregateSignatureVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
+where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    pub prover_commitments: Vec<GC>,
+    pub prover_response: Vec<UInt8<ConstraintF<C>>>,
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+}
+
+/// A slot of a batch to verify: whether it carries a signature, and the public key and
+/// the message of that signature.
+pub type SlotVar<C, GC> = (
+    Boolean<ConstraintF<C>>,
+    PublicKeyVar<C, GC>,
+    Vec<UInt8<ConstraintF<C>>>,
+);
+
+/// Computes the little-endian bits of the coefficients like `aggregation::coefficients`,
+/// where empty slots are given as zero challenges. Apart from the hash, this takes a
+/// multiplication and a bit decomposition per coefficient.
+pub fn coefficients<C, RO, ROG>(
+    challenges: &[Vec<UInt8<ConstraintF<C>>>],
+) -> Result<Vec<Vec<Boolean<ConstraintF<C>>>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+{
+    let ro_params =
+        ROG::ParametersVar::new_constant(ConstraintSystemRef::None, RO::Parameters::default())?;
+    let hash_digest = ROG::evaluate(&ro_params, &challenges.concat())?.to_bytes()?;
+    let seed = bytes_to_fp_var_mod_order(&hash_digest[..bytes_per_element::<ConstraintF<C>>()])?;
+    let mut power = FpVar::one();
+    challenges
+        .iter()
+        .map(|_| {
+            power *= &seed;
+            let mut bits = power.to_bits_le()?;
+            bits.truncate(8 * COEFFICIENT_BYTES);
+            Ok(bits)
+        })
+        .collect()
+}
+
+/// Verifies an aggregate of `Schnorr<C, RO>` signatures like `aggregation::verify`, where
+/// `ROG` is the gadget for `RO`.
+///
+/// Besides recomputing the challenge of every slot, this takes two variable-base scalar
+/// multiplications per slot (by the challenge and by the coefficient), and a single
+/// fixed-base one for the whole batch.
+pub fn verify<C, GC, RO, ROG>(
+    parameters: &ParametersVar<C, GC>,
+    batch: &[SlotVar<C, GC>],
+    aggregate: &AggregateSignatureVar<C, GC>,
+) -> Result<Boolean<ConstraintF<C>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+{
+    assert_eq!(batch.len(), aggregate.prover_commitments.len());
+    let mut challenges = Vec::with_capacity(batch.len());
+    let mut terms = Vec::with_capacity(batch.len());
+    for ((is_signed, public_key, message), prover_commitment) in
+        batch.iter().zip(&aggregate.prover_commitments)
+    {
+        let verifier_challenge =
+            challenge::<C, GC, RO, ROG>(parameters, public_key, prover_commitment, message)?;
+        // `r_i - e_i · Y_i`
+        let public_key_times_verifier_challenge = public_key
+            .pub_key
+            .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
+        terms.push(prover_commitment.clone() - public_key_times_verifier_challenge);
+        challenges.push(
+            verifier_challenge
+                .iter()
+                .map(|byte| UInt8::conditionally_select(is_signed, byte, &UInt8::constant(0)))
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+    }
+
+    let coefficients = coefficients::<C, RO, ROG>(&challenges)?;
+    let mut sum = GC::zero();
+    for (((is_signed, _, _), term), z) in batch.iter().zip(terms).zip(coefficients) {
+        let weighted_term = term.scalar_mul_le(z.iter())?;
+        sum += GC::conditionally_select(is_signed, &weighted_term, &GC::zero())?;
+    }
+    let generator_times_prover_response = parameters
+        .generator
+        .scalar_mul_le(aggregate.prover_response.to_bits_le()?.iter())?;
+    sum.is_eq(&generator_times_prover_response)
+}
+
+impl<C, GC> AllocVar<AggregateSignature<C>, ConstraintF<C>> for AggregateSignatureVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn new_variable<T: Borrow<AggregateSignature<C>>>(
+        cs: impl Into<Namespace<ConstraintF<C>>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|val| {
+            let cs = cs.into();
+            let val = val.borrow();
+            let prover_commitments = val
+                .prover_commitments
+                .iter()
+                .map(|commitment| GC::new_variable(cs.clone(), || Ok(*commitment), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            let response_bytes = to_bytes![val.prover_response].unwrap();
+            let prover_response = response_bytes
+                .iter()
+                .map(|byte| UInt8::new_variable(cs.clone(), || Ok(byte), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Self {
+                prover_commitments,
+                prover_response,
+                _group: PhantomData,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random_oracle::{blake2s, poseidon};
+    use crate::signature::aggregation;
+    use crate::signature::schnorr::{constraints::SchnorrSignatureVerifyGadget, Schnorr};
+    use crate::signature::{SigVerifyGadget, SignatureScheme};
+    use ark_ed_on_bls12_381::{constraints::EdwardsVar as JubJubVar, EdwardsProjective as JubJub};
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::test_rng;
+
+    /// Verifies an aggregate of three signatures (with the second slot empty) in a circuit,
+    /// checks that the circuit rejects it for a different message, and returns the number of
+    /// constraints it takes, along with the number of constraints that verifying the three
+    /// signatures individually takes, like a circuit with a fixed number of slots does.
+    fn aggregate_verifies<RO: RandomOracle, ROG: RandomOracleGadget<RO, ConstraintF<JubJub>>>(
+    ) -> (usize, usize) {
+        let rng = &mut test_rng();
+        let parameters = Schnorr::<JubJub, RO>::setup(rng).unwrap();
+        let messages = ["first", "second", "third"].map(str::as_bytes);
+        let signed = messages
+            .iter()
+            .map(|message| {
+                let (pk, sk) = Schnorr::<JubJub, RO>::keygen(&parameters, rng).unwrap();
+                let signature =
+                    Schnorr::<JubJub, RO>::sign(&parameters, &sk, message, rng).unwrap();
+                (pk, signature)
+            })
+            .collect::<Vec<_>>();
+        let batch = (0..3)
+            .map(|i| (i != 1).then(|| (signed[i].0, messages[i], &signed[i].1)))
+            .collect::<Vec<_>>();
+        let aggregate = aggregation::aggregate::<JubJub, RO>(&parameters, &batch).unwrap();
+
+        let circuit = |messages: [&[u8]; 3]| {
+            let cs = ConstraintSystem::<ConstraintF<JubJub>>::new_ref();
+            let parameters_var =
+                ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), &parameters).unwrap();
+            let batch_var = (0..3)
+                .map(|i| {
+                    (
+                        Boolean::new_witness(cs.clone(), || Ok(i != 1)).unwrap(),
+                        PublicKeyVar::new_witness(cs.clone(), || Ok(signed[i].0)).unwrap(),
+                        UInt8::new_witness_vec(cs.clone(), messages[i]).unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let aggregate_var =
+                AggregateSignatureVar::new_witness(cs.clone(), || Ok(&aggregate)).unwrap();
+            verify::<_, _, RO, ROG>(&parameters_var, &batch_var, &aggregate_var)
+                .unwrap()
+                .enforce_equal(&Boolean::TRUE)
+                .unwrap();
+            (cs.is_satisfied().unwrap(), cs.num_constraints())
+        };
+        let (is_satisfied, aggregate_constraints) = circuit(messages);
+        assert!(is_satisfied);
+        assert!(!circuit([messages[0], messages[1], messages[1]]).0);
+
+        let cs = ConstraintSystem::<ConstraintF<JubJub>>::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), &parameters).unwrap();
+        for i in 0..3 {
+            let pk_var = PublicKeyVar::new_witness(cs.clone(), || Ok(signed[i].0)).unwrap();
+            let message_var = UInt8::new_witness_vec(cs.clone(), messages[i]).unwrap();
+            let signature_var =
+                <SchnorrSignatureVerifyGadget<JubJub, JubJubVar, ROG> as SigVerifyGadget<
+                    Schnorr<JubJub, RO>,
+                    _,
+                >>::SignatureVar::new_witness(cs.clone(), || Ok(&signed[i].1))
+                .unwrap();
+            <SchnorrSignatureVerifyGadget<JubJub, JubJubVar, ROG> as SigVerifyGadget<
+                Schnorr<JubJub, RO>,
+                _,
+            >>::verify(&parameters_var, &pk_var, &message_var, &signature_var)
+            .unwrap()
+            .enforce_equal(&Boolean::TRUE)
+            .unwrap();
+        }
+        assert!(cs.is_satisfied().unwrap());
+        (aggregate_constraints, cs.num_constraints())
+    }
+
+    #[test]
+    fn half_aggregation_gadget_test() {
+        let (aggregate, individual) =
+            aggregate_verifies::<blake2s::RO, blake2s::constraints::ROGadget>();
+        println!("Blake2s: {aggregate} constraints for the aggregate, {individual} individually");
+        let (aggregate, individual) = aggregate_verifies::<
+            poseidon::RO<ConstraintF<JubJub>>,
+            poseidon::constraints::ROGadget,
+        >();
+        println!("Poseidon: {aggregate} constraints for the aggregate, {individual} individually");
+    }
+}
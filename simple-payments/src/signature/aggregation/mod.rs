@@ -0,0 +1,215 @@
+//! Half-aggregation of `Schnorr` signatures on distinct messages, following
+//! [Chalkias et al.](https://eprint.iacr.org/2021/350).
+//!
+//! An aggregate of the signatures `(s_i, e_i)` consists of all the prover commitments
+//! `r_i = s_i · G + e_i · Y_i`, and a single response `s = sum_i z_i · s_i`. It is valid if
+//! `s · G = sum_i z_i · (r_i - e_i · Y_i)`, where each challenge `e_i` is recomputed from
+//! `r_i`, and the coefficients `z_i` are derived from all the challenges with the random
+//! oracle. Without these coefficients, anyone could forge a signature by aggregating it
+//! with a signature under their own key.
+//!
+//! A batch consists of a fixed number of slots, some of which may carry no signature.
+//! This lets a rollup aggregate the signatures of all of its signed transactions.
+use crate::poseidon::bytes_per_element;
+use crate::random_oracle::RandomOracle;
+use crate::signature::schnorr::{self, Parameters, PublicKey, Signature};
+use ark_crypto_primitives::Error;
+use ark_ec::{msm::VariableBaseMSM, ProjectiveCurve};
+use ark_ff::{to_bytes, Field, One, PrimeField, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_std::vec::Vec;
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+type ConstraintF<C> = <<C as ProjectiveCurve>::BaseField as Field>::BasePrimeField;
+
+/// The number of bytes of each coefficient `z_i`.
+pub const COEFFICIENT_BYTES: usize = 16;
+
+/// A slot of a batch to verify: the public key and the message of a signature, or `None`
+/// if the slot is empty.
+pub type Slot<'a, C> = Option<(PublicKey<C>, &'a [u8])>;
+
+/// A slot of a batch to aggregate: the public key, the message, and the signature, or
+/// `None` if the slot is empty.
+pub type SignedSlot<'a, C> = Option<(PublicKey<C>, &'a [u8], &'a Signature<C>)>;
+
+/// A half-aggregate of the signatures of a batch.
+#[derive(Clone, Debug, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct AggregateSignature<C: ProjectiveCurve> {
+    /// The prover commitment of the signature in each slot, or the identity for slots
+    /// without a signature.
+    pub prover_commitments: Vec<C::Affine>,
+    /// The sum of the prover responses, weighted by the coefficients.
+    pub prover_response: C::ScalarField,
+}
+
+/// The coefficients `z_i` of a batch whose i-th slot carries the verifier challenge
+/// `challenges[i]`, or `None` if it carries no signature.
+///
+/// These are the low `COEFFICIENT_BYTES` bytes of `t^(i + 1)`, where `t` is the hash of
+/// all the challenges (with zeros for empty slots), read as an element of the base field
+/// of `C` (which is the constraint field of the circuits that verify signatures). Deriving
+/// all coefficients from a single hash keeps them cheap in a circuit over that field, see
+/// `constraints::coefficients`.
+pub fn coefficients<C, RO>(challenges: &[Option<[u8; 32]>]) -> Result<Vec<C::ScalarField>, Error>
+where
+    C: ProjectiveCurve,
+    RO: RandomOracle,
+{
+    let hash_input = challenges
+        .iter()
+        .flat_map(|challenge| challenge.unwrap_or([0u8; 32]))
+        .collect::<Vec<_>>();
+    let hash_digest = to_bytes![RO::evaluate(&RO::Parameters::default(), &hash_input)?]?;
+    let seed = ConstraintF::<C>::from_le_bytes_mod_order(
+        &hash_digest[..bytes_per_element::<ConstraintF<C>>()],
+    );
+    let mut power = ConstraintF::<C>::one();
+    challenges
+        .iter()
+        .map(|_| {
+            power *= seed;
+            let power_bytes = to_bytes![power]?;
+            Ok(C::ScalarField::from_le_bytes_mod_order(
+                &power_bytes[..COEFFICIENT_BYTES],
+            ))
+        })
+        .collect()
+}
+
+/// Aggregate the `Schnorr<C, RO>` signatures of a batch, recomputing their prover
+/// commitments with `schnorr::prover_commitment`.
+///
+/// This does not check that the signatures are valid; if one of them is not, neither is
+/// the aggregate.
+pub fn aggregate<C, RO>(
+    parameters: &Parameters<C>,
+    batch: &[SignedSlot<C>],
+) -> Result<AggregateSignature<C>, Error>
+where
+    C: ProjectiveCurve,
+    RO: RandomOracle,
+{
+    let challenges = batch
+        .iter()
+        .map(|slot| slot.map(|(_, _, signature)| signature.verifier_challenge))
+        .collect::<Vec<_>>();
+    let coefficients = coefficients::<C, RO>(&challenges)?;
+    let prover_commitments = batch
+        .iter()
+        .map(|slot| match slot {
+            Some((pk, _, signature)) => schnorr::prover_commitment(parameters, pk, signature),
+            None => C::zero(),
+        })
+        .collect::<Vec<_>>();
+    let mut prover_response = C::ScalarField::zero();
+    for (slot, z) in batch.iter().zip(coefficients) {
+        if let Some((_, _, signature)) = slot {
+            prover_response += z * signature.prover_response;
+        }
+    }
+    Ok(AggregateSignature {
+        prover_commitments: C::batch_normalization_into_affine(&prover_commitments),
+        prover_response,
+    })
+}
+
+/// Verify an aggregate of the `Schnorr<C, RO>` signatures of a batch.
+pub fn verify<C, RO>(
+    parameters: &Parameters<C>,
+    batch: &[Slot<C>],
+    aggregate: &AggregateSignature<C>,
+) -> Result<bool, Error>
+where
+    C: ProjectiveCurve,
+    RO: RandomOracle,
+{
+    if batch.len() != aggregate.prover_commitments.len() {
+        return Ok(false);
+    }
+    let challenges = batch
+        .iter()
+        .zip(&aggregate.prover_commitments)
+        .map(|(slot, prover_commitment)| {
+            slot.map(|(pk, message)| {
+                schnorr::challenge::<C, RO>(parameters, &pk, prover_commitment, message)
+            })
+            .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let coefficients = coefficients::<C, RO>(&challenges)?;
+
+    // Check that `sum_i z_i · r_i - sum_i z_i · e_i · Y_i - s · G = 0`.
+    let mut bases = Vec::with_capacity(2 * batch.len() + 1);
+    let mut scalars = Vec::with_capacity(2 * batch.len() + 1);
+    for (((slot, challenge), z), prover_commitment) in batch
+        .iter()
+        .zip(&challenges)
+        .zip(coefficients)
+        .zip(&aggregate.prover_commitments)
+    {
+        if let (Some((pk, _)), Some(challenge)) = (slot, challenge) {
+            let challenge_fe = C::ScalarField::from_le_bytes_mod_order(challenge);
+            bases.push(*prover_commitment);
+            scalars.push(z.into_repr());
+            bases.push(*pk);
+            scalars.push((-(z * challenge_fe)).into_repr());
+        }
+    }
+    bases.push(parameters.generator);
+    scalars.push((-aggregate.prover_response).into_repr());
+    Ok(VariableBaseMSM::multi_scalar_mul(&bases, &scalars).is_zero())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::random_oracle::blake2s;
+    use crate::signature::{schnorr::Schnorr, SignatureScheme};
+    use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+    use ark_std::test_rng;
+
+    type S = Schnorr<JubJub>;
+
+    #[test]
+    fn half_aggregation_test() {
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let messages = ["first", "second", "third"].map(str::as_bytes);
+        let (pks, signatures): (Vec<_>, Vec<_>) = messages
+            .iter()
+            .map(|message| {
+                let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+                (pk, S::sign(&parameters, &sk, message, rng).unwrap())
+            })
+            .unzip();
+        // The second slot is empty.
+        let signed_slot = |i: usize| (i != 1).then(|| (pks[i], messages[i], &signatures[i]));
+        let slot = |i: usize| (i != 1).then(|| (pks[i], messages[i]));
+        let batch = (0..3).map(signed_slot).collect::<Vec<_>>();
+        let aggregate = aggregate::<JubJub, blake2s::RO>(&parameters, &batch).unwrap();
+        let mut bytes = Vec::new();
+        aggregate.serialize(&mut bytes).unwrap();
+        assert_eq!(
+            AggregateSignature::<JubJub>::deserialize(&bytes[..]).unwrap(),
+            aggregate
+        );
+        let batch = (0..3).map(slot).collect::<Vec<_>>();
+        assert!(verify::<JubJub, blake2s::RO>(&parameters, &batch, &aggregate).unwrap());
+
+        // The aggregate does not verify for other messages, ...
+        let mut bad_batch = batch.clone();
+        bad_batch[2] = Some((pks[2], messages[1]));
+        assert!(!verify::<JubJub, blake2s::RO>(&parameters, &bad_batch, &aggregate).unwrap());
+        // ... for other slots, ...
+        let mut bad_batch = batch.clone();
+        bad_batch.swap(0, 2);
+        assert!(!verify::<JubJub, blake2s::RO>(&parameters, &bad_batch, &aggregate).unwrap());
+        // ... or with a signature in the empty slot.
+        let mut bad_batch = batch;
+        bad_batch[1] = Some((pks[1], messages[1]));
+        assert!(!verify::<JubJub, blake2s::RO>(&parameters, &bad_batch, &aggregate).unwrap());
+    }
+}
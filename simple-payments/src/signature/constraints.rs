@@ -37,14 +37,17 @@ pub trait SigRandomizePkGadget<S: SignatureScheme, ConstraintF: Field> {
 
 #[cfg(test)]
 mod test {
+    use crate::random_oracle::{blake2s, poseidon, RandomOracle, RandomOracleGadget};
     use crate::signature::{schnorr, schnorr::constraints::*, *};
     use ark_ec::ProjectiveCurve;
     use ark_ed_on_bls12_381::constraints::EdwardsVar as JubJubVar;
     use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+    use ark_ed_on_bls12_381::Fq;
     use ark_ff::PrimeField;
     use ark_r1cs_std::prelude::*;
     use ark_relations::r1cs::ConstraintSystem;
     use ark_std::test_rng;
+    use ark_std::UniformRand;
 
     fn sign_and_verify<F: PrimeField, S: SignatureScheme, SG: SigVerifyGadget<S, F>>(
         message: &[u8],
@@ -52,8 +55,8 @@ mod test {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
 
         let cs = ConstraintSystem::<F>::new_ref();
 
@@ -61,8 +64,8 @@ mod test {
         let signature_var = SG::SignatureVar::new_witness(cs.clone(), || Ok(&sig)).unwrap();
         let pk_var = SG::PublicKeyVar::new_witness(cs.clone(), || Ok(&pk)).unwrap();
         let mut msg_var = Vec::new();
-        for i in 0..message.len() {
-            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(&message[i])).unwrap())
+        for byte in message {
+            msg_var.push(UInt8::new_witness(cs.clone(), || Ok(byte)).unwrap())
         }
         let valid_sig_var = SG::verify(&parameters_var, &pk_var, &msg_var, &signature_var).unwrap();
 
@@ -78,6 +81,42 @@ mod test {
         assert!(!S::verify(&parameters, &pk, bad_message, &sig).unwrap());
     }
 
+    /// Checks that the gadget computes the same Schnorr challenge as `schnorr::challenge`
+    /// with the random oracle `RO`, and returns the number of constraints that this takes.
+    fn challenge_matches<RO: RandomOracle, ROG: RandomOracleGadget<RO, Fq>>() -> usize {
+        let rng = &mut test_rng();
+        let parameters = schnorr::Schnorr::<JubJub>::setup(rng).unwrap();
+        let (pk, _) = schnorr::Schnorr::<JubJub>::keygen(&parameters, rng).unwrap();
+        let commitment = JubJub::rand(rng).into_affine();
+        let message = b"Hi, I am a Schnorr challenge!";
+        let challenge =
+            schnorr::challenge::<JubJub, RO>(&parameters, &pk, &commitment, message).unwrap();
+
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), parameters).unwrap();
+        let pk_var = PublicKeyVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let commitment_var = JubJubVar::new_witness(cs.clone(), || Ok(commitment)).unwrap();
+        let message_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        let challenge_var = schnorr::constraints::challenge::<_, _, RO, ROG>(
+            &parameters_var,
+            &pk_var,
+            &commitment_var,
+            &message_var,
+        )
+        .unwrap();
+        assert_eq!(challenge_var.value().unwrap(), challenge);
+        assert!(cs.is_satisfied().unwrap());
+        cs.num_constraints()
+    }
+
+    #[test]
+    fn schnorr_challenge_with_either_oracle() {
+        let blake2s = challenge_matches::<blake2s::RO, blake2s::constraints::ROGadget>();
+        let poseidon = challenge_matches::<poseidon::RO<Fq>, poseidon::constraints::ROGadget>();
+        assert!(poseidon < blake2s);
+    }
+
     #[test]
     fn schnorr_signature_test() {
         type F = <JubJub as ProjectiveCurve>::BaseField;
@@ -92,4 +131,134 @@ mod test {
             "Bad message".as_bytes(),
         );
     }
+
+    #[test]
+    fn randomized_schnorr_key_test() {
+        type S = schnorr::Schnorr<JubJub>;
+        type VerifyGadget = SchnorrSignatureVerifyGadget<JubJub, JubJubVar>;
+        type RandomizeGadget = SchnorrRandomizePkGadget<JubJub, JubJubVar>;
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let randomness = [7u8; 32];
+        let randomized_sk = S::randomize_secret_key(&parameters, &sk, &randomness).unwrap();
+        let message = "Hi, I am a Schnorr signature under a randomized key!".as_bytes();
+        let sig = S::sign(&parameters, &randomized_sk, message, rng).unwrap();
+
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters_var =
+            ParametersVar::<JubJub, JubJubVar>::new_constant(cs.clone(), &parameters).unwrap();
+        let pk_var = PublicKeyVar::<JubJub, JubJubVar>::new_witness(cs.clone(), || Ok(pk)).unwrap();
+        let randomness_var = UInt8::new_witness_vec(cs.clone(), &randomness).unwrap();
+        let randomized_pk_var = <RandomizeGadget as SigRandomizePkGadget<S, Fq>>::randomize(
+            &parameters_var,
+            &pk_var,
+            &randomness_var,
+        )
+        .unwrap();
+        let expected_pk_var =
+            PublicKeyVar::new_input(cs.clone(), || Ok(randomized_sk.public_key)).unwrap();
+        randomized_pk_var.enforce_equal(&expected_pk_var).unwrap();
+
+        let message_var = UInt8::new_witness_vec(cs.clone(), message).unwrap();
+        let signature_var =
+            <VerifyGadget as SigVerifyGadget<S, Fq>>::SignatureVar::new_witness(cs.clone(), || {
+                Ok(&sig)
+            })
+            .unwrap();
+        let verifies = |pk_var| {
+            <VerifyGadget as SigVerifyGadget<S, Fq>>::verify(
+                &parameters_var,
+                pk_var,
+                &message_var,
+                &signature_var,
+            )
+            .unwrap()
+            .value()
+            .unwrap()
+        };
+        assert!(verifies(&randomized_pk_var));
+        assert!(!verifies(&pk_var));
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn public_key_subgroup_test() {
+        use ark_ed_on_bls12_381::EdwardsAffine;
+        use ark_ff::{One, Zero};
+
+        type S = schnorr::Schnorr<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let (pk, _sk) = S::keygen(&parameters, rng).unwrap();
+        // (0, -1) has order 2. Unlike witnesses, inputs are allocated without checking
+        // that they are in the prime-order subgroup.
+        let torsion = EdwardsAffine::new(Fq::zero(), -Fq::one());
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let is_in_subgroup = |key| {
+            PublicKeyVar::<JubJub, JubJubVar>::new_input(cs.clone(), || Ok(key))
+                .unwrap()
+                .is_in_prime_order_subgroup()
+                .unwrap()
+                .value()
+                .unwrap()
+        };
+        assert!(is_in_subgroup(pk));
+        assert!(is_in_subgroup(EdwardsAffine::zero()));
+        assert!(!is_in_subgroup(torsion));
+        assert!(!is_in_subgroup(pk + torsion));
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn eddsa_signature_test() {
+        use crate::signature::eddsa::{self, constraints::EdDSASignatureVerifyGadget};
+        let message = "Hi, I am an EdDSA signature!";
+        sign_and_verify::<Fq, eddsa::EdDSA<JubJub>, EdDSASignatureVerifyGadget<JubJub, JubJubVar>>(
+            message.as_bytes(),
+        );
+        sign_and_verify::<
+            Fq,
+            eddsa::EdDSA<JubJub, poseidon::RO<Fq>>,
+            EdDSASignatureVerifyGadget<JubJub, JubJubVar, poseidon::constraints::ROGadget>,
+        >(message.as_bytes());
+
+        // The gadget rejects the signature for another message.
+        type SG = EdDSASignatureVerifyGadget<JubJub, JubJubVar>;
+        type S = eddsa::EdDSA<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let sig = S::sign(&parameters, &sk, message.as_bytes(), rng).unwrap();
+        let cs = ConstraintSystem::<Fq>::new_ref();
+        let parameters_var =
+            <SG as SigVerifyGadget<S, Fq>>::ParametersVar::new_constant(cs.clone(), parameters)
+                .unwrap();
+        let pk_var =
+            <SG as SigVerifyGadget<S, Fq>>::PublicKeyVar::new_witness(cs.clone(), || Ok(pk))
+                .unwrap();
+        let sig_var =
+            <SG as SigVerifyGadget<S, Fq>>::SignatureVar::new_witness(cs.clone(), || Ok(sig))
+                .unwrap();
+        let bad_message_var = UInt8::new_witness_vec(cs.clone(), b"Bad message").unwrap();
+        let verifies = <SG as SigVerifyGadget<S, Fq>>::verify(
+            &parameters_var,
+            &pk_var,
+            &bad_message_var,
+            &sig_var,
+        )
+        .unwrap();
+        assert!(!verifies.value().unwrap());
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn schnorr_signature_with_poseidon_challenges_test() {
+        let message = "Hi, I am a Schnorr signature with a Poseidon challenge!";
+        sign_and_verify::<
+            Fq,
+            schnorr::Schnorr<JubJub, poseidon::RO<Fq>>,
+            SchnorrSignatureVerifyGadget<JubJub, JubJubVar, poseidon::constraints::ROGadget>,
+        >(message.as_bytes());
+    }
 }
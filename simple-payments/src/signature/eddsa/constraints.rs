@@ -0,0 +1,147 @@
+use super::{EdDSA, Signature};
+use ark_ec::ProjectiveCurve;
+use ark_ff::{to_bytes, Field};
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, Namespace, SynthesisError};
+use ark_std::vec::Vec;
+
+use crate::random_oracle::blake2s::constraints::ROGadget;
+use crate::random_oracle::{RandomOracle, RandomOracleGadget};
+use crate::signature::schnorr::constraints::{ParametersVar, PublicKeyVar};
+use crate::signature::SigVerifyGadget;
+
+use derivative::Derivative;
+
+use core::{borrow::Borrow, marker::PhantomData};
+
+type ConstraintF<C> = <<C as ProjectiveCurve>::BaseField as Field>::BasePrimeField;
+
+#[derive(Derivative)]
+#[derivative(
+    Debug(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>"),
+    Clone(bound = "C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>")
+)]
+pub struct SignatureVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
+where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    prover_commitment: GC,
+    prover_response: Vec<UInt8<ConstraintF<C>>>,
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+}
+
+/// Verifies signatures of `EdDSA<C, RO>`, where `ROG` is the gadget for `RO`. Parameters
+/// and public keys are those of `Schnorr<C, RO>`.
+pub struct EdDSASignatureVerifyGadget<
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    ROG = ROGadget,
+> where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_gadget: PhantomData<*const GC>,
+    #[doc(hidden)]
+    _random_oracle_gadget: PhantomData<*const ROG>,
+}
+
+impl<C, GC, RO, ROG> SigVerifyGadget<EdDSA<C, RO>, ConstraintF<C>>
+    for EdDSASignatureVerifyGadget<C, GC, ROG>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
+    type SignatureVar = SignatureVar<C, GC>;
+
+    fn verify(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        message: &[UInt8<ConstraintF<C>>],
+        signature: &Self::SignatureVar,
+    ) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
+        let challenge = challenge::<C, GC, RO, ROG>(
+            parameters,
+            &signature.prover_commitment,
+            public_key,
+            message,
+        )?;
+        // s · G = R + e · A. The public key has prime order, so multiplying it by the
+        // bytes of the challenge is the same as multiplying it by the challenge.
+        let generator_times_prover_response = parameters
+            .generator
+            .scalar_mul_le(signature.prover_response.to_bits_le()?.iter())?;
+        let public_key_times_challenge = public_key
+            .pub_key
+            .scalar_mul_le(challenge.to_bits_le()?.iter())?;
+        generator_times_prover_response
+            .is_eq(&(signature.prover_commitment.clone() + public_key_times_challenge))
+    }
+}
+
+/// Computes the challenge like `eddsa::challenge`, with the gadget `ROG` for the random
+/// oracle `RO`.
+pub fn challenge<C, GC, RO, ROG>(
+    parameters: &ParametersVar<C, GC>,
+    prover_commitment: &GC,
+    public_key: &PublicKeyVar<C, GC>,
+    message: &[UInt8<ConstraintF<C>>],
+) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+{
+    let mut hash_input = Vec::new();
+    if let Some(salt) = parameters.salt.as_ref() {
+        hash_input.extend_from_slice(salt);
+    }
+    hash_input.extend_from_slice(&prover_commitment.to_bytes()?);
+    hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
+    hash_input.extend_from_slice(message);
+
+    let ro_params =
+        ROG::ParametersVar::new_constant(ConstraintSystemRef::None, RO::Parameters::default())?;
+    let mut hash_digest = ROG::evaluate(&ro_params, &hash_input)?.to_bytes()?;
+    hash_digest.truncate(32);
+    Ok(hash_digest)
+}
+
+impl<C, GC> AllocVar<Signature<C>, ConstraintF<C>> for SignatureVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    fn new_variable<T: Borrow<Signature<C>>>(
+        cs: impl Into<Namespace<ConstraintF<C>>>,
+        f: impl FnOnce() -> Result<T, SynthesisError>,
+        mode: AllocationMode,
+    ) -> Result<Self, SynthesisError> {
+        f().and_then(|val| {
+            let cs = cs.into();
+            let val = val.borrow();
+            let prover_commitment =
+                GC::new_variable(cs.clone(), || Ok(val.prover_commitment), mode)?;
+            let response_bytes = to_bytes![val.prover_response].unwrap();
+            let prover_response = response_bytes
+                .iter()
+                .map(|byte| UInt8::new_variable(cs.clone(), || Ok(byte), mode))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Self {
+                prover_commitment,
+                prover_response,
+                _group: PhantomData,
+            })
+        })
+    }
+}
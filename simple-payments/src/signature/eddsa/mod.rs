@@ -0,0 +1,201 @@
+//! EdDSA-style signatures over a twisted Edwards curve such as Jubjub, in the style of
+//! [RFC 8032](https://datatracker.ietf.org/doc/html/rfc8032).
+//!
+//! A signature on `M` under the public key `A = x · G` is a pair `(R, s)`, where `R = k · G`
+//! for a nonce `k`, and `s = k + e · x` for the challenge `e = H(salt || R || A || M)`. It is
+//! valid if `s · G = R + e · A`. Unlike RFC 8032, the secret key is a scalar rather than a
+//! seed, and the nonce is derived from it like in `schnorr::nonce`; keys are the same as
+//! those of `schnorr::Schnorr`.
+use super::schnorr::{self, Parameters, PublicKey, SecretKey};
+use super::SignatureScheme;
+use crate::random_oracle::{blake2s, RandomOracle};
+use ark_crypto_primitives::Error;
+use ark_ec::{msm::VariableBaseMSM, AffineCurve, ProjectiveCurve};
+use ark_ff::{to_bytes, PrimeField, UniformRand, Zero};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use ark_std::rand::Rng;
+use ark_std::{hash::Hash, marker::PhantomData, vec::Vec};
+
+#[cfg(feature = "r1cs")]
+pub mod constraints;
+
+/// EdDSA signatures over the group `C`, whose challenges are computed with the random
+/// oracle `RO`.
+pub struct EdDSA<C: ProjectiveCurve, RO: RandomOracle = blake2s::RO> {
+    _group: PhantomData<C>,
+    _random_oracle: PhantomData<RO>,
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Eq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct Signature<C: ProjectiveCurve> {
+    /// The commitment `R = k · G` to the nonce.
+    pub prover_commitment: C::Affine,
+    /// The response `s = k + e · x`.
+    pub prover_response: C::ScalarField,
+}
+
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> SignatureScheme for EdDSA<C, RO>
+where
+    C::ScalarField: PrimeField,
+{
+    type Parameters = Parameters<C>;
+    type PublicKey = PublicKey<C>;
+    type SecretKey = SecretKey<C>;
+    type Signature = Signature<C>;
+
+    fn setup<R: Rng>(rng: &mut R) -> Result<Self::Parameters, Error> {
+        schnorr::Schnorr::<C, RO>::setup(rng)
+    }
+
+    fn keygen<R: Rng>(
+        parameters: &Self::Parameters,
+        rng: &mut R,
+    ) -> Result<(Self::PublicKey, Self::SecretKey), Error> {
+        schnorr::Schnorr::<C, RO>::keygen(parameters, rng)
+    }
+
+    /// Sign `message` with a nonce derived from `sk` and `message`, like
+    /// `Schnorr::sign`. `rng` is unused.
+    fn sign<R: Rng>(
+        parameters: &Self::Parameters,
+        sk: &Self::SecretKey,
+        message: &[u8],
+        _rng: &mut R,
+    ) -> Result<Self::Signature, Error> {
+        let nonce = schnorr::derive_nonce::<C, RO>(parameters, b"eddsa-nonce", sk, message)?;
+        Self::sign_with_nonce(parameters, sk, message, nonce)
+    }
+
+    fn verify(
+        parameters: &Self::Parameters,
+        pk: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<bool, Error> {
+        let Signature {
+            prover_commitment,
+            prover_response,
+        } = signature;
+        let challenge = C::ScalarField::from_le_bytes_mod_order(&challenge::<C, RO>(
+            parameters,
+            prover_commitment,
+            pk,
+            message,
+        )?);
+        // s · G = R + e · A
+        let lhs = parameters.generator.mul(*prover_response);
+        let rhs = prover_commitment.into_projective() + pk.mul(challenge);
+        Ok(lhs == rhs)
+    }
+
+    fn randomize_public_key(
+        parameters: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error> {
+        schnorr::Schnorr::<C, RO>::randomize_public_key(parameters, public_key, randomness)
+    }
+
+    fn randomize_secret_key(
+        parameters: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error> {
+        schnorr::Schnorr::<C, RO>::randomize_secret_key(parameters, secret_key, randomness)
+    }
+}
+
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> EdDSA<C, RO>
+where
+    C::ScalarField: PrimeField,
+{
+    /// Sign `message` like `SignatureScheme::sign`, but with a nonce sampled from `rng`.
+    /// If `rng` is weak, the signatures may leak the secret key.
+    pub fn sign_with_rng<R: Rng>(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Signature<C>, Error> {
+        let nonce = C::ScalarField::rand(rng);
+        Self::sign_with_nonce(parameters, sk, message, nonce)
+    }
+
+    /// Sign `message` with the nonce `nonce`, which must not be used to sign anything
+    /// else.
+    fn sign_with_nonce(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        nonce: C::ScalarField,
+    ) -> Result<Signature<C>, Error> {
+        // R = k · G
+        let prover_commitment = parameters.generator.mul(nonce).into_affine();
+        let challenge = C::ScalarField::from_le_bytes_mod_order(&challenge::<C, RO>(
+            parameters,
+            &prover_commitment,
+            &sk.public_key,
+            message,
+        )?);
+        // s = k + e · x
+        Ok(Signature {
+            prover_commitment,
+            prover_response: nonce + challenge * sk.secret_key,
+        })
+    }
+
+    /// Verify the signatures of a batch of `(public key, message, signature)` triples at
+    /// once: we check that `sum_i z_i · (s_i · G - R_i - e_i · A_i) = 0` for random 128-bit
+    /// coefficients `z_i`, with a single multi-scalar multiplication. This assumes that the
+    /// public keys and prover commitments are in the prime-order subgroup.
+    pub fn batch_verify<R: Rng>(
+        parameters: &Parameters<C>,
+        batch: &[(PublicKey<C>, &[u8], &Signature<C>)],
+        rng: &mut R,
+    ) -> Result<bool, Error> {
+        let mut bases = Vec::with_capacity(2 * batch.len() + 1);
+        let mut scalars = Vec::with_capacity(2 * batch.len() + 1);
+        let mut generator_scalar = C::ScalarField::zero();
+        for (pk, message, signature) in batch {
+            let challenge = C::ScalarField::from_le_bytes_mod_order(&challenge::<C, RO>(
+                parameters,
+                &signature.prover_commitment,
+                pk,
+                message,
+            )?);
+            let z = C::ScalarField::from(rng.gen::<u128>());
+            bases.push(signature.prover_commitment);
+            scalars.push((-z).into_repr());
+            bases.push(*pk);
+            scalars.push((-(z * challenge)).into_repr());
+            generator_scalar += z * signature.prover_response;
+        }
+        bases.push(parameters.generator);
+        scalars.push(generator_scalar.into_repr());
+        Ok(VariableBaseMSM::multi_scalar_mul(&bases, &scalars).is_zero())
+    }
+}
+
+/// Computes the challenge `e := H(salt || R || A || M)`, where `H` is the random oracle `RO`
+/// (with its default parameters), and `e` consists of the first 32 bytes of its output,
+/// like `schnorr::challenge` does.
+pub fn challenge<C: ProjectiveCurve, RO: RandomOracle>(
+    parameters: &Parameters<C>,
+    prover_commitment: &C::Affine,
+    pk: &PublicKey<C>,
+    message: &[u8],
+) -> Result<[u8; 32], Error> {
+    let mut hash_input = Vec::new();
+    if let Some(salt) = parameters.salt {
+        hash_input.extend_from_slice(&salt);
+    }
+    hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
+    hash_input.extend_from_slice(&to_bytes![pk]?);
+    hash_input.extend_from_slice(message);
+
+    let hash_digest = to_bytes![RO::evaluate(&RO::Parameters::default(), &hash_input)?]?;
+    assert!(hash_digest.len() >= 32);
+    let mut challenge = [0u8; 32];
+    challenge.copy_from_slice(&hash_digest[..32]);
+    Ok(challenge)
+}
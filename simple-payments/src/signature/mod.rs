@@ -1,3 +1,11 @@
+//! Signature schemes, and gadgets that verify their signatures in circuits.
+//!
+//! Keys can be randomized (see `SignatureScheme::randomize_public_key`), but signatures
+//! cannot: the challenges of `schnorr::Schnorr` and `eddsa::EdDSA` hash the signer's public
+//! key, so a signature under the randomized key needs a response to another challenge,
+//! which cannot be computed without the secret key. Instead, the holder of the secret key
+//! randomizes it (see `SignatureScheme::randomize_secret_key`), and signs under the
+//! randomized key.
 use ark_crypto_primitives::Error;
 use ark_ff::bytes::ToBytes;
 use ark_std::hash::Hash;
@@ -8,6 +16,8 @@ pub mod constraints;
 #[cfg(feature = "r1cs")]
 pub use constraints::*;
 
+pub mod aggregation;
+pub mod eddsa;
 pub mod schnorr;
 
 pub trait SignatureScheme {
@@ -36,20 +46,44 @@ pub trait SignatureScheme {
         message: &[u8],
         signature: &Self::Signature,
     ) -> Result<bool, Error>;
+
+    /// Randomize the public key `public_key` with `randomness`, such that signatures by
+    /// the secret key randomized with the same `randomness` (see `randomize_secret_key`)
+    /// verify under the result. Keys randomized with fresh randomness are unlinkable to
+    /// the original ones.
+    fn randomize_public_key(
+        pp: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error>;
+
+    /// Randomize the secret key `secret_key` with `randomness`, like
+    /// `randomize_public_key`.
+    fn randomize_secret_key(
+        pp: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error>;
 }
 
 #[cfg(test)]
 mod test {
+    use crate::random_oracle::RandomOracle;
+    use crate::random_oracle::{blake2s, poseidon};
     use crate::signature::{schnorr, *};
-    use ark_ed_on_bls12_381::EdwardsProjective as JubJub;
+    use ark_ec::{AffineCurve, ProjectiveCurve};
+    use ark_ed_on_bls12_381::Fr;
+    use ark_ed_on_bls12_381::{EdwardsProjective as JubJub, Fq};
+    use ark_serialize::CanonicalSerialize;
+    use ark_std::rand::{rngs::StdRng, Rng, SeedableRng};
     use ark_std::test_rng;
 
     fn sign_and_verify<S: SignatureScheme>(message: &[u8]) {
         let rng = &mut test_rng();
         let parameters = S::setup::<_>(rng).unwrap();
         let (pk, sk) = S::keygen(&parameters, rng).unwrap();
-        let sig = S::sign(&parameters, &sk, &message, rng).unwrap();
-        assert!(S::verify(&parameters, &pk, &message, &sig).unwrap());
+        let sig = S::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &pk, message, &sig).unwrap());
     }
 
     fn failed_verification<S: SignatureScheme>(message: &[u8], bad_message: &[u8]) {
@@ -69,4 +103,204 @@ mod test {
             "Bad message".as_bytes(),
         );
     }
+
+    #[test]
+    fn eddsa_signature_test() {
+        type S = eddsa::EdDSA<JubJub>;
+        let message = "Hi, I am an EdDSA signature!".as_bytes();
+        sign_and_verify::<S>(message);
+        sign_and_verify::<eddsa::EdDSA<JubJub, poseidon::RO<Fq>>>(message);
+        failed_verification::<S>(message, "Bad message".as_bytes());
+
+        // A known answer, consisting of the prover commitment and response.
+        let (parameters, sk) = fixed_schnorr_key::<blake2s::RO>();
+        let sig = S::sign(&parameters, &sk, message, &mut test_rng()).unwrap();
+        let mut bytes = Vec::new();
+        sig.serialize(&mut bytes).unwrap();
+        let hex = bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        assert_eq!(
+            hex,
+            "2a407712b6a0e9a818c18ebe6de3c39e07173b46d946c9b1c06ecf7fd16d4985\
+             24cb8c6eaeb42df8ff1ec00fd131cfe4f8851f71f7be383db0427b708c36dd04"
+        );
+
+        // The same keys sign for both schemes, but their signatures are not interchangeable.
+        let schnorr_sig =
+            schnorr::Schnorr::<JubJub>::sign(&parameters, &sk, message, &mut test_rng()).unwrap();
+        let schnorr_commitment =
+            schnorr::prover_commitment(&parameters, &sk.public_key, &schnorr_sig).into_affine();
+        assert_ne!(schnorr_commitment, sig.prover_commitment);
+        let forged_sig = eddsa::Signature {
+            prover_commitment: schnorr_commitment,
+            prover_response: -schnorr_sig.prover_response,
+        };
+        assert!(!S::verify(&parameters, &sk.public_key, message, &forged_sig).unwrap());
+    }
+
+    #[test]
+    fn eddsa_batch_verification_test() {
+        type S = eddsa::EdDSA<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let messages = ["first", "second", "third"].map(str::as_bytes);
+        let signed = messages
+            .iter()
+            .map(|message| {
+                let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+                (pk, S::sign(&parameters, &sk, message, rng).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let batch = signed
+            .iter()
+            .zip(messages)
+            .map(|((pk, sig), message)| (*pk, message, sig))
+            .collect::<Vec<_>>();
+        assert!(S::batch_verify(&parameters, &batch, rng).unwrap());
+
+        let mut bad_batch = batch;
+        bad_batch[0].1 = messages[1];
+        assert!(!S::batch_verify(&parameters, &bad_batch, rng).unwrap());
+    }
+
+    /// The signature scheme `schnorr::Schnorr<JubJub, RO>` with the default parameters,
+    /// and the secret key `42`.
+    fn fixed_schnorr_key<RO: RandomOracle>(
+    ) -> (schnorr::Parameters<JubJub>, schnorr::SecretKey<JubJub>) {
+        let parameters = schnorr::Schnorr::<JubJub, RO>::setup(&mut test_rng()).unwrap();
+        let secret_key = Fr::from(42u64);
+        let public_key = parameters.generator.mul(secret_key).into_affine();
+        let sk = schnorr::SecretKey {
+            secret_key,
+            public_key,
+        };
+        (parameters, sk)
+    }
+
+    fn to_hex(signature: &schnorr::Signature<JubJub>) -> String {
+        let mut bytes = Vec::new();
+        signature.serialize(&mut bytes).unwrap();
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn deterministic_signature<RO: RandomOracle>(message: &[u8]) -> String {
+        type S<RO> = schnorr::Schnorr<JubJub, RO>;
+        let (parameters, sk) = fixed_schnorr_key::<RO>();
+        let sig = S::<RO>::sign(&parameters, &sk, message, &mut test_rng()).unwrap();
+        assert!(S::<RO>::verify(&parameters, &sk.public_key, message, &sig).unwrap());
+        // The signature does not depend on the RNG.
+        let other_sig = S::<RO>::sign(&parameters, &sk, message, &mut StdRng::seed_from_u64(1));
+        assert_eq!(to_hex(&other_sig.unwrap()), to_hex(&sig));
+        to_hex(&sig)
+    }
+
+    #[test]
+    fn deterministic_schnorr_signature_test() {
+        // Known answers, each consisting of the prover response and the verifier challenge.
+        let message = "Hi, I am a deterministic Schnorr signature!".as_bytes();
+        assert_eq!(
+            deterministic_signature::<blake2s::RO>(message),
+            "7f891d0c162d67bdaa10490f6af1c7a269ba453d6722e7a04efc525a38499c0a\
+             0c1da2c5d0815ee251e2128e77b9b4c10bde61453eee8f11b2529c741c9de1b7"
+        );
+        assert_eq!(
+            deterministic_signature::<blake2s::RO>(b""),
+            "60468c3c6fdd7736a2d7491ab756763fdc9e337222d133568941e154b4888907\
+             ff9f90591a64778f546cf206dad772562809465e7120a12bf4d436a2ef863c21"
+        );
+        assert_eq!(
+            deterministic_signature::<poseidon::RO<Fq>>(message),
+            "3dc51bec2ace61dde2d01254c5697f47059fe55522b21e69873facc13fda5d08\
+             04faa8f038d3f6b8575f99c8f95af2ce029c50b1fc987d7e4014b4f725fda02b"
+        );
+
+        // The nonce depends on the salt, and the RNG-based variant is still available.
+        type S = schnorr::Schnorr<JubJub>;
+        let (mut parameters, sk) = fixed_schnorr_key::<blake2s::RO>();
+        parameters.salt = Some([1u8; 32]);
+        let commitment = |sig| schnorr::prover_commitment(&parameters, &sk.public_key, sig);
+        let sig = S::sign(&parameters, &sk, message, &mut test_rng()).unwrap();
+        let unsalted_parameters = fixed_schnorr_key::<blake2s::RO>().0;
+        let unsalted_sig = S::sign(&unsalted_parameters, &sk, message, &mut test_rng()).unwrap();
+        assert_ne!(commitment(&sig), commitment(&unsalted_sig));
+        let rng = &mut test_rng();
+        let random_sig = S::sign_with_rng(&parameters, &sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &sk.public_key, message, &random_sig).unwrap());
+        assert_ne!(commitment(&random_sig), commitment(&sig));
+        let other_random_sig = S::sign_with_rng(&parameters, &sk, message, rng).unwrap();
+        assert_ne!(commitment(&other_random_sig), commitment(&random_sig));
+    }
+
+    #[test]
+    fn randomized_schnorr_keys_test() {
+        type S = schnorr::Schnorr<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+        let randomness = rng.gen::<[u8; 32]>();
+        let randomized_pk = S::randomize_public_key(&parameters, &pk, &randomness).unwrap();
+        let randomized_sk = S::randomize_secret_key(&parameters, &sk, &randomness).unwrap();
+        assert_ne!(randomized_pk, pk);
+        assert_eq!(randomized_sk.public_key, randomized_pk);
+
+        let message = "Hi, I am a Schnorr signature under a randomized key!".as_bytes();
+        let sig = S::sign(&parameters, &randomized_sk, message, rng).unwrap();
+        assert!(S::verify(&parameters, &randomized_pk, message, &sig).unwrap());
+        assert!(!S::verify(&parameters, &pk, message, &sig).unwrap());
+
+        // Other randomness yields another key.
+        let other_pk = S::randomize_public_key(&parameters, &pk, &[1u8; 32]).unwrap();
+        assert!(!S::verify(&parameters, &other_pk, message, &sig).unwrap());
+    }
+
+    #[test]
+    fn challenges_depend_on_the_random_oracle() {
+        type BlakeSchnorr = schnorr::Schnorr<JubJub>;
+        type PoseidonSchnorr = schnorr::Schnorr<JubJub, poseidon::RO<Fq>>;
+        let message = "Hi, I am a Schnorr signature!".as_bytes();
+        sign_and_verify::<PoseidonSchnorr>(message);
+
+        let rng = &mut test_rng();
+        let parameters = BlakeSchnorr::setup::<_>(rng).unwrap();
+        let (pk, sk) = BlakeSchnorr::keygen(&parameters, rng).unwrap();
+        let sig = BlakeSchnorr::sign(&parameters, &sk, message, rng).unwrap();
+        assert!(!PoseidonSchnorr::verify(&parameters, &pk, message, &sig).unwrap());
+    }
+
+    #[test]
+    fn schnorr_batch_verification_test() {
+        type S = schnorr::Schnorr<JubJub>;
+        let rng = &mut test_rng();
+        let parameters = S::setup::<_>(rng).unwrap();
+        let messages = ["first", "second", "third"].map(str::as_bytes);
+        let signed = messages
+            .iter()
+            .map(|message| {
+                let (pk, sk) = S::keygen(&parameters, rng).unwrap();
+                (pk, S::sign(&parameters, &sk, message, rng).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let batch = signed
+            .iter()
+            .zip(messages)
+            .map(|((pk, sig), message)| (*pk, message, sig))
+            .collect::<Vec<_>>();
+        assert!(S::batch_verify(&parameters, &batch, rng).unwrap());
+        assert!(S::batch_verify(&parameters, &[], rng).unwrap());
+
+        // Signing the wrong message invalidates the batch.
+        let mut bad_batch = batch.clone();
+        bad_batch[0].1 = messages[1];
+        assert!(!S::batch_verify(&parameters, &bad_batch, rng).unwrap());
+
+        // So does a response that does not match the challenge.
+        let mut bad_sig = batch[2].2.clone();
+        bad_sig.prover_response += Fr::from(1u64);
+        let mut bad_batch = batch.clone();
+        bad_batch[2].2 = &bad_sig;
+        assert!(!S::verify(&parameters, &batch[2].0, batch[2].1, &bad_sig).unwrap());
+        assert!(!S::batch_verify(&parameters, &bad_batch, rng).unwrap());
+    }
 }
@@ -1,17 +1,17 @@
 use ark_ec::ProjectiveCurve;
-use ark_ff::{to_bytes, Field};
+use ark_ff::{to_bytes, BitIteratorBE, Field, FpParameters, PrimeField};
 use ark_r1cs_std::{bits::uint8::UInt8, prelude::*};
 use ark_relations::r1cs::ConstraintSystemRef;
 use ark_relations::r1cs::{Namespace, SynthesisError};
 use ark_std::vec::Vec;
 
-use crate::random_oracle::blake2s::constraints::{ParametersVar as B2SParamsVar, ROGadget};
-use crate::random_oracle::RandomOracleGadget;
-use crate::signature::SigVerifyGadget;
+use crate::random_oracle::blake2s::constraints::ROGadget;
+use crate::random_oracle::{RandomOracle, RandomOracleGadget};
+use crate::signature::{SigRandomizePkGadget, SigVerifyGadget};
 
 use derivative::Derivative;
 
-use core::{borrow::Borrow, marker::PhantomData};
+use core::{borrow::Borrow, hash::Hash, marker::PhantomData};
 
 use crate::signature::schnorr::{Parameters, PublicKey, Schnorr, Signature};
 
@@ -22,8 +22,8 @@ pub struct ParametersVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
 where
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
-    generator: GC,
-    salt: Option<Vec<UInt8<ConstraintF<C>>>>,
+    pub(crate) generator: GC,
+    pub(crate) salt: Option<Vec<UInt8<ConstraintF<C>>>>,
     _curve: PhantomData<C>,
 }
 
@@ -36,7 +36,7 @@ pub struct PublicKeyVar<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
 where
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
-    pub_key: GC,
+    pub(crate) pub_key: GC,
     #[doc(hidden)]
     _group: PhantomData<*const C>,
 }
@@ -56,21 +56,30 @@ where
     _group: PhantomData<GC>,
 }
 
-pub struct SchnorrSignatureVerifyGadget<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
-where
+/// Verifies signatures of `Schnorr<C, RO>`, where `ROG` is the gadget for `RO`.
+pub struct SchnorrSignatureVerifyGadget<
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    ROG = ROGadget,
+> where
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
 {
     #[doc(hidden)]
     _group: PhantomData<*const C>,
     #[doc(hidden)]
     _group_gadget: PhantomData<*const GC>,
+    #[doc(hidden)]
+    _random_oracle_gadget: PhantomData<*const ROG>,
 }
 
-impl<C, GC> SigVerifyGadget<Schnorr<C>, ConstraintF<C>> for SchnorrSignatureVerifyGadget<C, GC>
+impl<C, GC, RO, ROG> SigVerifyGadget<Schnorr<C, RO>, ConstraintF<C>>
+    for SchnorrSignatureVerifyGadget<C, GC, ROG>
 where
     C: ProjectiveCurve,
     GC: CurveVar<C, ConstraintF<C>>,
     for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
 {
     type ParametersVar = ParametersVar<C, GC>;
     type PublicKeyVar = PublicKeyVar<C, GC>;
@@ -92,24 +101,87 @@ where
             .scalar_mul_le(verifier_challenge.to_bits_le()?.iter())?;
         claimed_prover_commitment += &public_key_times_verifier_challenge;
 
-        let mut hash_input = Vec::new();
-        if parameters.salt.is_some() {
-            hash_input.extend_from_slice(parameters.salt.as_ref().unwrap());
-        }
-        hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
-        hash_input.extend_from_slice(&claimed_prover_commitment.to_bytes()?);
-        hash_input.extend_from_slice(message);
-
-        let b2s_params = <B2SParamsVar as AllocVar<_, ConstraintF<C>>>::new_constant(
-            ConstraintSystemRef::None,
-            (),
+        let obtained_verifier_challenge = challenge::<C, GC, RO, ROG>(
+            parameters,
+            public_key,
+            &claimed_prover_commitment,
+            message,
         )?;
-        let obtained_verifier_challenge = ROGadget::evaluate(&b2s_params, &hash_input)?.0;
 
-        obtained_verifier_challenge.is_eq(&verifier_challenge.to_vec())
+        obtained_verifier_challenge.is_eq(&verifier_challenge)
+    }
+}
+
+/// Randomizes public keys of `Schnorr<C, RO>` like `Schnorr::randomize_public_key`.
+pub struct SchnorrRandomizePkGadget<C: ProjectiveCurve, GC: CurveVar<C, ConstraintF<C>>>
+where
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    #[doc(hidden)]
+    _group: PhantomData<*const C>,
+    #[doc(hidden)]
+    _group_gadget: PhantomData<*const GC>,
+}
+
+impl<C, GC, RO> SigRandomizePkGadget<Schnorr<C, RO>, ConstraintF<C>>
+    for SchnorrRandomizePkGadget<C, GC>
+where
+    C: ProjectiveCurve + Hash,
+    C::ScalarField: PrimeField,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+{
+    type ParametersVar = ParametersVar<C, GC>;
+    type PublicKeyVar = PublicKeyVar<C, GC>;
+
+    fn randomize(
+        parameters: &Self::ParametersVar,
+        public_key: &Self::PublicKeyVar,
+        randomness: &[UInt8<ConstraintF<C>>],
+    ) -> Result<Self::PublicKeyVar, SynthesisError> {
+        // The generator has prime order, so multiplying it by the randomness is the same
+        // as multiplying it by the randomness reduced to a scalar.
+        let generator_times_randomness = parameters
+            .generator
+            .scalar_mul_le(randomness.to_bits_le()?.iter())?;
+        Ok(PublicKeyVar {
+            pub_key: public_key.pub_key.clone() + generator_times_randomness,
+            _group: PhantomData,
+        })
     }
 }
 
+/// Computes the verifier challenge like `schnorr::challenge`, with the gadget `ROG` for
+/// the random oracle `RO`.
+pub fn challenge<C, GC, RO, ROG>(
+    parameters: &ParametersVar<C, GC>,
+    public_key: &PublicKeyVar<C, GC>,
+    prover_commitment: &GC,
+    message: &[UInt8<ConstraintF<C>>],
+) -> Result<Vec<UInt8<ConstraintF<C>>>, SynthesisError>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+    RO: RandomOracle,
+    ROG: RandomOracleGadget<RO, ConstraintF<C>>,
+{
+    let mut hash_input = Vec::new();
+    if let Some(salt) = parameters.salt.as_ref() {
+        hash_input.extend_from_slice(salt);
+    }
+    hash_input.extend_from_slice(&public_key.pub_key.to_bytes()?);
+    hash_input.extend_from_slice(&prover_commitment.to_bytes()?);
+    hash_input.extend_from_slice(message);
+
+    let ro_params =
+        ROG::ParametersVar::new_constant(ConstraintSystemRef::None, RO::Parameters::default())?;
+    let mut hash_digest = ROG::evaluate(&ro_params, &hash_input)?.to_bytes()?;
+    hash_digest.truncate(32);
+    Ok(hash_digest)
+}
+
 impl<C, GC> AllocVar<Parameters<C>, ConstraintF<C>> for ParametersVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -150,6 +222,30 @@ where
     }
 }
 
+impl<C, GC> PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    /// Whether the key is in the prime-order subgroup, i.e., whether multiplying it by
+    /// the order of the subgroup yields the identity. Unlike the check that allocating
+    /// a witness performs, this does not depend on how the key was allocated.
+    pub fn is_in_prime_order_subgroup(&self) -> Result<Boolean<ConstraintF<C>>, SynthesisError> {
+        let modulus = <C::ScalarField as PrimeField>::Params::MODULUS;
+        // The bits of the order are constants, so double-and-add only needs to add the
+        // key for the bits that are set.
+        let mut multiple = GC::zero();
+        for bit in BitIteratorBE::without_leading_zeros(modulus) {
+            multiple.double_in_place()?;
+            if bit {
+                multiple += &self.pub_key;
+            }
+        }
+        multiple.is_eq(&GC::zero())
+    }
+}
+
 impl<C, GC> AllocVar<PublicKey<C>, ConstraintF<C>> for PublicKeyVar<C, GC>
 where
     C: ProjectiveCurve,
@@ -241,6 +337,26 @@ where
     }
 }
 
+impl<C, GC> CondSelectGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
+where
+    C: ProjectiveCurve,
+    GC: CurveVar<C, ConstraintF<C>>,
+    for<'a> &'a GC: GroupOpsBounds<'a, C, GC>,
+{
+    #[inline]
+    fn conditionally_select(
+        cond: &Boolean<ConstraintF<C>>,
+        true_value: &Self,
+        false_value: &Self,
+    ) -> Result<Self, SynthesisError> {
+        let pub_key = GC::conditionally_select(cond, &true_value.pub_key, &false_value.pub_key)?;
+        Ok(Self {
+            pub_key,
+            _group: PhantomData,
+        })
+    }
+}
+
 impl<C, GC> ToBytesGadget<ConstraintF<C>> for PublicKeyVar<C, GC>
 where
     C: ProjectiveCurve,
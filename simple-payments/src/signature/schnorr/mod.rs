@@ -1,4 +1,5 @@
 use super::SignatureScheme;
+use crate::random_oracle::{blake2s, RandomOracle};
 use ark_crypto_primitives::Error;
 use ark_ec::{AffineCurve, ProjectiveCurve};
 use ark_ff::{
@@ -6,18 +7,20 @@ use ark_ff::{
     fields::{Field, PrimeField},
     to_bytes, ToConstraintField, UniformRand,
 };
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError};
 use ark_std::io::{Result as IoResult, Write};
 use ark_std::rand::Rng;
 use ark_std::{hash::Hash, marker::PhantomData, vec::Vec};
-use blake2::Blake2s;
-use digest::Digest;
 
 use derivative::Derivative;
 #[cfg(feature = "r1cs")]
 pub mod constraints;
 
-pub struct Schnorr<C: ProjectiveCurve> {
+/// Schnorr signatures over the group `C`, whose verifier challenges are computed with the
+/// random oracle `RO`.
+pub struct Schnorr<C: ProjectiveCurve, RO: RandomOracle = blake2s::RO> {
     _group: PhantomData<C>,
+    _random_oracle: PhantomData<RO>,
 }
 
 #[derive(Derivative)]
@@ -48,7 +51,60 @@ pub struct Signature<C: ProjectiveCurve> {
     pub verifier_challenge: [u8; 32],
 }
 
-impl<C: ProjectiveCurve + Hash> SignatureScheme for Schnorr<C>
+impl<C: ProjectiveCurve> CanonicalSerialize for Parameters<C> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.generator.serialize(&mut writer)?;
+        self.salt.is_some().serialize(&mut writer)?;
+        if let Some(salt) = self.salt {
+            writer.write_all(&salt)?;
+        }
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.generator.serialized_size() + 1 + self.salt.map_or(0, |salt| salt.len())
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalDeserialize for Parameters<C> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let generator = C::Affine::deserialize(&mut reader)?;
+        let salt = if bool::deserialize(&mut reader)? {
+            let mut salt = [0u8; 32];
+            reader.read_exact(&mut salt)?;
+            Some(salt)
+        } else {
+            None
+        };
+        Ok(Self { generator, salt })
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalSerialize for Signature<C> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.prover_response.serialize(&mut writer)?;
+        writer.write_all(&self.verifier_challenge)?;
+        Ok(())
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.prover_response.serialized_size() + self.verifier_challenge.len()
+    }
+}
+
+impl<C: ProjectiveCurve> CanonicalDeserialize for Signature<C> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let prover_response = C::ScalarField::deserialize(&mut reader)?;
+        let mut verifier_challenge = [0u8; 32];
+        reader.read_exact(&mut verifier_challenge)?;
+        Ok(Self {
+            prover_response,
+            verifier_challenge,
+        })
+    }
+}
+
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> SignatureScheme for Schnorr<C, RO>
 where
     C::ScalarField: PrimeField,
 {
@@ -88,39 +144,95 @@ where
         ))
     }
 
+    /// Sign `message` with a nonce derived from `sk` and `message` (see `nonce`), so that
+    /// the signature does not depend on `rng`, which is unused. `Schnorr::sign_with_rng`
+    /// samples the nonce from `rng` instead.
     fn sign<R: Rng>(
         parameters: &Self::Parameters,
         sk: &Self::SecretKey,
         message: &[u8],
-        rng: &mut R,
+        _rng: &mut R,
     ) -> Result<Self::Signature, Error> {
-        // let sign_time = start_timer!(|| "SchnorrSig::Sign");
-        // (k, e);
-        let (random_scalar, verifier_challenge) = {
-            // Sample a random scalar `k` from the prime scalar field.
-            let random_scalar: C::ScalarField = C::ScalarField::rand(rng);
-            // Commit to the random scalar via r := k · G.
-            // This is the prover's first msg in the Sigma protocol.
-            let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
-
-            // Hash everything to get verifier challenge.
-            // e := H(salt || pubkey || r || msg);
-            let mut hash_input = Vec::new();
-            if parameters.salt != None {
-                hash_input.extend_from_slice(&parameters.salt.unwrap());
-            }
-            hash_input.extend_from_slice(&to_bytes![sk.public_key]?);
-            hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
-            hash_input.extend_from_slice(message);
+        let random_scalar = nonce::<C, RO>(parameters, sk, message)?;
+        Self::sign_with_nonce(parameters, sk, message, random_scalar)
+    }
 
-            let hash_digest = Blake2s::digest(&hash_input);
-            assert!(hash_digest.len() >= 32);
-            let mut verifier_challenge = [0u8; 32];
-            verifier_challenge.copy_from_slice(&hash_digest);
+    fn verify(
+        parameters: &Self::Parameters,
+        pk: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<bool, Error> {
+        // let verify_time = start_timer!(|| "SchnorrSig::Verify");
 
-            (random_scalar, verifier_challenge)
-        };
+        let claimed_prover_commitment = prover_commitment(parameters, pk, signature).into_affine();
+
+        // e = H(salt, kG, msg)
+        let obtained_verifier_challenge =
+            challenge::<C, RO>(parameters, pk, &claimed_prover_commitment, message)?;
+        // end_timer!(verify_time);
+        // The signature is valid iff the computed verifier challenge is the same as the one
+        // provided in the signature
+        Ok(signature.verifier_challenge == obtained_verifier_challenge)
+    }
+
+    fn randomize_public_key(
+        parameters: &Self::Parameters,
+        public_key: &Self::PublicKey,
+        randomness: &[u8],
+    ) -> Result<Self::PublicKey, Error> {
+        // Y' = Y + r · G, where r is the randomness read as a scalar.
+        let randomness = C::ScalarField::from_le_bytes_mod_order(randomness);
+        let randomized_pk = public_key.into_projective() + parameters.generator.mul(randomness);
+        Ok(randomized_pk.into_affine())
+    }
+
+    fn randomize_secret_key(
+        parameters: &Self::Parameters,
+        secret_key: &Self::SecretKey,
+        randomness: &[u8],
+    ) -> Result<Self::SecretKey, Error> {
+        // x' = x + r, so that x' · G = Y'.
+        Ok(SecretKey {
+            secret_key: secret_key.secret_key + C::ScalarField::from_le_bytes_mod_order(randomness),
+            public_key: Self::randomize_public_key(parameters, &secret_key.public_key, randomness)?,
+        })
+    }
+}
 
+impl<C: ProjectiveCurve + Hash, RO: RandomOracle> Schnorr<C, RO>
+where
+    C::ScalarField: PrimeField,
+{
+    /// Sign `message` like `SignatureScheme::sign`, but with a nonce sampled from `rng`.
+    /// If `rng` is weak, the signatures may leak the secret key.
+    pub fn sign_with_rng<R: Rng>(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        rng: &mut R,
+    ) -> Result<Signature<C>, Error> {
+        // Sample a random scalar `k` from the prime scalar field.
+        let random_scalar: C::ScalarField = C::ScalarField::rand(rng);
+        Self::sign_with_nonce(parameters, sk, message, random_scalar)
+    }
+
+    /// Sign `message` with the nonce `random_scalar`, which must not be used to sign
+    /// anything else.
+    fn sign_with_nonce(
+        parameters: &Parameters<C>,
+        sk: &SecretKey<C>,
+        message: &[u8],
+        random_scalar: C::ScalarField,
+    ) -> Result<Signature<C>, Error> {
+        // let sign_time = start_timer!(|| "SchnorrSig::Sign");
+        // Commit to the random scalar `k` via r := k · G.
+        // This is the prover's first msg in the Sigma protocol.
+        let prover_commitment = parameters.generator.mul(random_scalar).into_affine();
+
+        // Hash everything to get verifier challenge.
+        let verifier_challenge =
+            challenge::<C, RO>(parameters, &sk.public_key, &prover_commitment, message)?;
         let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(&verifier_challenge);
 
         // k - xe;
@@ -134,43 +246,123 @@ where
         Ok(signature)
     }
 
-    fn verify(
-        parameters: &Self::Parameters,
-        pk: &Self::PublicKey,
-        message: &[u8],
-        signature: &Self::Signature,
+    /// Verify the signatures of a batch of `(public key, message, signature)` triples at
+    /// once, and return whether all of them are valid.
+    ///
+    /// Signatures do not carry their prover commitments, so there is no equation to
+    /// combine across the batch: each commitment `r_i = s_i · G + e_i · Y_i` has to be
+    /// recomputed, and hashed to check the challenge `e_i`. We save on converting all of
+    /// them to affine coordinates at once, which takes a single field inversion. `rng` is
+    /// unused, since the check is deterministic. `EdDSA::batch_verify` checks a random
+    /// linear combination of its signatures instead, since they carry their commitments.
+    pub fn batch_verify<R: Rng>(
+        parameters: &Parameters<C>,
+        batch: &[(PublicKey<C>, &[u8], &Signature<C>)],
+        _rng: &mut R,
     ) -> Result<bool, Error> {
-        // let verify_time = start_timer!(|| "SchnorrSig::Verify");
+        let prover_commitments = batch
+            .iter()
+            .map(|(pk, _, signature)| prover_commitment(parameters, pk, signature))
+            .collect::<Vec<_>>();
+        let prover_commitments = C::batch_normalization_into_affine(&prover_commitments);
+        for ((pk, message, signature), prover_commitment) in batch.iter().zip(&prover_commitments) {
+            let obtained_verifier_challenge =
+                challenge::<C, RO>(parameters, pk, prover_commitment, message)?;
+            if signature.verifier_challenge != obtained_verifier_challenge {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
 
-        let Signature {
-            prover_response,
-            verifier_challenge,
-        } = signature;
-        let verifier_challenge_fe = C::ScalarField::from_le_bytes_mod_order(verifier_challenge);
-        // sG = kG - eY
-        // kG = sG + eY
-        // so we first solve for kG.
-        let mut claimed_prover_commitment = parameters.generator.mul(*prover_response);
-        let public_key_times_verifier_challenge = pk.mul(verifier_challenge_fe);
-        claimed_prover_commitment += &public_key_times_verifier_challenge;
-        let claimed_prover_commitment = claimed_prover_commitment.into_affine();
+/// Computes the verifier challenge `e := H(salt || pubkey || r || msg)`, where `H` is
+/// the random oracle `RO` (with its default parameters), and `e` consists of the first
+/// 32 bytes of its output.
+pub fn challenge<C: ProjectiveCurve, RO: RandomOracle>(
+    parameters: &Parameters<C>,
+    pk: &PublicKey<C>,
+    prover_commitment: &C::Affine,
+    message: &[u8],
+) -> Result<[u8; 32], Error> {
+    let mut hash_input = Vec::new();
+    if let Some(salt) = parameters.salt {
+        hash_input.extend_from_slice(&salt);
+    }
+    hash_input.extend_from_slice(&to_bytes![pk]?);
+    hash_input.extend_from_slice(&to_bytes![prover_commitment]?);
+    hash_input.extend_from_slice(message);
 
-        // e = H(salt, kG, msg)
+    let hash_digest = to_bytes![RO::evaluate(&RO::Parameters::default(), &hash_input)?]?;
+    assert!(hash_digest.len() >= 32);
+    let mut verifier_challenge = [0u8; 32];
+    verifier_challenge.copy_from_slice(&hash_digest[..32]);
+    Ok(verifier_challenge)
+}
+
+/// The prover commitment `r = k · G` that `signature` claims under `pk`, which is
+/// `s · G + e · Y`, in projective coordinates. The signature is valid iff its challenge is
+/// the hash of this commitment (see `challenge`).
+pub fn prover_commitment<C: ProjectiveCurve>(
+    parameters: &Parameters<C>,
+    pk: &PublicKey<C>,
+    signature: &Signature<C>,
+) -> C {
+    let verifier_challenge_fe =
+        C::ScalarField::from_le_bytes_mod_order(&signature.verifier_challenge);
+    // sG = kG - eY
+    // kG = sG + eY
+    // so we solve for kG.
+    let mut prover_commitment = parameters.generator.mul(signature.prover_response);
+    prover_commitment += &pk.mul(verifier_challenge_fe);
+    prover_commitment
+}
+
+/// Derives the nonce `k` of a signature by `sk` on `message` deterministically, like
+/// RFC 6979 and EdDSA do: `k` is read from the 64 bytes
+/// `H(salt || "schnorr-nonce" || i || sk || pubkey || msg)` for `i` in `0..2`, where `H` is
+/// the random oracle `RO`.
+///
+/// The nonce covers all the inputs of the challenge, including the random oracle: signing
+/// the same message under different challenges with the same nonce would reveal `sk`.
+pub fn nonce<C: ProjectiveCurve, RO: RandomOracle>(
+    parameters: &Parameters<C>,
+    sk: &SecretKey<C>,
+    message: &[u8],
+) -> Result<C::ScalarField, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    derive_nonce::<C, RO>(parameters, b"schnorr-nonce", sk, message)
+}
+
+/// Derives a nonce like `nonce`, with the domain tag `domain` instead of "schnorr-nonce".
+/// Other signature schemes with the same keys use other tags.
+pub(crate) fn derive_nonce<C: ProjectiveCurve, RO: RandomOracle>(
+    parameters: &Parameters<C>,
+    domain: &[u8],
+    sk: &SecretKey<C>,
+    message: &[u8],
+) -> Result<C::ScalarField, Error>
+where
+    C::ScalarField: PrimeField,
+{
+    let mut nonce_bytes = Vec::with_capacity(64);
+    for i in 0u8..2 {
         let mut hash_input = Vec::new();
-        if parameters.salt != None {
-            hash_input.extend_from_slice(&parameters.salt.unwrap());
+        if let Some(salt) = parameters.salt {
+            hash_input.extend_from_slice(&salt);
         }
-        hash_input.extend_from_slice(&to_bytes![pk]?);
-        hash_input.extend_from_slice(&to_bytes![claimed_prover_commitment]?);
+        hash_input.extend_from_slice(domain);
+        hash_input.push(i);
+        hash_input.extend_from_slice(&to_bytes![sk.secret_key, sk.public_key]?);
         hash_input.extend_from_slice(message);
 
-        // cast the hash output to get e
-        let obtained_verifier_challenge = &Blake2s::digest(&hash_input)[..];
-        // end_timer!(verify_time);
-        // The signature is valid iff the computed verifier challenge is the same as the one
-        // provided in the signature
-        Ok(verifier_challenge == obtained_verifier_challenge)
+        let hash_digest = to_bytes![RO::evaluate(&RO::Parameters::default(), &hash_input)?]?;
+        assert!(hash_digest.len() >= 32);
+        nonce_bytes.extend_from_slice(&hash_digest[..32]);
     }
+    Ok(C::ScalarField::from_le_bytes_mod_order(&nonce_bytes))
 }
 
 pub fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
@@ -0,0 +1,316 @@
+use ark_crypto_primitives::crh::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::{
+    Config, LeafDigest, LeafParam, Path, TwoToOneDigest, TwoToOneParam,
+};
+use ark_crypto_primitives::Error;
+use ark_ff::{to_bytes, ToBytes};
+use std::collections::HashMap;
+
+/// A Merkle tree whose leaves are all zero-filled initially, and which only stores
+/// the nodes that differ from those of such a blank tree. This lets us support trees with up to
+/// 2^32 leaves, of which only a handful are in use.
+///
+/// The tree hashes leaves and nodes exactly like `ark_crypto_primitives`'s `MerkleTree`,
+/// so it has the same root, and its paths verify against the same gadgets.
+#[derive(Clone)]
+pub struct SparseMerkleTree<P: Config> {
+    /// The hash of a zero-filled leaf.
+    empty_leaf_hash: LeafDigest<P>,
+    /// `empty_non_leaf_hashes[i]` is the hash of a non-leaf node at level `i` (where the
+    /// root is at level 0) whose descendant leaves are all zero-filled.
+    empty_non_leaf_hashes: Vec<TwoToOneDigest<P>>,
+    /// The hashes of the leaves that have been set, indexed by leaf position.
+    leaf_nodes: HashMap<usize, LeafDigest<P>>,
+    /// `non_leaf_nodes[i]` stores the hashes of the non-leaf nodes at level `i` that
+    /// have been recomputed, indexed by their position within the level.
+    non_leaf_nodes: Vec<HashMap<usize, TwoToOneDigest<P>>>,
+    /// Store the two-to-one hash parameters
+    two_to_one_hash_param: TwoToOneParam<P>,
+    /// Store the leaf hash parameters
+    leaf_hash_param: LeafParam<P>,
+    /// Stores the height of the tree (i.e., the number of levels, including the leaves).
+    height: usize,
+}
+
+impl<P: Config> SparseMerkleTree<P> {
+    /// Create a tree with `2^(height - 1)` zero-filled leaves, matching `MerkleTree::blank`.
+    /// This only computes one hash per level.
+    pub fn blank(
+        leaf_hash_param: &LeafParam<P>,
+        two_to_one_hash_param: &TwoToOneParam<P>,
+        height: usize,
+    ) -> Result<Self, Error> {
+        if height < 2 || height > usize::BITS as usize {
+            return Err("unsupported Merkle tree height".into());
+        }
+        let empty_leaf = vec![0u8; P::LeafHash::INPUT_SIZE_BITS / 8];
+        let empty_leaf_hash = P::LeafHash::evaluate(leaf_hash_param, &to_bytes!(empty_leaf)?)?;
+
+        // Compute the empty hashes from the bottom non-leaf level upwards.
+        let empty_leaf_bytes = to_bytes!(empty_leaf_hash)?;
+        let mut empty_non_leaf_hashes = vec![P::TwoToOneHash::evaluate(
+            two_to_one_hash_param,
+            &empty_leaf_bytes,
+            &empty_leaf_bytes,
+        )?];
+        for _ in 0..height - 2 {
+            let child_bytes = to_bytes!(empty_non_leaf_hashes.last().unwrap())?;
+            empty_non_leaf_hashes.push(P::TwoToOneHash::evaluate(
+                two_to_one_hash_param,
+                &child_bytes,
+                &child_bytes,
+            )?);
+        }
+        empty_non_leaf_hashes.reverse();
+
+        Ok(Self {
+            empty_leaf_hash,
+            empty_non_leaf_hashes,
+            leaf_nodes: HashMap::new(),
+            non_leaf_nodes: vec![HashMap::new(); height - 1],
+            two_to_one_hash_param: two_to_one_hash_param.clone(),
+            leaf_hash_param: leaf_hash_param.clone(),
+            height,
+        })
+    }
+
+    /// Returns the root of the Merkle tree.
+    pub fn root(&self) -> TwoToOneDigest<P> {
+        self.non_leaf_node(0, 0).clone()
+    }
+
+    /// Returns the height of the Merkle tree.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the leaf hash parameters.
+    pub fn leaf_hash_param(&self) -> &LeafParam<P> {
+        &self.leaf_hash_param
+    }
+
+    /// Returns the two-to-one hash parameters.
+    pub fn two_to_one_hash_param(&self) -> &TwoToOneParam<P> {
+        &self.two_to_one_hash_param
+    }
+
+    /// Returns the number of leaves in the Merkle tree.
+    pub fn num_leaves(&self) -> usize {
+        1 << (self.height - 1)
+    }
+
+    /// Returns the authentication path from leaf at `index` to root.
+    pub fn generate_proof(&self, index: usize) -> Result<Path<P>, Error> {
+        if index >= self.num_leaves() {
+            return Err("leaf index out of range".into());
+        }
+        let leaf_sibling_hash = self.leaf_node(index ^ 1).clone();
+        // The on-path node at level `i` is the ancestor of the leaf at position
+        // `index >> (leaf_level - i)`. The path stores the siblings of these nodes,
+        // from the top down, excluding the root.
+        let leaf_level = self.height - 1;
+        let auth_path = (1..leaf_level)
+            .map(|level| {
+                let on_path_index = index >> (leaf_level - level);
+                self.non_leaf_node(level, on_path_index ^ 1).clone()
+            })
+            .collect();
+        Ok(Path {
+            leaf_sibling_hash,
+            auth_path,
+            leaf_index: index,
+        })
+    }
+
+    /// Updates the leaf at `index` to `new_leaf`, and recomputes its ancestors.
+    pub fn update<L: ToBytes>(&mut self, index: usize, new_leaf: &L) -> Result<(), Error> {
+        let (leaf_hash, updated_path) = self.updated_path(index, new_leaf)?;
+        self.write_path(index, leaf_hash, updated_path);
+        Ok(())
+    }
+
+    /// Update the leaf and check if the updated root is equal to `asserted_new_root`.
+    ///
+    /// Tree will not be modified if the check fails.
+    pub fn check_update<L: ToBytes>(
+        &mut self,
+        index: usize,
+        new_leaf: &L,
+        asserted_new_root: &TwoToOneDigest<P>,
+    ) -> Result<bool, Error> {
+        let (leaf_hash, updated_path) = self.updated_path(index, new_leaf)?;
+        if &updated_path[0] != asserted_new_root {
+            return Ok(false);
+        }
+        self.write_path(index, leaf_hash, updated_path);
+        Ok(true)
+    }
+
+    /// Computes the hash of `new_leaf`, and the hashes of the non-leaf nodes from the
+    /// root down to the leaf at `index` that would result from setting that leaf to
+    /// `new_leaf`. The tree itself is left unchanged.
+    fn updated_path<L: ToBytes>(
+        &self,
+        index: usize,
+        new_leaf: &L,
+    ) -> Result<(LeafDigest<P>, Vec<TwoToOneDigest<P>>), Error> {
+        if index >= self.num_leaves() {
+            return Err("leaf index out of range".into());
+        }
+        let leaf_hash = P::LeafHash::evaluate(&self.leaf_hash_param, &to_bytes!(new_leaf)?)?;
+
+        // Recompute the bottom non-leaf node from the two leaves below it...
+        let (left, right) = ordered(index, &leaf_hash, self.leaf_node(index ^ 1));
+        let mut current_hash = P::TwoToOneHash::evaluate(
+            &self.two_to_one_hash_param,
+            &to_bytes!(left)?,
+            &to_bytes!(right)?,
+        )?;
+        let mut current_index = index >> 1;
+        let mut updated_path = Vec::with_capacity(self.height - 1);
+
+        // ...and then every node above it.
+        for level in (0..self.height - 2).rev() {
+            let sibling = self.non_leaf_node(level + 1, current_index ^ 1);
+            let (left, right) = ordered(current_index, &current_hash, sibling);
+            let parent_hash = P::TwoToOneHash::evaluate(
+                &self.two_to_one_hash_param,
+                &to_bytes!(left)?,
+                &to_bytes!(right)?,
+            )?;
+            updated_path.push(core::mem::replace(&mut current_hash, parent_hash));
+            current_index >>= 1;
+        }
+        updated_path.push(current_hash);
+        updated_path.reverse();
+        Ok((leaf_hash, updated_path))
+    }
+
+    /// Stores the output of `updated_path`. Nodes that are equal to the corresponding
+    /// node of a blank tree are removed rather than stored, so that the memory used
+    /// is proportional to the number of non-empty leaves.
+    fn write_path(
+        &mut self,
+        index: usize,
+        leaf_hash: LeafDigest<P>,
+        updated_path: Vec<TwoToOneDigest<P>>,
+    ) {
+        if leaf_hash == self.empty_leaf_hash {
+            self.leaf_nodes.remove(&index);
+        } else {
+            self.leaf_nodes.insert(index, leaf_hash);
+        }
+        let leaf_level = self.height - 1;
+        for (level, hash) in updated_path.into_iter().enumerate() {
+            let index_in_level = index >> (leaf_level - level);
+            if hash == self.empty_non_leaf_hashes[level] {
+                self.non_leaf_nodes[level].remove(&index_in_level);
+            } else {
+                self.non_leaf_nodes[level].insert(index_in_level, hash);
+            }
+        }
+    }
+
+    fn leaf_node(&self, index: usize) -> &LeafDigest<P> {
+        self.leaf_nodes.get(&index).unwrap_or(&self.empty_leaf_hash)
+    }
+
+    fn non_leaf_node(&self, level: usize, index: usize) -> &TwoToOneDigest<P> {
+        self.non_leaf_nodes[level]
+            .get(&index)
+            .unwrap_or(&self.empty_non_leaf_hashes[level])
+    }
+}
+
+/// Returns `(node, sibling)` if the node at `index` is a left child, and
+/// `(sibling, node)` otherwise.
+fn ordered<'a, T>(index: usize, node: &'a T, sibling: &'a T) -> (&'a T, &'a T) {
+    if index & 1 == 0 {
+        (node, sibling)
+    } else {
+        (sibling, node)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SparseMerkleTree;
+    use crate::ledger::{AccMerkleTree, MerkleConfig, Parameters};
+    use ark_crypto_primitives::crh::CRH;
+    use ark_crypto_primitives::merkle_tree::{Config, MerkleTree};
+
+    fn assert_same_tree(sparse: &AccMerkleTree, dense: &MerkleTree<MerkleConfig>) {
+        assert_eq!(sparse.root(), dense.root());
+        for index in 0..sparse.num_leaves() {
+            let sparse_path = sparse.generate_proof(index).unwrap();
+            let dense_path = dense.generate_proof(index).unwrap();
+            assert_eq!(sparse_path.leaf_index, dense_path.leaf_index);
+            assert_eq!(sparse_path.leaf_sibling_hash, dense_path.leaf_sibling_hash);
+            assert_eq!(sparse_path.auth_path, dense_path.auth_path);
+        }
+    }
+
+    #[test]
+    fn matches_dense_tree() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let height = 5;
+        let mut sparse: AccMerkleTree =
+            SparseMerkleTree::blank(&pp.leaf_crh_params, &pp.two_to_one_crh_params, height)
+                .unwrap();
+        let mut dense =
+            MerkleTree::blank(&pp.leaf_crh_params, &pp.two_to_one_crh_params, height).unwrap();
+        assert_same_tree(&sparse, &dense);
+
+        for (index, leaf) in [(0, [1u8; 8]), (7, [2u8; 8]), (6, [3u8; 8]), (15, [4u8; 8])] {
+            sparse.update(index, &leaf).unwrap();
+            dense.update(index, &leaf).unwrap();
+            assert_same_tree(&sparse, &dense);
+            let path = sparse.generate_proof(index).unwrap();
+            assert!(path
+                .verify(
+                    &pp.leaf_crh_params,
+                    &pp.two_to_one_crh_params,
+                    &sparse.root(),
+                    &leaf
+                )
+                .unwrap());
+        }
+
+        // `check_update` only applies updates that lead to the asserted root.
+        let mut expected = dense.clone();
+        expected.update(7, &[5u8; 8]).unwrap();
+        assert!(!sparse.check_update(7, &[5u8; 8], &dense.root()).unwrap());
+        assert_same_tree(&sparse, &dense);
+        assert!(sparse.check_update(7, &[5u8; 8], &expected.root()).unwrap());
+        assert_same_tree(&sparse, &expected);
+
+        assert!(sparse.update(16, &[0u8; 8]).is_err());
+        assert!(sparse.generate_proof(16).is_err());
+    }
+
+    #[test]
+    fn only_stores_non_empty_nodes() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let mut tree: AccMerkleTree =
+            SparseMerkleTree::blank(&pp.leaf_crh_params, &pp.two_to_one_crh_params, 33).unwrap();
+        assert_eq!(tree.num_leaves(), 1 << 32);
+        let blank_root = tree.root();
+        let stored_nodes = |tree: &AccMerkleTree| {
+            tree.leaf_nodes.len() + tree.non_leaf_nodes.iter().map(|l| l.len()).sum::<usize>()
+        };
+
+        tree.update((1 << 32) - 1, &[1u8; 8]).unwrap();
+        assert_eq!(stored_nodes(&tree), 33);
+        tree.update((1 << 32) - 2, &[2u8; 8]).unwrap();
+        assert_eq!(stored_nodes(&tree), 34);
+
+        // Clearing the leaves restores the blank tree, and frees the nodes.
+        let empty_leaf = vec![0u8; <MerkleConfig as Config>::LeafHash::INPUT_SIZE_BITS / 8];
+        tree.update((1 << 32) - 1, &empty_leaf).unwrap();
+        tree.update((1 << 32) - 2, &empty_leaf).unwrap();
+        assert_eq!(tree.root(), blank_root);
+        assert_eq!(stored_nodes(&tree), 0);
+    }
+}
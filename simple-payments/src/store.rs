@@ -0,0 +1,506 @@
+use crate::account::{AccountId, AccountPublicKey, MultisigPolicy};
+use crate::bridge::Deposit;
+use crate::ledger::{
+    AccountMerkleConfig, Amount, LedgerError, MerkleConfig, Parameters, SigScheme, State,
+    TransactionSigScheme,
+};
+use crate::transaction::Transaction;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
+use blake2::Blake2s;
+use derivative::Derivative;
+use digest::Digest;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+const PARAMETERS_FILE: &str = "parameters";
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "log";
+
+/// The size of the header of a log record: the payload length, followed by the
+/// Blake2s hash of the payload.
+const RECORD_HEADER_SIZE: usize = 4 + 32;
+
+/// The reasons for which a `LedgerStore` operation can fail.
+#[derive(Debug)]
+pub enum StoreError {
+    /// Reading or writing one of the store's files failed.
+    Io(io::Error),
+    /// One of the store's files could not be decoded.
+    Serialization(SerializationError),
+    /// The ledger rejected the operation.
+    Ledger(LedgerError),
+    /// The log contains an intact record that does not follow on from the snapshot.
+    CorruptLog,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Serialization(e) => write!(f, "serialization error: {}", e),
+            Self::Ledger(e) => write!(f, "ledger error: {}", e),
+            Self::CorruptLog => write!(f, "the log is out of sequence with the snapshot"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SerializationError> for StoreError {
+    fn from(e: SerializationError) -> Self {
+        Self::Serialization(e)
+    }
+}
+
+impl From<LedgerError> for StoreError {
+    fn from(e: LedgerError) -> Self {
+        Self::Ledger(e)
+    }
+}
+
+/// An operation on the ledger state, as recorded in the write-ahead log of a ledger whose
+/// transactions are signed with `S`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub enum LogEntry<S: TransactionSigScheme = SigScheme> {
+    /// See `State::register`.
+    Register(AccountPublicKey),
+    /// See `State::update_balance`.
+    UpdateBalance(AccountId, Amount),
+    /// See `State::set_fee_collector`.
+    SetFeeCollector(AccountId),
+    /// See `State::apply_transaction`.
+    Transaction(Box<Transaction<S>>),
+    /// See `State::register_multisig`.
+    RegisterMultisig(MultisigPolicy),
+    /// See `State::apply_deposit`.
+    Deposit(Deposit),
+}
+
+impl<S: TransactionSigScheme> LogEntry<S> {
+    /// Apply the operation to `state`. Like the `State` methods it calls, this leaves
+    /// `state` untouched if it fails.
+    pub fn apply<C: AccountMerkleConfig>(
+        &self,
+        parameters: &Parameters<C, S>,
+        state: &mut State<C>,
+    ) -> Result<(), LedgerError> {
+        match self {
+            Self::Register(public_key) => state.register(*public_key).map(|_| ()),
+            Self::UpdateBalance(id, amount) => state.update_balance(*id, *amount),
+            Self::SetFeeCollector(id) => state.set_fee_collector(*id),
+            Self::Transaction(tx) => state.apply_transaction(parameters, tx),
+            Self::RegisterMultisig(policy) => state.register_multisig(policy).map(|_| ()),
+            Self::Deposit(deposit) => state.apply_deposit(deposit),
+        }
+    }
+}
+
+impl<S: TransactionSigScheme> CanonicalSerialize for LogEntry<S> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        match self {
+            Self::Register(public_key) => {
+                0u8.serialize(&mut writer)?;
+                public_key.serialize(&mut writer)
+            }
+            Self::UpdateBalance(id, amount) => {
+                1u8.serialize(&mut writer)?;
+                id.serialize(&mut writer)?;
+                amount.serialize(&mut writer)
+            }
+            Self::SetFeeCollector(id) => {
+                2u8.serialize(&mut writer)?;
+                id.serialize(&mut writer)
+            }
+            Self::Transaction(tx) => {
+                3u8.serialize(&mut writer)?;
+                tx.serialize(&mut writer)
+            }
+            Self::RegisterMultisig(policy) => {
+                4u8.serialize(&mut writer)?;
+                policy.serialize(&mut writer)
+            }
+            Self::Deposit(deposit) => {
+                5u8.serialize(&mut writer)?;
+                deposit.recipient.serialize(&mut writer)?;
+                deposit.amount.serialize(&mut writer)
+            }
+        }
+    }
+
+    fn serialized_size(&self) -> usize {
+        1 + match self {
+            Self::Register(public_key) => public_key.serialized_size(),
+            Self::UpdateBalance(id, amount) => id.serialized_size() + amount.serialized_size(),
+            Self::SetFeeCollector(id) => id.serialized_size(),
+            Self::Transaction(tx) => tx.serialized_size(),
+            Self::RegisterMultisig(policy) => policy.serialized_size(),
+            Self::Deposit(deposit) => {
+                deposit.recipient.serialized_size() + deposit.amount.serialized_size()
+            }
+        }
+    }
+}
+
+impl<S: TransactionSigScheme> CanonicalDeserialize for LogEntry<S> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        match u8::deserialize(&mut reader)? {
+            0 => Ok(Self::Register(AccountPublicKey::deserialize(&mut reader)?)),
+            1 => Ok(Self::UpdateBalance(
+                AccountId::deserialize(&mut reader)?,
+                Amount::deserialize(&mut reader)?,
+            )),
+            2 => Ok(Self::SetFeeCollector(AccountId::deserialize(&mut reader)?)),
+            3 => Ok(Self::Transaction(Box::new(Transaction::deserialize(
+                &mut reader,
+            )?))),
+            4 => Ok(Self::RegisterMultisig(MultisigPolicy::deserialize(
+                &mut reader,
+            )?)),
+            5 => Ok(Self::Deposit(Deposit {
+                recipient: AccountId::deserialize(&mut reader)?,
+                amount: Amount::deserialize(&mut reader)?,
+            })),
+            _ => Err(SerializationError::InvalidData),
+        }
+    }
+}
+
+/// A ledger `State` that is persisted in a directory, so that it survives restarts
+/// and crashes.
+///
+/// The directory holds the ledger parameters, a snapshot of the state, and a
+/// write-ahead log of the operations performed since the snapshot was taken. Every
+/// operation is appended to the log, and the log is flushed to disk, *before* it is
+/// applied to the in-memory state. Reopening the store replays the log on top of the
+/// snapshot. A record that was only partially written when the process crashed is
+/// discarded, along with everything after it.
+///
+/// Operations that the ledger rejects are logged too. Since the ledger rejects them
+/// without changing the state, they have no effect when they are replayed either.
+pub struct LedgerStore<C: AccountMerkleConfig = MerkleConfig, S: TransactionSigScheme = SigScheme> {
+    directory: PathBuf,
+    parameters: Parameters<C, S>,
+    state: State<C>,
+    log: File,
+    /// The sequence number of the next log record. Sequence numbers keep increasing
+    /// across snapshots, so that records that are already part of the snapshot can
+    /// be skipped during recovery.
+    next_sequence_number: u64,
+}
+
+impl<C: AccountMerkleConfig, S: TransactionSigScheme> LedgerStore<C, S> {
+    /// Create a store in `directory` whose initial state is `state`. Any store that
+    /// already exists in `directory` is overwritten.
+    pub fn create(
+        directory: impl AsRef<Path>,
+        parameters: Parameters<C, S>,
+        state: State<C>,
+    ) -> Result<Self, StoreError> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+        write_atomically(&directory.join(PARAMETERS_FILE), |writer| {
+            parameters.serialize(writer)
+        })?;
+        write_snapshot(&directory, 0, &state)?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(directory.join(LOG_FILE))?;
+        log.set_len(0)?;
+        log.sync_all()?;
+        Ok(Self {
+            directory,
+            parameters,
+            state,
+            log,
+            next_sequence_number: 0,
+        })
+    }
+
+    /// Open the store in `directory`, recovering the state from the snapshot and
+    /// the log.
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let directory = directory.as_ref().to_path_buf();
+        let parameters = Parameters::deserialize(&fs::read(directory.join(PARAMETERS_FILE))?[..])?;
+        let (snapshot_sequence_number, mut state) =
+            <(u64, State<C>)>::deserialize(&fs::read(directory.join(SNAPSHOT_FILE))?[..])?;
+
+        let log_path = directory.join(LOG_FILE);
+        let log_bytes = fs::read(&log_path)?;
+        let mut next_sequence_number = snapshot_sequence_number;
+        let mut valid_len = 0;
+        while let Some((record_len, sequence_number, entry)) = read_record(&log_bytes[valid_len..])
+        {
+            if sequence_number >= snapshot_sequence_number {
+                if sequence_number != next_sequence_number {
+                    return Err(StoreError::CorruptLog);
+                }
+                // Rejected operations were rejected the first time around, too.
+                let _ = entry.apply(&parameters, &mut state);
+                next_sequence_number += 1;
+            }
+            valid_len += record_len;
+        }
+
+        // Drop the partially written record at the end of the log, if there is one.
+        let log = OpenOptions::new().append(true).open(&log_path)?;
+        if valid_len < log_bytes.len() {
+            log.set_len(valid_len as u64)?;
+            log.sync_all()?;
+        }
+        Ok(Self {
+            directory,
+            parameters,
+            state,
+            log,
+            next_sequence_number,
+        })
+    }
+
+    /// The ledger parameters.
+    pub fn parameters(&self) -> &Parameters<C, S> {
+        &self.parameters
+    }
+
+    /// The current ledger state.
+    pub fn state(&self) -> &State<C> {
+        &self.state
+    }
+
+    /// Persist `entry`, and then apply it to the state.
+    pub fn apply(&mut self, entry: LogEntry<S>) -> Result<(), StoreError> {
+        self.append(&entry)?;
+        entry.apply(&self.parameters, &mut self.state)?;
+        Ok(())
+    }
+
+    /// Persistent version of `State::register`.
+    pub fn register(&mut self, public_key: AccountPublicKey) -> Result<AccountId, StoreError> {
+        let id = self.state.next_available_account;
+        self.apply(LogEntry::Register(public_key))?;
+        Ok(id.expect("registration succeeded"))
+    }
+
+    /// Persistent version of `State::register_multisig`.
+    pub fn register_multisig(&mut self, policy: &MultisigPolicy) -> Result<AccountId, StoreError> {
+        let id = self.state.next_available_account;
+        self.apply(LogEntry::RegisterMultisig(policy.clone()))?;
+        Ok(id.expect("registration succeeded"))
+    }
+
+    /// Persistent version of `State::update_balance`.
+    pub fn update_balance(&mut self, id: AccountId, new_amount: Amount) -> Result<(), StoreError> {
+        self.apply(LogEntry::UpdateBalance(id, new_amount))
+    }
+
+    /// Persistent version of `State::set_fee_collector`.
+    pub fn set_fee_collector(&mut self, id: AccountId) -> Result<(), StoreError> {
+        self.apply(LogEntry::SetFeeCollector(id))
+    }
+
+    /// Persistent version of `State::apply_transaction`. Transactions that are
+    /// invalid are rejected before they are logged.
+    pub fn apply_transaction(&mut self, tx: &Transaction<S>) -> Result<(), StoreError> {
+        tx.check_validity(&self.parameters, &self.state)?;
+        self.apply(LogEntry::Transaction(Box::new(tx.clone())))
+    }
+
+    /// Persistent version of `State::apply_deposit`. As there, the caller must make sure
+    /// that `deposit` comes from the L1 deposit queue.
+    pub fn apply_deposit(&mut self, deposit: &Deposit) -> Result<(), StoreError> {
+        self.apply(LogEntry::Deposit(*deposit))
+    }
+
+    /// Replace the snapshot with the current state, and empty the log.
+    pub fn checkpoint(&mut self) -> Result<(), StoreError> {
+        write_snapshot(&self.directory, self.next_sequence_number, &self.state)?;
+        // If we crash before the log is emptied, the records in it are older than
+        // the new snapshot, and are skipped during recovery.
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        Ok(())
+    }
+
+    /// Append a record for `entry` to the log, and flush it to disk.
+    fn append(&mut self, entry: &LogEntry<S>) -> Result<(), StoreError> {
+        let mut payload = Vec::new();
+        self.next_sequence_number.serialize(&mut payload)?;
+        entry.serialize(&mut payload)?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&Blake2s::digest(&payload));
+        record.extend_from_slice(&payload);
+        self.log.write_all(&record)?;
+        self.log.sync_data()?;
+        self.next_sequence_number += 1;
+        Ok(())
+    }
+}
+
+/// Decode the log record at the start of `bytes`. Returns the length of the record,
+/// and its contents, or `None` if `bytes` does not start with an intact record.
+fn read_record<S: TransactionSigScheme>(bytes: &[u8]) -> Option<(usize, u64, LogEntry<S>)> {
+    let header = bytes.get(..RECORD_HEADER_SIZE)?;
+    let payload_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let payload = bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + payload_len)?;
+    if Blake2s::digest(payload)[..] != header[4..] {
+        return None;
+    }
+    let (sequence_number, entry) = <(u64, LogEntry<S>)>::deserialize(payload).ok()?;
+    Some((RECORD_HEADER_SIZE + payload_len, sequence_number, entry))
+}
+
+/// Write a snapshot consisting of `sequence_number` and `state`.
+fn write_snapshot<C: AccountMerkleConfig>(
+    directory: &Path,
+    sequence_number: u64,
+    state: &State<C>,
+) -> Result<(), StoreError> {
+    write_atomically(&directory.join(SNAPSHOT_FILE), |writer| {
+        sequence_number.serialize(&mut *writer)?;
+        state.serialize(writer)
+    })
+}
+
+/// Write the output of `serialize` to `path`, such that `path` holds either the old or
+/// the new contents if we crash.
+fn write_atomically(
+    path: &Path,
+    serialize: impl FnOnce(&mut BufWriter<File>) -> Result<(), SerializationError>,
+) -> Result<(), StoreError> {
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serialize(&mut writer)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LedgerStore, LogEntry, StoreError, LOG_FILE};
+    use crate::account::Nonce;
+    use crate::bridge::Deposit;
+    use crate::ledger::{Amount, LedgerError, MerkleConfig, Parameters, SigScheme, State};
+    use crate::signature::SignatureScheme;
+    use crate::transaction::Transaction;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "ark-simple-payments-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn recovers_after_crash() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let dir = temp_dir("recovery");
+        let mut store = LedgerStore::create(&dir, pp.clone(), State::new(32, &pp)).unwrap();
+        let (alice_pk, alice_sk) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let (bob_pk, _bob_sk) = SigScheme::keygen(&pp.sig_params, &mut rng).unwrap();
+        let alice_id = store.register(alice_pk).unwrap();
+        let bob_id = store.register(bob_pk).unwrap();
+        store.update_balance(alice_id, Amount(10)).unwrap();
+        let tx = Transaction::create(
+            &pp,
+            alice_id,
+            bob_id,
+            &bob_pk,
+            Amount(3),
+            Amount(0),
+            Nonce(0),
+            &alice_sk,
+            &mut rng,
+        );
+        store.apply_transaction(&tx).unwrap();
+        store
+            .apply_deposit(&Deposit {
+                recipient: bob_id,
+                amount: Amount(2),
+            })
+            .unwrap();
+        // Rejected operations do not affect recovery.
+        assert!(matches!(
+            store.apply_transaction(&tx),
+            Err(StoreError::Ledger(LedgerError::InvalidNonce { .. }))
+        ));
+        assert!(matches!(
+            store.register(alice_pk),
+            Err(StoreError::Ledger(LedgerError::DuplicatePublicKey))
+        ));
+        let root = store.state().root();
+        drop(store);
+
+        // Simulate a crash in the middle of appending a record.
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(log);
+
+        let mut store = LedgerStore::<MerkleConfig>::open(&dir).unwrap();
+        assert_eq!(store.state().root(), root);
+        assert_eq!(store.state().id_to_account_info[&bob_id].balance, Amount(5));
+        // The partial record is gone, so new records can be appended and recovered.
+        store
+            .apply(LogEntry::SetFeeCollector(bob_id))
+            .expect("should work");
+        drop(store);
+        let store = LedgerStore::<MerkleConfig>::open(&dir).unwrap();
+        assert_eq!(store.state().fee_collector, Some(bob_id));
+        assert_eq!(store.state().root(), root);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_skip_old_records() {
+        let mut rng = ark_std::test_rng();
+        let pp = Parameters::sample(&mut rng);
+        let dir = temp_dir("checkpoint");
+        let mut state = State::new(32, &pp);
+        let (alice_id, _alice_pk, _alice_sk) =
+            state.sample_keys_and_register(&pp, &mut rng).unwrap();
+        let mut store = LedgerStore::create(&dir, pp.clone(), state).unwrap();
+        store.update_balance(alice_id, Amount(5)).unwrap();
+        let old_log = fs::read(dir.join(LOG_FILE)).unwrap();
+        store.checkpoint().unwrap();
+        assert!(fs::read(dir.join(LOG_FILE)).unwrap().is_empty());
+        store.update_balance(alice_id, Amount(7)).unwrap();
+        let root = store.state().root();
+        drop(store);
+
+        // Simulate a crash after writing the snapshot, but before emptying the log.
+        let new_log = fs::read(dir.join(LOG_FILE)).unwrap();
+        fs::write(dir.join(LOG_FILE), [old_log, new_log].concat()).unwrap();
+
+        let store = LedgerStore::<MerkleConfig>::open(&dir).unwrap();
+        assert_eq!(store.state().root(), root);
+        assert_eq!(
+            store.state().id_to_account_info[&alice_id].balance,
+            Amount(7)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,105 +1,601 @@
-use crate::account::{AccountId, AccountPublicKey, AccountSecretKey};
-use crate::ledger::{self, Amount};
-use crate::signature::{
-    schnorr::{self, Schnorr},
-    SignatureScheme,
+use crate::account::{
+    public_key_to_bytes_le, AccountId, AccountInformation, AccountPublicKey, AccountSecretKey,
+    MultisigPolicy, Nonce, RegistryKey,
 };
+use crate::ledger::{
+    self, AccountMerkleConfig, Amount, LedgerError, SigScheme, Signature, TransactionSigScheme,
+};
+use crate::signature::schnorr;
 use ark_ed_on_bls12_381::EdwardsProjective;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize, Read, SerializationError, Write};
 use ark_std::rand::Rng;
+use derivative::Derivative;
+
+/// The kinds of transactions that the ledger supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// Moves `amount` from the sender to the recipient.
+    Transfer,
+    /// Credits `amount` to the recipient, out of a deposit on the L1. Deposits are not
+    /// signed, do not pay fees, and do not involve the sender. See `bridge::Deposit`.
+    Deposit,
+    /// Debits `amount` and `fee` from the sender, so that `amount` can be paid out on
+    /// the L1. Withdrawals do not involve the recipient. See `bridge::Exit`.
+    Withdrawal,
+    /// Creates the account `recipient` for the given public key, and inserts the key
+    /// into the registry after the key of `sender`. Registrations are not signed, and
+    /// move no money. See `Transaction::create_registration`.
+    Registration(AccountPublicKey),
+}
+
+impl TransactionKind {
+    /// Encode the kind as a byte.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Transfer => 0,
+            Self::Deposit => 1,
+            Self::Withdrawal => 2,
+            Self::Registration(_) => 3,
+        }
+    }
+}
 
-/// Transaction transferring some amount from one account to another.
-#[derive(Clone, Debug)]
-pub struct Transaction {
+/// Transaction transferring some amount from one account to another, or between an
+/// account and the L1, or registering a new account. Transfers and withdrawals are
+/// signed with `S`.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct Transaction<S: TransactionSigScheme = SigScheme> {
+    /// What the transaction does.
+    pub kind: TransactionKind,
     /// The account information of the sender.
     pub sender: AccountId,
     /// The account information of the recipient.
     pub recipient: AccountId,
     /// The amount being transferred from the sender to the receiver.
     pub amount: Amount,
+    /// The fee paid by the sender to the fee collector, on top of `amount`.
+    pub fee: Amount,
+    /// The sender's nonce at the time the transaction was created.
+    pub nonce: Nonce,
     /// The spend authorization is a signature over the sender, the recipient,
-    /// and the amount.
-    pub signature: schnorr::Signature<EdwardsProjective>,
+    /// the amount, the fee, the nonce, and the kind.
+    pub signature: Signature<S>,
+    /// Transfers and withdrawals from multisig accounts carry the approvals of the
+    /// account's signers instead, and leave `signature` at its default.
+    pub multisig: Option<MultisigApproval<S>>,
 }
 
-impl Transaction {
-    /// Verify just the signature in the transaction.
-    fn verify_signature(
+/// The approval of a transaction by the signers of a multisig account.
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""))]
+pub struct MultisigApproval<S: TransactionSigScheme = SigScheme> {
+    /// The policy of the sender's account.
+    pub policy: MultisigPolicy,
+    /// For each public key of `policy`, the signature of that signer over the same
+    /// message as `Transaction::signature`, or `None` if they did not approve.
+    pub signatures: Vec<Option<Signature<S>>>,
+}
+
+impl<S: TransactionSigScheme> CanonicalSerialize for MultisigApproval<S> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.policy.serialize(&mut writer)?;
+        self.signatures.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        self.policy.serialized_size() + self.signatures.serialized_size()
+    }
+}
+
+impl<S: TransactionSigScheme> CanonicalDeserialize for MultisigApproval<S> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let policy = MultisigPolicy::deserialize(&mut reader)?;
+        let signatures = Vec::deserialize(&mut reader)?;
+        if signatures.len() != policy.public_keys.len() {
+            return Err(SerializationError::InvalidData);
+        }
+        Ok(Self { policy, signatures })
+    }
+}
+
+/// The message that authorizes a transaction, which consists of
+/// (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount || Fee ||
+/// Nonce || Kind), where the public keys are those of the sender's and the recipient's
+/// accounts (see `account::public_key_to_bytes_le`). Withdrawals do not involve the
+/// recipient, so their RecipientPubKey is the sender's public key instead.
+/// `TransactionVar::authorized_message` in the rollup crate computes the same message in
+/// a circuit.
+#[allow(clippy::too_many_arguments)]
+fn authorized_message(
+    kind: TransactionKind,
+    sender: AccountId,
+    sender_public_key: &AccountPublicKey,
+    recipient: AccountId,
+    recipient_public_key: &AccountPublicKey,
+    amount: Amount,
+    fee: Amount,
+    nonce: Nonce,
+) -> Vec<u8> {
+    let recipient_public_key = match kind {
+        TransactionKind::Withdrawal => sender_public_key,
+        _ => recipient_public_key,
+    };
+    let mut message = sender.to_bytes_le();
+    message.extend(public_key_to_bytes_le(sender_public_key));
+    message.extend(recipient.to_bytes_le());
+    message.extend(public_key_to_bytes_le(recipient_public_key));
+    message.extend(amount.to_bytes_le());
+    message.extend(fee.to_bytes_le());
+    message.extend(nonce.to_bytes_le());
+    message.push(kind.to_byte());
+    message
+}
+
+impl<S: TransactionSigScheme> Transaction<S> {
+    /// The message that the sender signs, which binds the public keys that the sender
+    /// and the recipient have in `state` (see `authorized_message`). Only transfers and
+    /// withdrawals are signed, and only transactions whose accounts exist have a message.
+    pub fn signed_message<C: AccountMerkleConfig>(
         &self,
+        state: &ledger::State<C>,
+    ) -> Option<Vec<u8>> {
+        let sender_public_key = state.id_to_account_info.get(&self.sender)?.public_key;
+        let recipient_public_key = match self.kind {
+            TransactionKind::Transfer => state.id_to_account_info.get(&self.recipient)?.public_key,
+            TransactionKind::Withdrawal => sender_public_key,
+            TransactionKind::Deposit | TransactionKind::Registration(_) => return None,
+        };
+        Some(authorized_message(
+            self.kind,
+            self.sender,
+            &sender_public_key,
+            self.recipient,
+            &recipient_public_key,
+            self.amount,
+            self.fee,
+            self.nonce,
+        ))
+    }
+
+    /// Verify just one signature over the signed message `message` of the transaction.
+    /// The salt of `pp` is the domain tag of the ledger's chain, so signatures for other
+    /// chains do not verify.
+    fn verify_signature(
         pp: &schnorr::Parameters<EdwardsProjective>,
+        message: &[u8],
         pub_key: &AccountPublicKey,
+        signature: &Signature<S>,
     ) -> bool {
-        // The authorized message consists of
-        // (SenderAccId || SenderPubKey || RecipientAccId || RecipientPubKey || Amount)
-        let mut message = self.sender.to_bytes_le();
-        message.extend(self.recipient.to_bytes_le());
-        message.extend(self.amount.to_bytes_le());
-        Schnorr::verify(pp, pub_key, &message, &self.signature).unwrap()
-    }
-
-    /// Check that the transaction is valid for the given ledger state. This checks
-    /// the following conditions:
-    /// 1. Verify that the signature is valid with respect to the public key
-    /// corresponding to `self.sender`.
-    /// 2. Verify that the sender's account has sufficient balance to finance
-    /// the transaction.
-    /// 3. Verify that the recipient's account exists.
-    pub fn validate(&self, parameters: &ledger::Parameters, state: &ledger::State) -> bool {
+        S::verify(pp, pub_key, message, signature).unwrap()
+    }
+
+    /// The signatures that authorize a transfer or withdrawal from the account with
+    /// information `sender_acc_info`, along with the public keys they must verify
+    /// under: `signature` under the sender's public key, or the approvals under the
+    /// keys of the sender's multisig policy. This does not verify the signatures.
+    ///
+    /// Fails if the transaction is not approved like the sender's account requires, or
+    /// if it carries fewer approvals than the threshold of its policy.
+    pub(crate) fn authorizing_signatures<C: AccountMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<C, S>,
+        sender_acc_info: &AccountInformation,
+    ) -> Result<Vec<(AccountPublicKey, &Signature<S>)>, LedgerError> {
+        let approval = match &self.multisig {
+            None if !sender_acc_info.policy.is_multisig() => {
+                return Ok(vec![(sender_acc_info.public_key, &self.signature)]);
+            }
+            Some(approval)
+                if approval.policy.digest::<C>(&parameters.leaf_crh_params)
+                    == sender_acc_info.policy
+                    && approval.signatures.len() == approval.policy.public_keys.len() =>
+            {
+                approval
+            }
+            _ => return Err(LedgerError::InvalidPolicy),
+        };
+        let signatures = approval
+            .policy
+            .public_keys
+            .iter()
+            .zip(&approval.signatures)
+            .filter_map(|(public_key, signature)| Some((*public_key, signature.as_ref()?)))
+            .collect::<Vec<_>>();
+        if signatures.len() < usize::from(approval.policy.threshold) {
+            return Err(LedgerError::InsufficientApprovals);
+        }
+        Ok(signatures)
+    }
+
+    /// Check that the transaction is valid for the given ledger state. For transfers
+    /// and withdrawals, this checks the following conditions:
+    /// 1. Verify that the recipient's account exists and differs from the sender's (only
+    ///    for transfers), since the signed message binds its public key, and the rollup
+    ///    circuit cannot update the same account as both sender and recipient.
+    /// 2. Verify that the signature is valid with respect to the public key
+    ///    corresponding to `self.sender`. If the sender is a multisig account, verify
+    ///    instead that the transaction carries the account's policy, and valid approvals
+    ///    from at least as many of its signers as its threshold.
+    /// 3. Verify that the transaction's nonce equals the sender's current nonce.
+    /// 4. Verify that the sender's account has sufficient balance to finance
+    ///    the transaction amount and the fee.
+    /// 5. Verify that a fee collector exists if the transaction pays a fee.
+    ///
+    /// Deposits are always rejected, since anyone can create them. They are credited
+    /// via `State::apply_deposit` instead, once they have been locked on the L1.
+    ///
+    /// Registrations are checked to be the one returned by `create_registration`.
+    ///
+    /// Returns the first check that fails as an error.
+    pub fn check_validity<C: AccountMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<C, S>,
+        state: &ledger::State<C>,
+    ) -> Result<(), LedgerError> {
+        self.check_validity_with(parameters, state, true)
+    }
+
+    /// Like `check_validity`, but only verifies the signature if `verify_signature` is
+    /// true. `State::validate_batch` verifies the signatures of many transactions at once.
+    pub(crate) fn check_validity_with<C: AccountMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<C, S>,
+        state: &ledger::State<C>,
+        verify_signature: bool,
+    ) -> Result<(), LedgerError> {
+        if let TransactionKind::Registration(public_key) = self.kind {
+            let expected = Self::create_registration(state, public_key)?;
+            let matches = (
+                self.sender,
+                self.recipient,
+                self.amount,
+                self.fee,
+                self.nonce,
+            ) == (
+                expected.sender,
+                expected.recipient,
+                expected.amount,
+                expected.fee,
+                expected.nonce,
+            );
+            if !matches {
+                return Err(LedgerError::InvalidRegistration);
+            }
+            return Ok(());
+        }
+        if self.kind == TransactionKind::Deposit {
+            return Err(LedgerError::SelfIssuedDeposit);
+        }
+        if self.kind == TransactionKind::Transfer && self.sender == self.recipient {
+            return Err(LedgerError::SelfTransfer);
+        }
         // Lookup public key corresponding to sender ID
-        if let Some(sender_acc_info) = state.id_to_account_info.get(&self.sender) {
-            let mut result = true;
-            // Check that the account_info exists in the Merkle tree.
-            result &= {
-                let path = state
-                    .account_merkle_tree
-                    .generate_proof(self.sender.0 as usize)
-                    .expect("path should exist");
-                path.verify(
-                    &parameters.leaf_crh_params,
-                    &parameters.two_to_one_crh_params,
-                    &state.account_merkle_tree.root(),
-                    &sender_acc_info.to_bytes_le(),
-                )
-                .unwrap()
-            };
-            // Verify the signature against the sender pubkey.
-            result &= self.verify_signature(&parameters.sig_params, &sender_acc_info.public_key);
-            // assert!(result, "signature verification failed");
-            // Verify the amount is available in the sender account.
-            result &= self.amount <= sender_acc_info.balance;
-            // Verify that recipient account exists.
-            result &= state.id_to_account_info.get(&self.recipient).is_some();
-            result
-        } else {
-            false
-        }
-    }
-
-    /// Create a (possibly invalid) transaction.
-    pub fn create<R: Rng>(
-        parameters: &ledger::Parameters,
+        let sender_acc_info = state
+            .id_to_account_info
+            .get(&self.sender)
+            .ok_or(LedgerError::UnknownSender)?;
+        // Check that the account_info exists in the Merkle tree.
+        let path = state
+            .account_merkle_tree
+            .generate_proof(self.sender.leaf_index())
+            .expect("path should exist");
+        let in_tree = path
+            .verify(
+                &parameters.leaf_crh_params,
+                &parameters.two_to_one_crh_params,
+                &state.account_merkle_tree.root(),
+                &sender_acc_info.to_bytes_le(),
+            )
+            .unwrap();
+        if !in_tree {
+            return Err(LedgerError::InvalidMembershipProof);
+        }
+        // The sender exists, so the message is only missing if the recipient of a
+        // transfer does not exist.
+        let message = self
+            .signed_message(state)
+            .ok_or(LedgerError::UnknownRecipient)?;
+        // Verify the signature against the sender pubkey, or the approvals against the
+        // sender's multisig policy.
+        let signatures = self.authorizing_signatures(parameters, sender_acc_info)?;
+        if verify_signature
+            && !signatures.iter().all(|(public_key, signature)| {
+                Self::verify_signature(&parameters.sig_params, &message, public_key, signature)
+            })
+        {
+            return Err(LedgerError::InvalidSignature);
+        }
+        // Verify that the transaction has not been applied already.
+        if self.nonce != sender_acc_info.nonce {
+            return Err(LedgerError::InvalidNonce {
+                expected: sender_acc_info.nonce,
+                found: self.nonce,
+            });
+        }
+        // Verify the amount and the fee are available in the sender account.
+        let total = self
+            .amount
+            .checked_add(self.fee)
+            .ok_or(LedgerError::Overflow)?;
+        if total > sender_acc_info.balance {
+            return Err(LedgerError::InsufficientBalance);
+        }
+        // Verify that there is someone to pay the fee to.
+        if self.fee != Amount(0) && state.fee_collector.is_none() {
+            return Err(LedgerError::MissingFeeCollector);
+        }
+        Ok(())
+    }
+
+    /// Check that the transaction is valid for the given ledger state.
+    /// See `check_validity` for the conditions that are checked.
+    pub fn validate<C: AccountMerkleConfig>(
+        &self,
+        parameters: &ledger::Parameters<C, S>,
+        state: &ledger::State<C>,
+    ) -> bool {
+        self.check_validity(parameters, state).is_ok()
+    }
+
+    /// Create a (possibly invalid) transaction. `nonce` should be the sender's
+    /// current nonce, and `recipient_public_key` the public key of the account
+    /// `recipient`, for the transaction to be valid.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create<C: AccountMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<C, S>,
         sender: AccountId,
         recipient: AccountId,
+        recipient_public_key: &AccountPublicKey,
         amount: Amount,
+        fee: Amount,
+        nonce: Nonce,
         sender_sk: &AccountSecretKey,
         rng: &mut R,
     ) -> Self {
-        // The authorized message consists of (SenderAccId || RecipientAccId || Amount)
-        let mut message = sender.to_bytes_le();
-        message.extend(recipient.to_bytes_le());
-        message.extend(amount.to_bytes_le());
-        let signature = Schnorr::sign(&parameters.sig_params, sender_sk, &message, rng).unwrap();
+        Self::sign(
+            parameters,
+            TransactionKind::Transfer,
+            sender,
+            recipient,
+            recipient_public_key,
+            amount,
+            fee,
+            nonce,
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) withdrawal of `amount` from the account `sender`,
+    /// which pays `fee` to the fee collector.
+    pub fn create_withdrawal<C: AccountMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<C, S>,
+        sender: AccountId,
+        amount: Amount,
+        fee: Amount,
+        nonce: Nonce,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        Self::sign(
+            parameters,
+            TransactionKind::Withdrawal,
+            sender,
+            sender,
+            &sender_sk.public_key,
+            amount,
+            fee,
+            nonce,
+            sender_sk,
+            rng,
+        )
+    }
+
+    /// Create a (possibly invalid) transfer or withdrawal (depending on `kind`) from the
+    /// multisig account `sender`, whose policy is `policy`, to the account `recipient`
+    /// with public key `recipient_public_key`. It is approved by each of `signers` whose
+    /// public key belongs to `policy`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_multisig<C: AccountMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<C, S>,
+        kind: TransactionKind,
+        sender: AccountId,
+        recipient: AccountId,
+        recipient_public_key: &AccountPublicKey,
+        amount: Amount,
+        fee: Amount,
+        nonce: Nonce,
+        policy: &MultisigPolicy,
+        signers: &[&AccountSecretKey],
+        rng: &mut R,
+    ) -> Self {
+        let message = authorized_message(
+            kind,
+            sender,
+            &policy.account_key(),
+            recipient,
+            recipient_public_key,
+            amount,
+            fee,
+            nonce,
+        );
+        let signatures = policy
+            .public_keys
+            .iter()
+            .map(|public_key| {
+                let signer = signers.iter().find(|sk| sk.public_key == *public_key)?;
+                Some(S::sign(&parameters.sig_params, signer, &message, rng).unwrap())
+            })
+            .collect();
+        Self {
+            kind,
+            sender,
+            recipient,
+            amount,
+            fee,
+            nonce,
+            signature: Signature::<S>::default(),
+            multisig: Some(MultisigApproval {
+                policy: policy.clone(),
+                signatures,
+            }),
+        }
+    }
+
+    /// Create a deposit of `amount` to the account `recipient`. Deposits are not
+    /// signed; instead, they are only valid if they come from the L1 deposit queue.
+    pub fn create_deposit(recipient: AccountId, amount: Amount) -> Self {
+        Self {
+            kind: TransactionKind::Deposit,
+            sender: recipient,
+            recipient,
+            amount,
+            fee: Amount(0),
+            nonce: Nonce(0),
+            signature: Signature::<S>::default(),
+            multisig: None,
+        }
+    }
+
+    /// Create the registration of `public_key` as the next account of `state`. Its
+    /// recipient is the identifier of the new account, and its sender is the account
+    /// whose public key precedes `public_key` in the registry (see
+    /// `State::registry_predecessor`). Since both depend on `state`, a registration
+    /// only remains valid until the next one is applied.
+    ///
+    /// Fails if there is no space for a new account, if `public_key` belongs to an
+    /// existing account, or if `public_key` is not in the prime-order subgroup, since
+    /// `TransactionSigScheme::batch_verify` assumes that keys have no small-order
+    /// component.
+    pub fn create_registration<C: AccountMerkleConfig>(
+        state: &ledger::State<C>,
+        public_key: AccountPublicKey,
+    ) -> Result<Self, LedgerError> {
+        let recipient = state
+            .next_available_account
+            .filter(|id| id.leaf_index() < state.account_merkle_tree.num_leaves())
+            .ok_or(LedgerError::LedgerFull)?;
+        if !public_key.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(LedgerError::InvalidPublicKey);
+        }
+        if state.pub_key_to_id.contains_key(&public_key) {
+            return Err(LedgerError::DuplicatePublicKey);
+        }
+        // The key must fit strictly between its predecessor and its successor. This
+        // also rejects the (unlikely) keys that collide with registered ones, with the
+        // sentinel, or with `RegistryKey::max()`.
+        let key = RegistryKey::of(&public_key);
+        let sender = state.registry_predecessor(key);
+        let predecessor_info = state.leaf_info(sender).expect("should exist");
+        if RegistryKey::of(&predecessor_info.public_key) >= key || key >= predecessor_info.next_key
+        {
+            return Err(LedgerError::DuplicatePublicKey);
+        }
+        Ok(Self {
+            kind: TransactionKind::Registration(public_key),
+            sender,
+            recipient,
+            amount: Amount(0),
+            fee: Amount(0),
+            nonce: Nonce(0),
+            signature: Signature::<S>::default(),
+            multisig: None,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sign<C: AccountMerkleConfig, R: Rng>(
+        parameters: &ledger::Parameters<C, S>,
+        kind: TransactionKind,
+        sender: AccountId,
+        recipient: AccountId,
+        recipient_public_key: &AccountPublicKey,
+        amount: Amount,
+        fee: Amount,
+        nonce: Nonce,
+        sender_sk: &AccountSecretKey,
+        rng: &mut R,
+    ) -> Self {
+        let message = authorized_message(
+            kind,
+            sender,
+            &sender_sk.public_key,
+            recipient,
+            recipient_public_key,
+            amount,
+            fee,
+            nonce,
+        );
+        let signature = S::sign(&parameters.sig_params, sender_sk, &message, rng).unwrap();
         Self {
+            kind,
             sender,
             recipient,
             amount,
+            fee,
+            nonce,
             signature,
+            multisig: None,
         }
     }
 }
 
+impl<S: TransactionSigScheme> CanonicalSerialize for Transaction<S> {
+    fn serialize<W: Write>(&self, mut writer: W) -> Result<(), SerializationError> {
+        self.kind.to_byte().serialize(&mut writer)?;
+        if let TransactionKind::Registration(public_key) = self.kind {
+            public_key.serialize(&mut writer)?;
+        }
+        self.sender.serialize(&mut writer)?;
+        self.recipient.serialize(&mut writer)?;
+        self.amount.serialize(&mut writer)?;
+        self.fee.serialize(&mut writer)?;
+        self.nonce.serialize(&mut writer)?;
+        self.signature.serialize(&mut writer)?;
+        self.multisig.serialize(&mut writer)
+    }
+
+    fn serialized_size(&self) -> usize {
+        let public_key_size = match self.kind {
+            TransactionKind::Registration(public_key) => public_key.serialized_size(),
+            _ => 0,
+        };
+        1 + public_key_size
+            + self.sender.serialized_size()
+            + self.recipient.serialized_size()
+            + self.amount.serialized_size()
+            + self.fee.serialized_size()
+            + self.nonce.serialized_size()
+            + self.signature.serialized_size()
+            + self.multisig.serialized_size()
+    }
+}
+
+impl<S: TransactionSigScheme> CanonicalDeserialize for Transaction<S> {
+    fn deserialize<R: Read>(mut reader: R) -> Result<Self, SerializationError> {
+        let kind = match u8::deserialize(&mut reader)? {
+            0 => TransactionKind::Transfer,
+            1 => TransactionKind::Deposit,
+            2 => TransactionKind::Withdrawal,
+            3 => TransactionKind::Registration(AccountPublicKey::deserialize(&mut reader)?),
+            _ => return Err(SerializationError::InvalidData),
+        };
+        Ok(Self {
+            kind,
+            sender: AccountId::deserialize(&mut reader)?,
+            recipient: AccountId::deserialize(&mut reader)?,
+            amount: Amount::deserialize(&mut reader)?,
+            fee: Amount::deserialize(&mut reader)?,
+            nonce: Nonce::deserialize(&mut reader)?,
+            signature: Signature::<S>::deserialize(&mut reader)?,
+            multisig: Option::<MultisigApproval<S>>::deserialize(&mut reader)?,
+        })
+    }
+}
+
 // Ideas to make exercises more interesting/complex:
-// 1. Add fees
-// 2. Add recipient confirmation requirement if tx amount is too large.
-// 3. Add authority confirmation if tx amount is too large.
-// 4. Create account if it doesn't exist.
-// 5. Add idea for compressing state transitions with repeated senders and recipients.
+// 1. Add recipient confirmation requirement if tx amount is too large.
+// 2. Add authority confirmation if tx amount is too large.
+// 3. Create account if it doesn't exist.
+// 4. Add idea for compressing state transitions with repeated senders and recipients.